@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use cpal::{Device, FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+// Keeps the output this far behind the emulation at most, in seconds. The emulator is paced by the
+// display rather than the sound card, so the queue either runs dry or piles up over time
+const MAX_LATENCY: f32 = 0.1;
+
+// Pole of the high-pass filter that takes out the DC offset of the APU output, about 37Hz at 44.1kHz
+const HIGH_PASS: f32 = 0.995;
+
+// Plays the samples generated by the APU on the default output device
+pub struct Audio
+{
+	queue: Arc<Mutex<VecDeque<f32>>>,
+	max_queued: usize,
+
+	previous_in: f32,
+	previous_out: f32,
+
+	// Devices that can't run at the APU's rate get the samples resampled to theirs,
+	// `position` is how far the output is between the last two input samples
+	step: f32,
+	position: f32,
+	last: f32,
+
+	// Playback stops as soon as the stream is dropped
+	_stream: Stream
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(device: &Device, config: &StreamConfig, queue: Arc<Mutex<VecDeque<f32>>>) -> Result<Stream, cpal::BuildStreamError>
+{
+	let channels = config.channels as usize;
+
+	// Silence fills in whenever the emulator falls behind
+	device.build_output_stream(
+		config,
+		move |data: &mut [T], _| {
+			let mut queue = queue.lock().unwrap();
+			for frame in data.chunks_mut(channels)
+			{
+				frame.fill(T::from_sample(queue.pop_front().unwrap_or(0.0)));
+			}
+		},
+		|err| eprintln!("Audio output failed: {}", err),
+		None
+	)
+}
+
+impl Audio
+{
+	pub fn new(sample_rate: u32) -> Result<Audio, Box<dyn Error>>
+	{
+		let device = cpal::default_host().default_output_device().ok_or("No audio output device")?;
+
+		// Plays at the APU's rate if the device can, otherwise at whatever the device prefers
+		let supported = device.supported_output_configs()?
+			.filter(|config| config.sample_format() == SampleFormat::F32)
+			.find(|config| (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&sample_rate))
+			.map(|config| config.with_sample_rate(cpal::SampleRate(sample_rate)));
+
+		let supported = match supported
+		{
+			Some(config) 	=> config,
+			None 			=> device.default_output_config()?
+		};
+
+		let format = supported.sample_format();
+		let output_rate = supported.sample_rate().0;
+		let config = supported.config();
+
+		let queue = Arc::new(Mutex::new(VecDeque::new()));
+		let stream = match format
+		{
+			SampleFormat::F32 	=> build_stream::<f32>(&device, &config, Arc::clone(&queue))?,
+			SampleFormat::I16 	=> build_stream::<i16>(&device, &config, Arc::clone(&queue))?,
+			SampleFormat::U16 	=> build_stream::<u16>(&device, &config, Arc::clone(&queue))?,
+			format 				=> return Err(format!("Unsupported sample format {:?}", format).into())
+		};
+
+		stream.play()?;
+
+		Ok(Audio
+		{
+			queue,
+			max_queued: (output_rate as f32 * MAX_LATENCY) as usize,
+
+			previous_in: 0.0,
+			previous_out: 0.0,
+
+			step: sample_rate as f32 / output_rate as f32,
+			position: 0.0,
+			last: 0.0,
+
+			_stream: stream
+		})
+	}
+
+	pub fn push(&mut self, samples: &[f32])
+	{
+		let mut queue = self.queue.lock().unwrap();
+
+		for &sample in samples
+		{
+			let out = sample - self.previous_in + HIGH_PASS * self.previous_out;
+			self.previous_in = sample;
+			self.previous_out = out;
+
+			let out = out.clamp(-1.0, 1.0);
+			while self.position < 1.0
+			{
+				queue.push_back(self.last + (out - self.last) * self.position);
+				self.position += self.step;
+			}
+
+			self.position -= 1.0;
+			self.last = out;
+		}
+
+		let excess = queue.len().saturating_sub(self.max_queued);
+		queue.drain(..excess);
+	}
+}
@@ -1,15 +1,137 @@
 mod nes;
 mod renderer;
+mod audio;
 
 use std::ffi::{CStr};
+use std::fs;
 use glfw::{Context};
 
-use nes::nes::NES;
+use nes::nes::{NES, State, SAMPLE_RATE};
+use nes::cartridge::{Cartridge, LoadOptions, RomError};
 use renderer::context;
+use audio::Audio;
 
 fn main() {
-    let nes = NES::new();
-    nes.powerup();
+    // Any arguments after the ROM are patches to apply to it, disk images also need --bios <disksys.rom>.
+    // --nrom-fallback runs ROMs with an unsupported mapper as NROM, --entry <hex address> skips the reset vector,
+    // --magic <hex value> sets the constant used by the unstable XAA and LAX #imm opcodes, --trace logs every instruction.
+    // --db <nes20db.xml> loads a game database to correct bad headers, nes20db.xml in the working directory is used otherwise
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let nrom_fallback = match args.iter().position(|arg| arg == "--nrom-fallback")
+    {
+        Some(i) => { args.remove(i); true },
+        None => false
+    };
+
+    let mut trace = match args.iter().position(|arg| arg == "--trace")
+    {
+        Some(i) => { args.remove(i); true },
+        None => false
+    };
+
+    let bios = match args.iter().position(|arg| arg == "--bios")
+    {
+        Some(i) if i + 1 < args.len() => Some(args.drain(i..i + 2).nth(1).unwrap()),
+        _ => None
+    };
+
+    let database = match args.iter().position(|arg| arg == "--db")
+    {
+        Some(i) if i + 1 < args.len() => Some(args.drain(i..i + 2).nth(1).unwrap()),
+        _ => None
+    };
+
+    let mut entry = match args.iter().position(|arg| arg == "--entry")
+    {
+        Some(i) if i + 1 < args.len() => args.drain(i..i + 2).nth(1).and_then(|addr| u16::from_str_radix(addr.trim_start_matches('$'), 16).ok()),
+        _ => None
+    };
+
+    let magic = match args.iter().position(|arg| arg == "--magic")
+    {
+        Some(i) if i + 1 < args.len() => args.drain(i..i + 2).nth(1).and_then(|val| u8::from_str_radix(val.trim_start_matches('$'), 16).ok()),
+        _ => None
+    };
+
+    // Without a ROM, nestest runs in its automation mode and logs a trace to compare with nestest.log
+    if args.is_empty()
+    {
+        args.push(String::from("roms/nestest.nes"));
+        entry = entry.or(Some(0xC000));
+        trace = true;
+    }
+
+    // Without a database only the images known to the emulator itself get their headers corrected
+    match &database
+    {
+        Some(database) => {
+            if let Err(err) = nes::database::load(database)
+            {
+                eprintln!("Failed to load {}: {}", database, err);
+            }
+        },
+
+        None => {
+            let _ = nes::database::load("nes20db.xml");
+        }
+    }
+
+    let filepath = args[0].clone();
+
+    // A patch or BIOS that can't be read fails the same way as the ROM itself
+    let options = || -> Result<LoadOptions, RomError> {
+        Ok(LoadOptions
+        {
+            patches: args[1..].iter().map(fs::read).collect::<Result<_, _>>()?,
+            bios: bios.as_ref().map(fs::read).transpose()?,
+            nrom_fallback
+        })
+    };
+
+    let cartridge = options().and_then(|options| Cartridge::load_file(&filepath, &options));
+
+    let cartridge = match cartridge
+    {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", filepath, err);
+            return;
+        }
+    };
+
+    if let Some(title) = cartridge.title()
+    {
+        println!("Loaded {}", title);
+    }
+
+    let nes = NES::new(cartridge);
+    if let Some(magic) = magic
+    {
+        nes.set_magic_constant(magic);
+    }
+
+    nes.set_trace(trace);
+
+    match entry
+    {
+        Some(entry) => nes.powerup_at(entry),
+        None => nes.powerup()
+    }
+
+    if let Some(nsf) = nes.nsf()
+    {
+        println!("{} - {} ({} songs, playing {})", nsf.artist, nsf.title, nsf.songs, nes.current_track() + 1);
+    }
+
+    // Sound is optional, without an output device the emulator runs silently
+    let mut audio = match Audio::new(SAMPLE_RATE)
+    {
+        Ok(audio) => Some(audio),
+        Err(err) => {
+            eprintln!("Failed to open audio output: {}", err);
+            None
+        }
+    };
 
     let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
 
@@ -33,6 +155,7 @@ fn main() {
         return;
     }
 
+    let mut state = State::Running;
     while !window.should_close()
     {
         glfw.poll_events();
@@ -40,11 +163,35 @@ fn main() {
         {
             match event 
             {
+                // Tab flips the disk over on the Famicom Disk System
+                glfw::WindowEvent::Key(glfw::Key::Tab, _, glfw::Action::Press, _) => nes.switch_disk_side(),
+
+                // Left and right pick the song when playing an NSF file
+                glfw::WindowEvent::Key(glfw::Key::Right, _, glfw::Action::Press, _) => nes.next_track(),
+                glfw::WindowEvent::Key(glfw::Key::Left, _, glfw::Action::Press, _) => nes.previous_track(),
+
+                glfw::WindowEvent::Key(glfw::Key::R, _, glfw::Action::Press, _) => nes.reset(),
                 _ => {}
             }
         }
 
         nes.single_frame();
+
+        let samples = nes.audio_samples();
+        if let Some(audio) = &mut audio
+        {
+            audio.push(&samples);
+        }
+
+        // The game crashed the CPU, it stays stuck until it's reset
+        if nes.state() != state
+        {
+            state = nes.state();
+            if let State::Jammed { pc, opcode } = state
+            {
+                eprintln!("CPU jammed on opcode ${:02X} at ${:04X}, press R to reset", opcode, pc);
+            }
+        }
         unsafe { context::clear(); }
 
         window.swap_buffers();
@@ -1,193 +1,360 @@
 use crate::nes::cpu::{CPU, FetchType};
+use crate::nes::bus::Bus;
+use crate::nes::instructions::Access;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Addressing
+{
+	Acc,
+	Abs,
+	Abx,
+	Aby,
+	Imm,
+	Imp,
+	Ind,
+	Idx,
+	Idy,
+	Rel,
+	Zpg,
+	Zpx,
+	Zpy
+}
 
 #[macro_export]
-macro_rules! instr_size
+macro_rules! addressing
 {
-	(acc) => { 1 };
-	(abs) => { 3 };
-	(abx) => { 3 };
-	(aby) => { 3 };
-	(imm) => { 2 };
-	(imp) => { 1 };
-	(ind) => { 3 };
-	(idx) => { 2 };
-	(idy) => { 2 };
-	(rel) => { 2 };
-	(zpg) => { 2 };
-	(zpx) => { 2 };
-	(zpy) => { 2 };
+	(acc) => { Addressing::Acc };
+	(abs) => { Addressing::Abs };
+	(abx) => { Addressing::Abx };
+	(aby) => { Addressing::Aby };
+	(imm) => { Addressing::Imm };
+	(imp) => { Addressing::Imp };
+	(ind) => { Addressing::Ind };
+	(idx) => { Addressing::Idx };
+	(idy) => { Addressing::Idy };
+	(rel) => { Addressing::Rel };
+	(zpg) => { Addressing::Zpg };
+	(zpx) => { Addressing::Zpx };
+	(zpy) => { Addressing::Zpy };
 }
 
-macro_rules! abs_indexed_addr
+impl Addressing
 {
-	($name: ident, $register: ident) => 
+	pub const fn size(self) -> u8
 	{
-		pub fn $name(&mut self) 
+		match self
 		{
-			let bus = self.bus.upgrade().unwrap();
-
-			let lo = bus.borrow().read_cpu(self.pc) as u16;
-			let hi = bus.borrow().read_cpu(self.pc + 1) as u16;
-			self.pc += instr_size!($name) - 1;
-
-			let fetched_addr = (hi << 8) | lo;
-			self.absolute_addr = fetched_addr.wrapping_add(self.$register as u16);
+			Addressing::Acc | Addressing::Imp 											=> 1,
+			Addressing::Abs | Addressing::Abx | Addressing::Aby | Addressing::Ind 		=> 3,
+			_ 																			=> 2
+		}
+	}
 
-			self.additional_cycles = ((fetched_addr & 0xFF00) != (self.absolute_addr & 0xFF00)) as u8;
+	// Operand in the trace, `addr` points at the first byte after the opcode
+	pub fn format(self, bus: &Bus, addr: u16, x: u8, y: u8) -> String
+	{
+		let byte = bus.peek_cpu(addr);
+		let word = ((bus.peek_cpu(addr.wrapping_add(1)) as u16) << 8) | byte as u16;
+		let zpg_word = |ptr: u8| ((bus.peek_cpu(ptr.wrapping_add(1) as u16) as u16) << 8) | bus.peek_cpu(ptr as u16) as u16;
 
-			self.fetch_type = FetchType::Mem;
+		match self
+		{
+			Addressing::Acc => String::from("A"),
+			Addressing::Imp => String::new(),
+			Addressing::Imm => format!("#${:02X}", byte),
+			Addressing::Rel => format!("${:02X}", byte),
+			Addressing::Zpg => format!("${:02X} = {:02X}", byte, bus.peek_cpu(byte as u16)),
+			Addressing::Zpx => format!("${:02X},X @ ${:04X}", byte, byte.wrapping_add(x)),
+			Addressing::Zpy => format!("${:02X},Y @ ${:04X}", byte, byte.wrapping_add(y)),
+			Addressing::Abs => format!("${:04X}", word),
+			Addressing::Abx => format!("${:04X},X @ ${:04X}", word, word.wrapping_add(x as u16)),
+			Addressing::Aby => format!("${:04X},Y @ ${:04X}", word, word.wrapping_add(y as u16)),
+
+			Addressing::Ind => {
+				let target = ((bus.peek_cpu((word & 0xFF00) | (word.wrapping_add(1) & 0x00FF)) as u16) << 8) | bus.peek_cpu(word) as u16;
+				format!("(${:04X}) = ${:04X}", word, target)
+			},
+
+			Addressing::Idx => {
+				let ptr = byte.wrapping_add(x);
+				let target = zpg_word(ptr);
+				format!("(${:02X},X) @ [${:02X}] = ${:04X} = {:02X}", byte, ptr, target, bus.peek_cpu(target))
+			},
+
+			Addressing::Idy => {
+				let base = zpg_word(byte);
+				let target = base.wrapping_add(y as u16);
+				format!("(${:02X}),Y @ [${:04X} + Y] = ${:04X} = {:02X}", byte, base, target, bus.peek_cpu(target))
+			}
+		}
+	}
+}
 
-			print!("{: <40}", format!("${:04X},{} @ ${:04X}", fetched_addr, stringify!($register).to_uppercase(), self.absolute_addr));
+// Indexed addressing adds the index to the low byte first, the high byte is only fixed a cycle later.
+// Reads go ahead with the unfixed address and only repeat it if a page was crossed, writes and
+// read-modify-write instructions always read it first
+macro_rules! abs_indexed_addr
+{
+	($name: ident, $register: ident) =>
+	{
+		pub fn $name(&mut self)
+		{
+			match self.step()
+			{
+				1 => {
+					self.base_addr = self.read(self.pc) as u16;
+					self.pc = self.pc.wrapping_add(1);
+				},
+
+				2 => {
+					self.base_addr |= (self.read(self.pc) as u16) << 8;
+					self.pc = self.pc.wrapping_add(1);
+
+					self.absolute_addr = self.base_addr.wrapping_add(self.$register as u16);
+				},
+
+				3 => self.indexed_read(),
+				step => self.operate(step - 4)
+			}
 		}
 	}
 }
 
 macro_rules! zpg_indexed_addr
 {
-	($name: ident, $register: ident) => 
+	($name: ident, $register: ident) =>
 	{
-		pub fn $name(&mut self) 
+		pub fn $name(&mut self)
 		{
-			let bus = self.bus.upgrade().unwrap();
-
-			let fetched_addr = bus.borrow().read_cpu(self.pc);
-			self.absolute_addr = fetched_addr.wrapping_add(self.$register) as u16;
-			self.pc += instr_size!($name) - 1;
-
-			self.fetch_type = FetchType::Mem;
-
-			print!("{: <40}", format!("${:02X},{} @ ${:04X}", fetched_addr, stringify!($register).to_uppercase(), self.absolute_addr));
+			match self.step()
+			{
+				1 => {
+					self.absolute_addr = self.read(self.pc) as u16;
+					self.pc = self.pc.wrapping_add(1);
+				},
+
+				// The unindexed address is read while the index is added, which never leaves the zero page
+				2 => {
+					self.read(self.absolute_addr);
+					self.absolute_addr = (self.absolute_addr as u8).wrapping_add(self.$register) as u16;
+				},
+
+				step => self.operate(step - 3)
+			}
 		}
 	}
 }
 
-pub type AddrFn = fn(&mut CPU);
-
 impl CPU
 {
-	pub fn abs(&mut self)
+	// Accumulator and implied instructions read the next byte and throw it away
+	pub fn imp(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		self.read(self.pc);
 
-		let lo = bus.borrow().read_cpu(self.pc) as u16;
-		let hi = bus.borrow().read_cpu(self.pc + 1) as u16;
+		self.fetch_type = FetchType::Acc;
+		(self.instr.action)(self);
+		self.finish();
+	}
 
-		self.pc += instr_size!(abs) - 1;
-		self.absolute_addr = (hi << 8) | lo;
+	pub fn imm(&mut self)
+	{
+		self.data = self.read(self.pc);
+		self.pc = self.pc.wrapping_add(1);
 
 		self.fetch_type = FetchType::Mem;
-
-		print!("{: <40}", format!("${:04X}", self.absolute_addr));
+		(self.instr.action)(self);
+		self.finish();
 	}
 
-	abs_indexed_addr!(abx, x);
-	abs_indexed_addr!(aby, y);
-
-	pub fn acc(&mut self)
+	pub fn zpg(&mut self)
 	{
-		self.fetch_type = FetchType::Acc;
+		match self.step()
+		{
+			1 => {
+				self.absolute_addr = self.read(self.pc) as u16;
+				self.pc = self.pc.wrapping_add(1);
+			},
 
-		print!("{: <40}", "A");
+			step => self.operate(step - 2)
+		}
 	}
 
-	pub fn idx(&mut self)
-	{
-		let bus = self.bus.upgrade().unwrap();
-		
-		let mut zpg_addr = bus.borrow().read_cpu(self.pc);
-		self.pc += instr_size!(idx) - 1;
+	zpg_indexed_addr!(zpx, x);
+	zpg_indexed_addr!(zpy, y);
 
-		zpg_addr = zpg_addr.wrapping_add(self.x);
-		let lo = bus.borrow().read_cpu(zpg_addr as u16) as u16;
-		let hi = bus.borrow().read_cpu(zpg_addr.wrapping_add(1) as u16) as u16;
+	pub fn abs(&mut self)
+	{
+		match self.step()
+		{
+			1 => {
+				self.absolute_addr = self.read(self.pc) as u16;
+				self.pc = self.pc.wrapping_add(1);
+			},
 
-		self.absolute_addr = (hi << 8) | lo;
-		self.fetch_type = FetchType::Mem;
+			2 => {
+				self.absolute_addr |= (self.read(self.pc) as u16) << 8;
+				self.pc = self.pc.wrapping_add(1);
+			},
 
-		print!("{: <40}", format!("(${:02X},X) @ [${:02X}] = ${:04X} = {:02X}", zpg_addr.wrapping_sub(self.x), zpg_addr, self.absolute_addr, bus.borrow().read_cpu(self.absolute_addr)));
+			step => self.operate(step - 3)
+		}
 	}
 
-	pub fn idy(&mut self)
-	{
-		let bus = self.bus.upgrade().unwrap();
-		
-		let zpg_addr = bus.borrow().read_cpu(self.pc);
-		self.pc += instr_size!(idy) - 1;
+	abs_indexed_addr!(abx, x);
+	abs_indexed_addr!(aby, y);
 
-		let lo = bus.borrow().read_cpu(zpg_addr as u16) as u16;
-		let hi = bus.borrow().read_cpu(zpg_addr.wrapping_add(1) as u16) as u16;
+	// Only JMP uses indirect addressing, the pointer's high byte is read without a carry into its page
+	pub fn ind(&mut self)
+	{
+		match self.step()
+		{
+			1 => {
+				self.base_addr = self.read(self.pc) as u16;
+				self.pc = self.pc.wrapping_add(1);
+			},
 
-		let target_addr = (hi << 8) | lo;
-		self.absolute_addr = target_addr.wrapping_add(self.y as u16);
+			2 => {
+				self.base_addr |= (self.read(self.pc) as u16) << 8;
+				self.pc = self.pc.wrapping_add(1);
+			},
 
-		self.additional_cycles = ((target_addr & 0xFF00) != (self.absolute_addr & 0xFF00)) as u8;
+			3 => self.absolute_addr = self.read(self.base_addr) as u16,
 
-		self.fetch_type = FetchType::Mem;
+			_ => {
+				let hi = self.read((self.base_addr & 0xFF00) | (self.base_addr.wrapping_add(1) & 0x00FF)) as u16;
+				self.absolute_addr |= hi << 8;
 
-		print!("{: <40}", format!("(${:02X}),Y @ [${:04X} + Y] = ${:04X} = {:02X}", zpg_addr, target_addr, self.absolute_addr, bus.borrow().read_cpu(self.absolute_addr)));
+				self.pc = self.absolute_addr;
+				self.finish();
+			}
+		}
 	}
 
-	pub fn imm(&mut self)
+	pub fn idx(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
-
-		self.absolute_addr = self.pc;
-		self.pc += instr_size!(imm) - 1;
+		match self.step()
+		{
+			1 => {
+				self.pointer = self.read(self.pc);
+				self.pc = self.pc.wrapping_add(1);
+			},
 
-		self.fetch_type = FetchType::Mem;
+			2 => {
+				self.read(self.pointer as u16);
+				self.pointer = self.pointer.wrapping_add(self.x);
+			},
 
-		print!("{: <40}", format!("#${:02X}", bus.borrow().read_cpu(self.absolute_addr)));
-	}
+			3 => self.absolute_addr = self.read(self.pointer as u16) as u16,
+			4 => self.absolute_addr |= (self.read(self.pointer.wrapping_add(1) as u16) as u16) << 8,
 
-	pub fn imp(&mut self)
-	{
-		print!("{: <40}", "");
+			step => self.operate(step - 5)
+		}
 	}
 
-	pub fn ind(&mut self)
+	pub fn idy(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
-
-		let mut lo = bus.borrow().read_cpu(self.pc) as u16;
-		let mut hi = bus.borrow().read_cpu(self.pc + 1) as u16;
-		self.pc += instr_size!(ind) - 1;
-
-		let indirect_addr = (hi << 8) | lo;
-		lo = bus.borrow().read_cpu(indirect_addr) as u16;
-		hi = bus.borrow().read_cpu((indirect_addr & 0xFF00) | ((indirect_addr + 1) & 0x00FF)) as u16;
+		match self.step()
+		{
+			1 => {
+				self.pointer = self.read(self.pc);
+				self.pc = self.pc.wrapping_add(1);
+			},
 
-		self.absolute_addr = (hi << 8) | lo;
+			2 => self.base_addr = self.read(self.pointer as u16) as u16,
 
-		self.fetch_type = FetchType::Mem;
+			3 => {
+				self.base_addr |= (self.read(self.pointer.wrapping_add(1) as u16) as u16) << 8;
+				self.absolute_addr = self.base_addr.wrapping_add(self.y as u16);
+			},
 
-		print!("{: <40}", format!("(${:04X}) = ${:04X}", indirect_addr, self.absolute_addr));
+			4 => self.indexed_read(),
+			step => self.operate(step - 5)
+		}
 	}
 
+	// The branch is decided once the offset is in, a taken branch reads the next opcode while it adds
+	// the offset to the low byte of PC and once more if it has to fix the high byte
 	pub fn rel(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
-		
-		self.relative_addr = bus.borrow().read_cpu(self.pc) as i8;
-		self.pc += instr_size!(rel) - 1;
-
-		self.fetch_type = FetchType::Mem;
-
-		print!("{: <40}", format!("${:02X}", self.relative_addr));
+		match self.step()
+		{
+			1 => {
+				self.relative_addr = self.read(self.pc) as i8;
+				self.pc = self.pc.wrapping_add(1);
+
+				(self.instr.action)(self);
+				if !self.branch_taken
+				{
+					self.finish();
+				}
+			},
+
+			2 => {
+				self.read(self.pc);
+
+				self.absolute_addr = self.pc.wrapping_add(self.relative_addr as u16);
+				self.pc = (self.pc & 0xFF00) | (self.absolute_addr & 0x00FF);
+
+				if self.pc == self.absolute_addr
+				{
+					self.hold_poll = true;
+					self.finish();
+				}
+			},
+
+			_ => {
+				self.read(self.pc);
+				self.pc = self.absolute_addr;
+				self.finish();
+			}
+		}
 	}
 
-	pub fn zpg(&mut self)
+	// Reads the address before its high byte is fixed. That's the real operand of a read that didn't cross a page
+	fn indexed_read(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
-
-		self.absolute_addr = bus.borrow().read_cpu(self.pc) as u16;
-		self.pc += instr_size!(zpg) - 1;
+		let partial = (self.base_addr & 0xFF00) | (self.absolute_addr & 0x00FF);
+		match partial == self.absolute_addr && self.instr.reads_only()
+		{
+			true 	=> self.operate(0),
+			false 	=> {
+				self.read(partial);
+			}
+		}
+	}
 
+	// The cycles after the address is known. Read-modify-write instructions write the unmodified
+	// value back while they work on it, so registers that react to writes see both
+	fn operate(&mut self, cycle: u8)
+	{
 		self.fetch_type = FetchType::Mem;
 
-		print!("{: <40}", format!("${:02X} = {:02X}", self.absolute_addr, bus.borrow().read_cpu(self.absolute_addr)))
+		match (self.instr.access, cycle)
+		{
+			(Access::Write, _) => {
+				(self.instr.action)(self);
+				self.write(self.absolute_addr, self.data);
+				self.finish();
+			},
+
+			(Access::Modify, 0) => self.data = self.read(self.absolute_addr),
+
+			(Access::Modify, 1) => {
+				self.write(self.absolute_addr, self.data);
+				(self.instr.action)(self);
+			},
+
+			(Access::Modify, _) => {
+				self.write(self.absolute_addr, self.data);
+				self.finish();
+			},
+
+			_ => {
+				self.data = self.read(self.absolute_addr);
+				(self.instr.action)(self);
+				self.finish();
+			}
+		}
 	}
-
-	zpg_indexed_addr!(zpx, x);
-	zpg_indexed_addr!(zpy, y);
-
-}
\ No newline at end of file
+}
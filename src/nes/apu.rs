@@ -0,0 +1,829 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use crate::nes::bus::Bus;
+
+static LENGTH_TABLE: [u8; 32] = [
+	10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+	12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+static DUTY_TABLE: [[u8; 8]; 4] = [
+	[0, 1, 0, 0, 0, 0, 0, 0],
+	[0, 1, 1, 0, 0, 0, 0, 0],
+	[0, 1, 1, 1, 1, 0, 0, 0],
+	[1, 0, 0, 1, 1, 1, 1, 1]
+];
+
+static TRIANGLE_TABLE: [u8; 32] = [
+	15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+	0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+];
+
+// Noise and DMC periods are given in CPU cycles (NTSC)
+static NOISE_TABLE: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+static DMC_TABLE: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+// Frame counter steps in CPU cycles, the last one ends the sequence
+static FOUR_STEP: [u32; 4] = [7457, 14913, 22371, 29829];
+static FIVE_STEP: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+
+const CPU_FREQUENCY: f64 = 1789773.0;
+pub const SAMPLE_RATE: u32 = 44100;
+
+// Samples are dropped if nobody collects them, instead of piling up forever
+const MAX_SAMPLES: usize = SAMPLE_RATE as usize;
+
+// Full-scale expansion audio relative to the APU's output, which puts a VRC6 pulse
+// about as loud as an APU pulse at the same volume
+const EXPANSION_LEVEL: f32 = 0.6;
+
+struct Envelope
+{
+	start: bool,
+	looping: bool,
+	constant: bool,
+	volume: u8,
+
+	divider: u8,
+	decay: u8
+}
+
+impl Envelope
+{
+	fn new() -> Envelope
+	{
+		Envelope { start: false, looping: false, constant: false, volume: 0, divider: 0, decay: 0 }
+	}
+
+	fn write(&mut self, val: u8)
+	{
+		self.looping = (val & 0x20) == 0x20;
+		self.constant = (val & 0x10) == 0x10;
+		self.volume = val & 0x0F;
+	}
+
+	fn clock(&mut self)
+	{
+		if self.start
+		{
+			self.start = false;
+			self.decay = 15;
+			self.divider = self.volume;
+			return;
+		}
+
+		match self.divider
+		{
+			0 => {
+				self.divider = self.volume;
+				match self.decay
+				{
+					0 if self.looping 	=> self.decay = 15,
+					0 					=> { },
+					_ 					=> self.decay -= 1
+				}
+			},
+
+			_ => self.divider -= 1
+		}
+	}
+
+	fn output(&self) -> u8
+	{
+		match self.constant
+		{
+			true 	=> self.volume,
+			false 	=> self.decay
+		}
+	}
+}
+
+struct Pulse
+{
+	// The first pulse channel negates with one's complement, the second with two's complement
+	second: bool,
+
+	enabled: bool,
+	duty: u8,
+	step: u8,
+	period: u16,
+	timer: u16,
+	length: u8,
+	envelope: Envelope,
+
+	sweep_enabled: bool,
+	sweep_period: u8,
+	sweep_negate: bool,
+	sweep_shift: u8,
+	sweep_reload: bool,
+	sweep_divider: u8
+}
+
+impl Pulse
+{
+	fn new(second: bool) -> Pulse
+	{
+		Pulse
+		{
+			second,
+
+			enabled: false,
+			duty: 0,
+			step: 0,
+			period: 0,
+			timer: 0,
+			length: 0,
+			envelope: Envelope::new(),
+
+			sweep_enabled: false,
+			sweep_period: 0,
+			sweep_negate: false,
+			sweep_shift: 0,
+			sweep_reload: false,
+			sweep_divider: 0
+		}
+	}
+
+	fn write(&mut self, register: u16, val: u8)
+	{
+		match register
+		{
+			0 => {
+				self.duty = val >> 6;
+				self.envelope.write(val);
+			},
+
+			1 => {
+				self.sweep_enabled = (val & 0x80) == 0x80;
+				self.sweep_period = (val >> 4) & 0x07;
+				self.sweep_negate = (val & 0x08) == 0x08;
+				self.sweep_shift = val & 0x07;
+				self.sweep_reload = true;
+			},
+
+			2 => self.period = (self.period & 0x0700) | val as u16,
+
+			_ => {
+				self.period = (self.period & 0x00FF) | (((val & 0x07) as u16) << 8);
+				if self.enabled
+				{
+					self.length = LENGTH_TABLE[(val >> 3) as usize];
+				}
+
+				self.step = 0;
+				self.envelope.start = true;
+			}
+		}
+	}
+
+	fn target_period(&self) -> u16
+	{
+		let change = self.period >> self.sweep_shift;
+		match (self.sweep_negate, self.second)
+		{
+			(false, _) 		=> self.period + change,
+			(true, false) 	=> self.period.saturating_sub(change + 1),
+			(true, true) 	=> self.period.saturating_sub(change)
+		}
+	}
+
+	// The sweep unit mutes the channel even while it isn't adjusting the period
+	fn muted(&self) -> bool
+	{
+		self.period < 8 || self.target_period() > 0x7FF
+	}
+
+	fn clock_timer(&mut self)
+	{
+		match self.timer
+		{
+			0 => {
+				self.timer = self.period;
+				self.step = (self.step + 1) & 0x07;
+			},
+
+			_ => self.timer -= 1
+		}
+	}
+
+	fn clock_length(&mut self)
+	{
+		if !self.envelope.looping && self.length > 0
+		{
+			self.length -= 1;
+		}
+	}
+
+	fn clock_sweep(&mut self)
+	{
+		if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted()
+		{
+			self.period = self.target_period();
+		}
+
+		match self.sweep_divider == 0 || self.sweep_reload
+		{
+			true => {
+				self.sweep_divider = self.sweep_period;
+				self.sweep_reload = false;
+			},
+
+			false => self.sweep_divider -= 1
+		}
+	}
+
+	fn output(&self) -> u8
+	{
+		match self.length > 0 && !self.muted() && DUTY_TABLE[self.duty as usize][self.step as usize] == 1
+		{
+			true 	=> self.envelope.output(),
+			false 	=> 0
+		}
+	}
+}
+
+struct Triangle
+{
+	enabled: bool,
+	control: bool,
+	step: u8,
+	period: u16,
+	timer: u16,
+	length: u8,
+
+	linear_load: u8,
+	linear_counter: u8,
+	linear_reload: bool
+}
+
+impl Triangle
+{
+	fn new() -> Triangle
+	{
+		Triangle { enabled: false, control: false, step: 0, period: 0, timer: 0, length: 0, linear_load: 0, linear_counter: 0, linear_reload: false }
+	}
+
+	fn write(&mut self, register: u16, val: u8)
+	{
+		match register
+		{
+			0 => {
+				self.control = (val & 0x80) == 0x80;
+				self.linear_load = val & 0x7F;
+			},
+
+			2 => self.period = (self.period & 0x0700) | val as u16,
+
+			3 => {
+				self.period = (self.period & 0x00FF) | (((val & 0x07) as u16) << 8);
+				if self.enabled
+				{
+					self.length = LENGTH_TABLE[(val >> 3) as usize];
+				}
+
+				self.linear_reload = true;
+			},
+
+			_ => { }
+		}
+	}
+
+	// The sequencer only advances while both counters are running
+	fn clock_timer(&mut self)
+	{
+		match self.timer
+		{
+			0 => {
+				self.timer = self.period;
+				if self.length > 0 && self.linear_counter > 0
+				{
+					self.step = (self.step + 1) & 0x1F;
+				}
+			},
+
+			_ => self.timer -= 1
+		}
+	}
+
+	fn clock_length(&mut self)
+	{
+		if !self.control && self.length > 0
+		{
+			self.length -= 1;
+		}
+	}
+
+	fn clock_linear(&mut self)
+	{
+		match self.linear_reload
+		{
+			true 	=> self.linear_counter = self.linear_load,
+			false 	=> self.linear_counter = self.linear_counter.saturating_sub(1)
+		}
+
+		if !self.control
+		{
+			self.linear_reload = false;
+		}
+	}
+
+	// The sequencer holds its step while halted, so silencing the channel leaves it at a level instead of popping to 0
+	fn output(&self) -> u8
+	{
+		TRIANGLE_TABLE[self.step as usize]
+	}
+}
+
+struct Noise
+{
+	enabled: bool,
+	mode: bool,
+	period: u16,
+	timer: u16,
+	length: u8,
+	envelope: Envelope,
+
+	shift: u16
+}
+
+impl Noise
+{
+	fn new() -> Noise
+	{
+		Noise { enabled: false, mode: false, period: NOISE_TABLE[0], timer: 0, length: 0, envelope: Envelope::new(), shift: 1 }
+	}
+
+	fn write(&mut self, register: u16, val: u8)
+	{
+		match register
+		{
+			0 => self.envelope.write(val),
+
+			2 => {
+				self.mode = (val & 0x80) == 0x80;
+				self.period = NOISE_TABLE[(val & 0x0F) as usize];
+			},
+
+			3 => {
+				if self.enabled
+				{
+					self.length = LENGTH_TABLE[(val >> 3) as usize];
+				}
+
+				self.envelope.start = true;
+			},
+
+			_ => { }
+		}
+	}
+
+	// 15-bit LFSR, mode 1 feeds back from bit 6 for the short metallic sequence
+	fn clock_timer(&mut self)
+	{
+		match self.timer
+		{
+			0 => {
+				self.timer = self.period - 1;
+
+				let tap = match self.mode
+				{
+					true 	=> 6,
+					false 	=> 1
+				};
+
+				let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+				self.shift = (self.shift >> 1) | (feedback << 14);
+			},
+
+			_ => self.timer -= 1
+		}
+	}
+
+	fn clock_length(&mut self)
+	{
+		if !self.envelope.looping && self.length > 0
+		{
+			self.length -= 1;
+		}
+	}
+
+	fn output(&self) -> u8
+	{
+		match self.length > 0 && (self.shift & 0x01) == 0
+		{
+			true 	=> self.envelope.output(),
+			false 	=> 0
+		}
+	}
+}
+
+struct DMC
+{
+	irq_enabled: bool,
+	irq: bool,
+	looping: bool,
+	period: u16,
+	timer: u16,
+
+	sample_address: u16,
+	sample_length: u16,
+	address: u16,
+	remaining: u16,
+	buffer: Option<u8>,
+
+	shift: u8,
+	bits: u8,
+	silence: bool,
+	level: u8
+}
+
+impl DMC
+{
+	fn new() -> DMC
+	{
+		DMC
+		{
+			irq_enabled: false,
+			irq: false,
+			looping: false,
+			period: DMC_TABLE[0],
+			timer: 0,
+
+			sample_address: 0xC000,
+			sample_length: 1,
+			address: 0xC000,
+			remaining: 0,
+			buffer: None,
+
+			shift: 0,
+			bits: 8,
+			silence: true,
+			level: 0
+		}
+	}
+
+	fn write(&mut self, register: u16, val: u8)
+	{
+		match register
+		{
+			0 => {
+				self.irq_enabled = (val & 0x80) == 0x80;
+				self.looping = (val & 0x40) == 0x40;
+				self.period = DMC_TABLE[(val & 0x0F) as usize];
+
+				if !self.irq_enabled
+				{
+					self.irq = false;
+				}
+			},
+
+			1 => self.level = val & 0x7F,
+			2 => self.sample_address = 0xC000 | ((val as u16) << 6),
+			_ => self.sample_length = ((val as u16) << 4) | 0x01
+		}
+	}
+
+	fn restart(&mut self)
+	{
+		self.address = self.sample_address;
+		self.remaining = self.sample_length;
+	}
+
+	// Returns the address of the next sample byte if the buffer needs refilling
+	fn fetch_address(&self) -> Option<u16>
+	{
+		match self.buffer.is_none() && self.remaining > 0
+		{
+			true 	=> Some(self.address),
+			false 	=> None
+		}
+	}
+
+	// The address wraps around to $8000 after $FFFF
+	fn fill(&mut self, val: u8)
+	{
+		self.buffer = Some(val);
+		self.address = match self.address
+		{
+			0xFFFF 	=> 0x8000,
+			addr 	=> addr + 1
+		};
+
+		self.remaining -= 1;
+		if self.remaining == 0
+		{
+			match self.looping
+			{
+				true 	=> self.restart(),
+				false 	=> self.irq = self.irq_enabled
+			}
+		}
+	}
+
+	fn clock_timer(&mut self)
+	{
+		if self.timer > 0
+		{
+			self.timer -= 1;
+			return;
+		}
+
+		self.timer = self.period - 1;
+
+		if !self.silence
+		{
+			match self.shift & 0x01
+			{
+				1 if self.level <= 125 	=> self.level += 2,
+				0 if self.level >= 2 	=> self.level -= 2,
+				_ 						=> { }
+			}
+		}
+
+		self.shift >>= 1;
+		self.bits -= 1;
+
+		if self.bits == 0
+		{
+			self.bits = 8;
+			match self.buffer.take()
+			{
+				Some(val) => {
+					self.shift = val;
+					self.silence = false;
+				},
+
+				None => self.silence = true
+			}
+		}
+	}
+}
+
+// The 2A03's sound generator: two pulse channels, a triangle, noise and delta modulation,
+// sequenced by the frame counter. DMC sample fetches go out over the CPU bus
+pub struct APU
+{
+	pulse1: Pulse,
+	pulse2: Pulse,
+	triangle: Triangle,
+	noise: Noise,
+	dmc: DMC,
+
+	five_step: bool,
+	irq_inhibit: bool,
+	frame_irq: bool,
+	frame_cycle: u32,
+	cycle: u64,
+
+	samples: Vec<f32>,
+	sample_sum: f32,
+	sample_count: u32,
+	sample_time: f64,
+
+	bus: Weak<RefCell<Bus>>
+}
+
+impl APU
+{
+	pub fn new(bus: &Rc<RefCell<Bus>>) -> APU
+	{
+		APU
+		{
+			pulse1: Pulse::new(false),
+			pulse2: Pulse::new(true),
+			triangle: Triangle::new(),
+			noise: Noise::new(),
+			dmc: DMC::new(),
+
+			five_step: false,
+			irq_inhibit: false,
+			frame_irq: false,
+			frame_cycle: 0,
+			cycle: 0,
+
+			samples: Vec::new(),
+			sample_sum: 0.0,
+			sample_count: 0,
+			sample_time: 0.0,
+
+			bus: Rc::downgrade(bus)
+		}
+	}
+
+	pub fn irq(&self) -> bool
+	{
+		self.frame_irq || self.dmc.irq
+	}
+
+	// Reading the status clears the frame interrupt
+	pub fn read_status(&mut self) -> u8
+	{
+		let status = (self.pulse1.length > 0) as u8
+			| ((self.pulse2.length > 0) as u8) << 1
+			| ((self.triangle.length > 0) as u8) << 2
+			| ((self.noise.length > 0) as u8) << 3
+			| ((self.dmc.remaining > 0) as u8) << 4
+			| (self.frame_irq as u8) << 6
+			| (self.dmc.irq as u8) << 7;
+
+		self.frame_irq = false;
+		status
+	}
+
+	pub fn write_register(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x4000..=0x4003 => self.pulse1.write(addr & 0x03, val),
+			0x4004..=0x4007 => self.pulse2.write(addr & 0x03, val),
+			0x4008..=0x400B => self.triangle.write(addr & 0x03, val),
+			0x400C..=0x400F => self.noise.write(addr & 0x03, val),
+			0x4010..=0x4013 => self.dmc.write(addr & 0x03, val),
+
+			0x4015 => {
+				self.pulse1.enabled = (val & 0x01) == 0x01;
+				self.pulse2.enabled = (val & 0x02) == 0x02;
+				self.triangle.enabled = (val & 0x04) == 0x04;
+				self.noise.enabled = (val & 0x08) == 0x08;
+
+				// Disabling a channel silences it right away by clearing its length counter
+				if !self.pulse1.enabled 	{ self.pulse1.length = 0; }
+				if !self.pulse2.enabled 	{ self.pulse2.length = 0; }
+				if !self.triangle.enabled 	{ self.triangle.length = 0; }
+				if !self.noise.enabled 		{ self.noise.length = 0; }
+
+				match (val & 0x10) == 0x10
+				{
+					true if self.dmc.remaining == 0 	=> self.dmc.restart(),
+					true 								=> { },
+					false 								=> self.dmc.remaining = 0
+				}
+
+				self.dmc.irq = false;
+			},
+
+			// Switching to the 5-step sequence clocks the envelopes and length counters immediately
+			0x4017 => {
+				self.five_step = (val & 0x80) == 0x80;
+				self.irq_inhibit = (val & 0x40) == 0x40;
+				self.frame_cycle = 0;
+
+				if self.irq_inhibit
+				{
+					self.frame_irq = false;
+				}
+
+				if self.five_step
+				{
+					self.quarter_frame();
+					self.half_frame();
+				}
+			},
+
+			_ => { }
+		}
+	}
+
+	// Reset silences every channel as if $4015 was cleared and restarts the frame counter
+	// in the mode it was in
+	pub fn reset(&mut self)
+	{
+		self.write_register(0x4015, 0x00);
+
+		self.frame_irq = false;
+		self.frame_cycle = 0;
+		self.dmc.level &= 0x01;
+	}
+
+	// Called once per CPU cycle, the pulse and noise timers run at half that rate
+	pub fn clock(&mut self)
+	{
+		self.cycle += 1;
+
+		if self.cycle.is_multiple_of(2)
+		{
+			self.pulse1.clock_timer();
+			self.pulse2.clock_timer();
+		}
+
+		self.triangle.clock_timer();
+		self.noise.clock_timer();
+		self.dmc.clock_timer();
+
+		if let Some(addr) = self.dmc.fetch_address()
+		{
+			let val = self.bus.upgrade().unwrap().borrow().read_cpu(addr);
+			self.dmc.fill(val);
+		}
+
+		self.clock_frame_counter();
+
+		let expansion = self.bus.upgrade().unwrap().borrow().expansion_audio();
+		self.generate_sample(expansion);
+	}
+
+	fn clock_frame_counter(&mut self)
+	{
+		self.frame_cycle += 1;
+
+		let steps: &[u32] = match self.five_step
+		{
+			true 	=> &FIVE_STEP,
+			false 	=> &FOUR_STEP
+		};
+
+		let step = match steps.iter().position(|&cycle| cycle == self.frame_cycle)
+		{
+			Some(step) 	=> step,
+			None 		=> return
+		};
+
+		// The 5-step sequence has an empty fourth step
+		if !(self.five_step && step == 3)
+		{
+			self.quarter_frame();
+		}
+
+		if step == 1 || step == steps.len() - 1
+		{
+			self.half_frame();
+		}
+
+		if step == steps.len() - 1
+		{
+			self.frame_cycle = 0;
+			if !self.five_step && !self.irq_inhibit
+			{
+				self.frame_irq = true;
+			}
+		}
+	}
+
+	fn quarter_frame(&mut self)
+	{
+		self.pulse1.envelope.clock();
+		self.pulse2.envelope.clock();
+		self.noise.envelope.clock();
+		self.triangle.clock_linear();
+	}
+
+	fn half_frame(&mut self)
+	{
+		self.pulse1.clock_length();
+		self.pulse2.clock_length();
+		self.triangle.clock_length();
+		self.noise.clock_length();
+
+		self.pulse1.clock_sweep();
+		self.pulse2.clock_sweep();
+	}
+
+	// Non-linear mixer approximation from the NESdev wiki, between 0.0 and 1.0
+	pub fn output(&self) -> f32
+	{
+		let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+		let pulse_out = match pulse > 0.0
+		{
+			true 	=> 95.88 / (8128.0 / pulse + 100.0),
+			false 	=> 0.0
+		};
+
+		let tnd = self.triangle.output() as f32 / 8227.0 + self.noise.output() as f32 / 12241.0 + self.dmc.level as f32 / 22638.0;
+		let tnd_out = match tnd > 0.0
+		{
+			true 	=> 159.79 / (1.0 / tnd + 100.0),
+			false 	=> 0.0
+		};
+
+		pulse_out + tnd_out
+	}
+
+	// Averages the output over every CPU cycle that falls into a sample, together with the cartridge's
+	// expansion audio
+	fn generate_sample(&mut self, expansion: f32)
+	{
+		self.sample_sum += self.output() + expansion * EXPANSION_LEVEL;
+		self.sample_count += 1;
+
+		self.sample_time += SAMPLE_RATE as f64 / CPU_FREQUENCY;
+		if self.sample_time >= 1.0
+		{
+			self.sample_time -= 1.0;
+
+			if self.samples.len() < MAX_SAMPLES
+			{
+				self.samples.push(self.sample_sum / self.sample_count as f32);
+			}
+
+			self.sample_sum = 0.0;
+			self.sample_count = 0;
+		}
+	}
+
+	// Hands out the samples generated since the last call, at SAMPLE_RATE
+	pub fn take_samples(&mut self) -> Vec<f32>
+	{
+		std::mem::take(&mut self.samples)
+	}
+}
@@ -0,0 +1,51 @@
+use std::{borrow::Cow, io::{Cursor, Read}};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::nes::cartridge::RomError;
+
+// ROM sets are often kept compressed, archives are detected by their magic number and unpacked in memory.
+// Anything else is passed through untouched
+pub fn unpack(data: &[u8]) -> Result<Cow<'_, [u8]>, RomError>
+{
+	if data.starts_with(b"PK\x03\x04")
+	{
+		return unzip(data).map(Cow::Owned);
+	}
+
+	if data.starts_with(&[0x1F, 0x8B])
+	{
+		return gunzip(data).map(Cow::Owned);
+	}
+
+	Ok(Cow::Borrowed(data))
+}
+
+// Picks the first .nes entry in the archive
+fn unzip(data: &[u8]) -> Result<Vec<u8>, RomError>
+{
+	let mut archive = ZipArchive::new(Cursor::new(data))?;
+
+	for i in 0..archive.len()
+	{
+		let mut file = archive.by_index(i)?;
+		if file.is_file() && file.name().to_ascii_lowercase().ends_with(".nes")
+		{
+			let mut rom = Vec::with_capacity(file.size() as usize);
+			file.read_to_end(&mut rom)?;
+
+			return Ok(rom);
+		}
+	}
+
+	Err(RomError::NoRomInArchive)
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, RomError>
+{
+	let mut rom = Vec::new();
+	GzDecoder::new(data).read_to_end(&mut rom)?;
+
+	Ok(rom)
+}
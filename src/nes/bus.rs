@@ -3,26 +3,29 @@ use std::rc::{Rc, Weak};
 
 use crate::nes::cpu::CPU;
 use crate::nes::ppu::PPU;
+use crate::nes::apu::APU;
 use crate::nes::cartridge::Cartridge;
 
 pub struct Bus
 {
 	cpu: Weak<RefCell<CPU>>,
 	ppu: Weak<RefCell<PPU>>,
-	cartridge: Cartridge,
+	apu: Weak<RefCell<APU>>,
+	cartridge: Rc<RefCell<Cartridge>>,
 
 	ram: Vec<u8>
 }
 
 impl Bus 
 {
-	pub fn new() -> Bus 
+	pub fn new(cartridge: Cartridge) -> Bus 
 	{
 		Bus 
 		{
 			cpu: Weak::new(),
 			ppu: Weak::new(),
-			cartridge: Cartridge::new("roms/nestest.nes"),
+			apu: Weak::new(),
+			cartridge: Rc::new(RefCell::new(cartridge)),
 			ram: vec![0; 0x800]
 		}
 	}
@@ -37,15 +40,55 @@ impl Bus
 		self.ppu = Rc::downgrade(ppu);
 	}
 
+	pub fn attach_apu(&mut self, apu: &Rc<RefCell<APU>>)
+	{	
+		self.apu = Rc::downgrade(apu);
+	}
+
+	pub fn cartridge(&self) -> Rc<RefCell<Cartridge>>
+	{
+		Rc::clone(&self.cartridge)
+	}
+
+	pub fn nmi(&self) -> bool
+	{
+		self.ppu.upgrade().unwrap().borrow().nmi()
+	}
+
+	pub fn irq(&self) -> bool
+	{
+		self.cartridge.borrow().irq() || self.apu.upgrade().unwrap().borrow().irq()
+	}
+
+	// Expansion audio from chips on the cartridge, mixed in with the APU
+	pub fn expansion_audio(&self) -> f32
+	{
+		self.cartridge.borrow().audio_output()
+	}
+
 	pub fn read_cpu(&self, addr: u16) -> u8 
 	{
 		match addr
 		{
 			0..=0x1FFF 		=> self.ram[(addr & 0x7FF) as usize],
 			0x2000..=0x3FFF => self.ppu.upgrade().unwrap().borrow_mut().get_regsiter(addr & 0x7),
-			0x8000..=0xFFFF => self.cartridge.read_prg(addr & 0x7FFF),
+			0x4015 			=> self.apu.upgrade().unwrap().borrow_mut().read_status(),
+			0x4020..=0xFFFF => self.cartridge.borrow_mut().read_cpu(addr),
+
+			// The write-only APU registers, dummy reads of indexed stores land here
+			_ => 0
+		}
+	}
 
-			_ => panic!("Tried to access invalid memory address ${:04X}", addr)
+	// Reads without side effects for the trace, the PPU and APU registers change state when read
+	pub fn peek_cpu(&self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0..=0x1FFF 		=> self.ram[(addr & 0x7FF) as usize],
+			0x6000..=0xFFFF => self.cartridge.borrow_mut().read_cpu(addr),
+
+			_ => 0
 		}
 	}
 
@@ -54,8 +97,14 @@ impl Bus
 		match addr 
 		{
 			0..=0x1FFF 		=> self.ram[(addr & 0x7FF) as usize] = val,
-			0x2000..=0x3FFF => self.ppu.upgrade().unwrap().borrow_mut().set_regsiter(addr & 0x7, val),
-			0x8000..=0xFFFF => self.cartridge.write_prg(addr & 0x7FFF, val),
+			0x2000..=0x3FFF => {
+				self.ppu.upgrade().unwrap().borrow_mut().set_regsiter(addr & 0x7, val);
+
+				// Some mappers snoop on the PPU registers
+				self.cartridge.borrow_mut().write_cpu(addr, val);
+			},
+			0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.upgrade().unwrap().borrow_mut().write_register(addr, val),
+			0x4020..=0xFFFF => self.cartridge.borrow_mut().write_cpu(addr, val),
 
 			_ => { }
 		}
@@ -1,9 +1,354 @@
-use std::{fs::File, io::{BufReader, Read}};
+use std::{error::Error, fmt, fs, io::{self, Read}, path::{Path, PathBuf}};
 
-struct Header 
+use zip::result::ZipError;
+
+use crate::nes::archive;
+use crate::nes::database;
+use crate::nes::unif;
+use crate::nes::patch::{self, PatchError};
+use crate::nes::mappers::{self, Mapper};
+use crate::nes::mappers::fds::{self, FDS};
+use crate::nes::mappers::nsf::{self, NsfInfo};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mirroring
+{
+	Horizontal,
+	Vertical,
+	FourScreen,
+	SingleScreenLower,
+	SingleScreenUpper
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Timing
+{
+	Ntsc,
+	Pal,
+	MultiRegion,
+	Dendy
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ConsoleType
+{
+	Nes,
+	VsSystem,
+	Playchoice,
+	Extended(u8)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct VsSystem
+{
+	pub ppu_type: u8,
+	pub hardware_type: u8
+}
+
+#[derive(Clone, Debug)]
+pub struct Header
+{
+	pub nes2: bool,
+
+	pub prg_blocks: u16,
+	pub chr_blocks: u16,
+	pub prg_rom_size: usize,
+	pub chr_rom_size: usize,
+
+	pub mapper: u16,
+	pub submapper: u8,
+	pub mirroring: Mirroring,
+	pub battery: bool,
+	pub trainer: bool,
+	pub four_screen: bool,
+
+	pub prg_ram_size: usize,
+	pub prg_nvram_size: usize,
+	pub chr_ram_size: usize,
+	pub chr_nvram_size: usize,
+
+	pub timing: Timing,
+	pub console: ConsoleType,
+	pub vs_system: Option<VsSystem>,
+	pub misc_roms: u8,
+	pub expansion_device: u8
+}
+
+// NES 2.0 stores RAM sizes as shift counts, where the size is 64 << n bytes and 0 means none
+fn shift_size(count: u8) -> usize
+{
+	match count
+	{
+		0 => 0,
+		n => 64usize << n
+	}
+}
+
+// NES 2.0 ROM sizes use the exponent-multiplier notation if the MSB nibble is $F. Sizes too large
+// to represent become usize::MAX, which no image is long enough to contain
+fn rom_size(lsb: u8, msb: u8, block_size: usize) -> (u16, usize)
+{
+	match msb
+	{
+		0x0F => {
+			let exponent = (lsb >> 2) as u32;
+			let multiplier = (lsb & 0x03) as usize * 2 + 1;
+			let size = 1usize.checked_shl(exponent)
+				.and_then(|size| size.checked_mul(multiplier))
+				.unwrap_or(usize::MAX);
+
+			((size / block_size) as u16, size)
+		},
+
+		_ => {
+			let blocks = ((msb as u16) << 8) | lsb as u16;
+			(blocks, block_size * blocks as usize)
+		}
+	}
+}
+
+impl Header
+{
+	// For formats without an iNES header, the rest of the fields are filled in by the caller
+	pub fn new(mapper: u16, prg_rom_size: usize, chr_rom_size: usize) -> Header
+	{
+		Header
+		{
+			nes2: false,
+
+			prg_blocks: (prg_rom_size / 0x4000) as u16,
+			chr_blocks: (chr_rom_size / 0x2000) as u16,
+			prg_rom_size,
+			chr_rom_size,
+
+			mapper,
+			submapper: 0,
+			mirroring: Mirroring::Horizontal,
+			battery: false,
+			trainer: false,
+			four_screen: false,
+
+			prg_ram_size: 0,
+			prg_nvram_size: 0,
+			chr_ram_size: 0,
+			chr_nvram_size: 0,
+
+			timing: Timing::Ntsc,
+			console: ConsoleType::Nes,
+			vs_system: None,
+			misc_roms: 0,
+			expansion_device: 0
+		}
+	}
+
+	pub fn parse(data: &[u8; 16]) -> Header
+	{
+		// Old dumping tools signed bytes 7-15 of iNES 1.0 headers ("DiskDude!"),
+		// none of them can be trusted if the padding at the end isn't zero
+		let mut data = *data;
+		if (data[7] & 0x0C) != 0x08 && data[12..16].iter().any(|&byte| byte != 0)
+		{
+			data[7..16].fill(0);
+		}
+
+		let flags6 = data[6];
+		let flags7 = data[7];
+
+		let four_screen = (flags6 & 0x08) == 0x08;
+		let mirroring = match (four_screen, flags6 & 0x01)
+		{
+			(true, _) 	=> Mirroring::FourScreen,
+			(false, 0) 	=> Mirroring::Horizontal,
+			(false, _) 	=> Mirroring::Vertical
+		};
+
+		let battery = (flags6 & 0x02) == 0x02;
+		let console = match flags7 & 0x03
+		{
+			0 => ConsoleType::Nes,
+			1 => ConsoleType::VsSystem,
+			2 => ConsoleType::Playchoice,
+			_ => ConsoleType::Extended(data[13] & 0x0F)
+		};
+
+		let mut header = Header
+		{
+			nes2: (flags7 & 0x0C) == 0x08,
+
+			prg_blocks: data[4] as u16,
+			chr_blocks: data[5] as u16,
+			prg_rom_size: 0x4000 * data[4] as usize,
+			chr_rom_size: 0x2000 * data[5] as usize,
+
+			mapper: ((flags7 & 0xF0) | (flags6 >> 4)) as u16,
+			submapper: 0,
+			mirroring,
+			battery,
+			trainer: (flags6 & 0x04) == 0x04,
+			four_screen,
+
+			prg_ram_size: 0,
+			prg_nvram_size: 0,
+			chr_ram_size: 0,
+			chr_nvram_size: 0,
+
+			timing: match data[9] & 0x01
+			{
+				0 => Timing::Ntsc,
+				_ => Timing::Pal
+			},
+			console,
+			vs_system: None,
+			misc_roms: 0,
+			expansion_device: 0
+		};
+
+		if !header.nes2
+		{
+			// Byte 8 is the PRG-RAM size in 8K units, a value of 0 infers 8K for compatibility
+			let prg_ram_size = 0x2000 * match data[8]
+			{
+				0 => 1,
+				n => n as usize
+			};
+
+			match battery
+			{
+				true 	=> header.prg_nvram_size = prg_ram_size,
+				false 	=> header.prg_ram_size = prg_ram_size
+			};
+
+			// iNES 1.0 can't declare CHR-RAM, boards without CHR-ROM carry 8K of it
+			if header.chr_blocks == 0
+			{
+				header.chr_ram_size = 0x2000;
+			}
+
+			if console == ConsoleType::VsSystem
+			{
+				header.vs_system = Some(VsSystem { ppu_type: 0, hardware_type: 0 });
+			}
+
+			return header;
+		}
+
+		(header.prg_blocks, header.prg_rom_size) = rom_size(data[4], data[9] & 0x0F, 0x4000);
+		(header.chr_blocks, header.chr_rom_size) = rom_size(data[5], data[9] >> 4, 0x2000);
+
+		header.mapper |= ((data[8] & 0x0F) as u16) << 8;
+		header.submapper = data[8] >> 4;
+
+		header.prg_ram_size = shift_size(data[10] & 0x0F);
+		header.prg_nvram_size = shift_size(data[10] >> 4);
+		header.chr_ram_size = shift_size(data[11] & 0x0F);
+		header.chr_nvram_size = shift_size(data[11] >> 4);
+
+		header.timing = match data[12] & 0x03
+		{
+			0 => Timing::Ntsc,
+			1 => Timing::Pal,
+			2 => Timing::MultiRegion,
+			_ => Timing::Dendy
+		};
+
+		if console == ConsoleType::VsSystem
+		{
+			header.vs_system = Some(VsSystem {
+				ppu_type: data[13] & 0x0F,
+				hardware_type: data[13] >> 4
+			});
+		}
+
+		header.misc_roms = data[14] & 0x03;
+		header.expansion_device = data[15] & 0x3F;
+
+		header
+	}
+}
+
+#[derive(Debug)]
+pub enum RomError
+{
+	Io(io::Error),
+	Zip(ZipError),
+	NoRomInArchive,
+	Patch(PatchError),
+	BadMagic,
+	Truncated,
+	NoPrgRom,
+	BadTrainer,
+	UnsupportedMapper { mapper: u16, board: Option<&'static str> },
+	UnsupportedBoard(String),
+	MissingBios,
+	BadBios
+}
+
+impl fmt::Display for RomError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		match self
+		{
+			RomError::Io(err) 					=> write!(f, "{}", err),
+			RomError::Zip(err) 					=> write!(f, "{}", err),
+			RomError::NoRomInArchive 			=> write!(f, "Archive does not contain a .nes file"),
+			RomError::Patch(err) 				=> write!(f, "Failed to apply patch: {}", err),
+			RomError::BadMagic 					=> write!(f, "ROM is not in the iNES, UNIF, FDS or NSF format"),
+			RomError::Truncated 				=> write!(f, "ROM does not contain the amount of data specified in the header"),
+			RomError::BadTrainer 				=> write!(f, "ROM does not contain the trainer specified in the header"),
+			RomError::NoPrgRom 					=> write!(f, "ROM does not contain any PRG-ROM"),
+			RomError::UnsupportedMapper { mapper, board } => match board
+			{
+				Some(board) => write!(f, "Mapper {} ({}) is not supported", mapper, board),
+				None 		=> write!(f, "Mapper {} is not supported", mapper)
+			},
+			RomError::UnsupportedBoard(board) 	=> match board.is_empty()
+			{
+				true 	=> write!(f, "UNIF image does not name its board"),
+				false 	=> write!(f, "Board {} is not supported", board)
+			},
+			RomError::MissingBios 				=> write!(f, "Disk images need the FDS BIOS (disksys.rom)"),
+			RomError::BadBios 					=> write!(f, "FDS BIOS has to be 8K")
+		}
+	}
+}
+
+impl Error for RomError
+{
+	fn source(&self) -> Option<&(dyn Error + 'static)>
+	{
+		match self
+		{
+			RomError::Io(err) 		=> Some(err),
+			RomError::Zip(err) 		=> Some(err),
+			RomError::Patch(err) 	=> Some(err),
+			_ 						=> None
+		}
+	}
+}
+
+impl From<io::Error> for RomError
+{
+	fn from(err: io::Error) -> RomError
+	{
+		RomError::Io(err)
+	}
+}
+
+impl From<ZipError> for RomError
+{
+	fn from(err: ZipError) -> RomError
+	{
+		RomError::Zip(err)
+	}
+}
+
+impl From<PatchError> for RomError
 {
-	prg_blocks: u8,
-	chr_blocks: u8
+	fn from(err: PatchError) -> RomError
+	{
+		RomError::Patch(err)
+	}
 }
 
 #[allow(dead_code)]
@@ -11,50 +356,428 @@ pub struct Cartridge
 {
 	header: Header,
 
-	prg: Vec<u8>,
-	chr: Vec<u8>
+	trainer: Option<Vec<u8>>,
+	mapper: Box<dyn Mapper>,
+	title: Option<String>,
+	nsf: Option<NsfInfo>,
+
+	// Images loaded from memory have nowhere to keep their save
+	save_path: Option<PathBuf>,
+	saved_data: Vec<u8>
+}
+
+// Everything that changes how an image is loaded, the defaults load it as it is
+#[derive(Default)]
+pub struct LoadOptions
+{
+	// IPS, UPS and BPS patches are applied in order to the unpacked image, before the header is parsed
+	pub patches: Vec<Vec<u8>>,
+
+	// Disk images run on the FDS BIOS, which isn't needed for anything else
+	pub bios: Option<Vec<u8>>,
+
+	// Images with an unsupported mapper are run as NROM instead of failing to load, which gets
+	// some of them as far as the title screen
+	pub nrom_fallback: bool
 }
 
-impl Cartridge 
+impl Cartridge
 {
-	pub fn new(filepath: &str) -> Cartridge 
+	// Accepts a plain iNES image as well as one inside a zip or gzip archive
+	pub fn from_bytes(data: &[u8]) -> Result<Cartridge, RomError>
 	{
-		let fp = File::open(filepath).expect("Failed to load ROM");
-		let mut reader = BufReader::new(fp);
+		Cartridge::load(data, &LoadOptions::default())
+	}
 
-		let mut header_data = vec![0u8; 16];
-		reader.read_exact(&mut header_data).expect("Header not present in ROM");
+	pub fn load(data: &[u8], options: &LoadOptions) -> Result<Cartridge, RomError>
+	{
+		let mut image = archive::unpack(data)?;
+		for patch in &options.patches
+		{
+			image = patch::apply(&image, patch)?.into();
+		}
+
+		let data: &[u8] = &image;
+
+		if data.starts_with(b"UNIF")
+		{
+			let unif = unif::parse(data)?;
+			return Cartridge::assemble(unif.header, None, unif.prg, unif.chr, unif.name, options.nrom_fallback);
+		}
+
+		if fds::is_disk_image(data)
+		{
+			return Cartridge::assemble_disk(data, options.bios.as_deref().ok_or(RomError::MissingBios)?);
+		}
+
+		if nsf::is_nsf(data)
+		{
+			return Cartridge::assemble_nsf(data);
+		}
 
-		let header = Header {
-			prg_blocks: header_data[4],
-			chr_blocks: header_data[5]
+		if !data.starts_with(b"NES\x1A")
+		{
+			return Err(RomError::BadMagic);
+		}
+
+		let header_data: &[u8; 16] = data.get(..16)
+			.and_then(|header| header.try_into().ok())
+			.ok_or(RomError::Truncated)?;
+
+		let header = Header::parse(header_data);
+		let mut offset = 16;
+
+		let trainer = match header.trainer
+		{
+			true => {
+				let trainer_data = data.get(offset..offset + 0x200).ok_or(RomError::BadTrainer)?;
+				offset += 0x200;
+
+				Some(trainer_data.to_vec())
+			},
+
+			false => None
 		};
 
-		// TODO: For now assume there is no trainer
-		let mut prg_data = vec![0u8; 0x4000 * header.prg_blocks as usize];
-		let mut chr_data = vec![0u8; 0x2000 * header.chr_blocks as usize];
+		let prg_end = offset.checked_add(header.prg_rom_size).ok_or(RomError::Truncated)?;
+		let prg_data = data.get(offset..prg_end).ok_or(RomError::Truncated)?.to_vec();
+		offset = prg_end;
 
-		reader.read_exact(&mut prg_data).expect("ROM does not contain specified amount of PRG data");
-		reader.read_exact(&mut chr_data).expect("ROM does not contain specified amount of CHR data");
+		let chr_end = offset.checked_add(header.chr_rom_size).ok_or(RomError::Truncated)?;
+		let chr_data = data.get(offset..chr_end).ok_or(RomError::Truncated)?.to_vec();
 
-		Cartridge 
-		{ 
+		Cartridge::assemble(header, trainer, prg_data, chr_data, None, options.nrom_fallback)
+	}
+
+	// Shared by the iNES and UNIF loaders once the image has been split up
+	fn assemble(mut header: Header, trainer: Option<Vec<u8>>, prg_data: Vec<u8>, mut chr_data: Vec<u8>, title: Option<String>, nrom_fallback: bool) -> Result<Cartridge, RomError>
+	{
+		// Every board maps PRG-ROM at $8000, there's nothing to run without it
+		if prg_data.is_empty()
+		{
+			return Err(RomError::NoPrgRom);
+		}
+
+		// Known images get their board information from the database instead of trusting the header
+		let game = database::lookup(&prg_data, &chr_data);
+		if let Some(game) = game
+		{
+			game.apply(&mut header);
+		}
+
+		// Without CHR-ROM the mapper works on CHR-RAM, fall back to 8K if a NES 2.0 header declares none
+		if header.chr_rom_size == 0
+		{
+			chr_data = match header.chr_ram_size + header.chr_nvram_size
+			{
+				0 => vec![0u8; 0x2000],
+				size => vec![0u8; size]
+			};
+		}
+
+		let mapper = mappers::create(&header, prg_data, chr_data, nrom_fallback).ok_or(RomError::UnsupportedMapper
+		{
+			mapper: header.mapper,
+			board: mappers::board_name(header.mapper)
+		})?;
+		let saved_data = mapper.battery_data().map(|data| data.to_vec()).unwrap_or_default();
+
+		Ok(Cartridge
+		{
 			header: header,
 
-			prg: prg_data, 
-			chr: chr_data
+			trainer,
+			mapper,
+			title: game.map(|game| game.title.to_string()).filter(|title| !title.is_empty()).or(title),
+			nsf: None,
+
+			save_path: None,
+			saved_data
+		})
+	}
+
+	// The RAM adapter takes the place of the cartridge, the disk sides take the place of the PRG and CHR
+	fn assemble_disk(data: &[u8], bios: &[u8]) -> Result<Cartridge, RomError>
+	{
+		if bios.len() != 0x2000
+		{
+			return Err(RomError::BadBios);
+		}
+
+		let sides = fds::split_sides(data);
+		if sides.is_empty()
+		{
+			return Err(RomError::Truncated);
+		}
+
+		let mut header = Header::new(20, 0, 0);
+		header.prg_ram_size = 0x8000;
+		header.chr_ram_size = 0x2000;
+
+		let mapper = Box::new(FDS::new(bios.to_vec(), sides));
+		let saved_data = mapper.battery_data().map(|data| data.to_vec()).unwrap_or_default();
+
+		Ok(Cartridge
+		{
+			header,
+
+			trainer: None,
+			mapper,
+			title: None,
+			nsf: None,
+
+			save_path: None,
+			saved_data
+		})
+	}
+
+	// NSF files only carry the tune's code and data, the player in NES drives them
+	fn assemble_nsf(data: &[u8]) -> Result<Cartridge, RomError>
+	{
+		let (info, mapper) = nsf::parse(data)?;
+
+		let mut header = Header::new(31, data.len(), 0);
+		header.prg_ram_size = 0x2000;
+		header.chr_ram_size = 0x2000;
+		header.timing = info.timing;
+
+		Ok(Cartridge
+		{
+			header,
+
+			trainer: None,
+			mapper: Box::new(mapper),
+			title: Some(info.title.clone()).filter(|title| !title.is_empty()),
+			nsf: Some(info),
+
+			save_path: None,
+			saved_data: Vec::new()
+		})
+	}
+
+	pub fn from_reader<R: Read>(mut reader: R) -> Result<Cartridge, RomError>
+	{
+		let mut data = Vec::new();
+		reader.read_to_end(&mut data)?;
+
+		Cartridge::from_bytes(&data)
+	}
+
+	pub fn from_file<P: AsRef<Path>>(filepath: P) -> Result<Cartridge, RomError>
+	{
+		Cartridge::load_file(filepath, &LoadOptions::default())
+	}
+
+	// Battery-backed memory is kept in a .sav file next to the ROM, disk images keep the changes made to the disk there
+	pub fn load_file<P: AsRef<Path>>(filepath: P, options: &LoadOptions) -> Result<Cartridge, RomError>
+	{
+		let filepath = filepath.as_ref();
+		let data = fs::read(filepath)?;
+
+		let mut cartridge = Cartridge::load(&data, options)?;
+
+		let save_path = filepath.with_extension("sav");
+		if cartridge.mapper.battery_data().is_some()
+		{
+			if let Ok(data) = fs::read(&save_path)
+			{
+				cartridge.mapper.load_battery_data(&data);
+				cartridge.saved_data = cartridge.mapper.battery_data().unwrap_or_default().to_vec();
+			}
 		}
+
+		cartridge.save_path = Some(save_path);
+		Ok(cartridge)
+	}
+
+	pub fn header(&self) -> &Header
+	{
+		&self.header
 	}
 
-	// TODO: For now all memio is hardcoded to work with nestest.nes for testing
+	// Only known if the image was found in the database or is a UNIF image with a name
+	pub fn title(&self) -> Option<&str>
+	{
+		self.title.as_deref()
+	}
 
-	pub fn read_prg(&self, addr: u16) -> u8 
+	// Only set if the image is an NSF or NSFe file rather than a cartridge
+	pub fn nsf(&self) -> Option<&NsfInfo>
 	{
-		self.prg[(addr & 0x3FFF) as usize]
+		self.nsf.as_ref()
 	}
 
-	pub fn write_prg(&mut self, addr: u16, val: u8)
+	pub fn read_cpu(&mut self, addr: u16) -> u8
 	{
-		// nothing
+		self.mapper.read_cpu(addr)
 	}
-}
\ No newline at end of file
+
+	pub fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		self.mapper.write_cpu(addr, val);
+	}
+
+	pub fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.mapper.read_ppu(addr)
+	}
+
+	pub fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		self.mapper.write_ppu(addr, val);
+	}
+
+	pub fn mirroring(&self) -> Mirroring
+	{
+		self.mapper.mirroring()
+	}
+
+	pub fn read_nametable(&mut self, addr: u16, vram: &[u8]) -> u8
+	{
+		self.mapper.read_nametable(addr, vram)
+	}
+
+	pub fn write_nametable(&mut self, addr: u16, val: u8, vram: &mut [u8])
+	{
+		self.mapper.write_nametable(addr, val, vram);
+	}
+
+	pub fn irq(&self) -> bool
+	{
+		self.mapper.irq()
+	}
+
+	pub fn ppu_address(&mut self, addr: u16)
+	{
+		self.mapper.ppu_address(addr);
+	}
+
+	pub fn cpu_clock(&mut self)
+	{
+		self.mapper.cpu_clock();
+	}
+
+	pub fn audio_output(&self) -> f32
+	{
+		self.mapper.audio_output()
+	}
+
+	pub fn disk_sides(&self) -> usize
+	{
+		self.mapper.disk_sides()
+	}
+
+	pub fn insert_disk(&mut self, side: Option<usize>)
+	{
+		self.mapper.insert_disk(side);
+	}
+
+	pub fn current_disk(&self) -> Option<usize>
+	{
+		self.mapper.current_disk()
+	}
+
+	// Writes the battery-backed memory to the save file, but only if it changed since the last write
+	pub fn save(&mut self)
+	{
+		let (path, data) = match (&self.save_path, self.mapper.battery_data())
+		{
+			(Some(path), Some(data)) if data != self.saved_data.as_slice() => (path, data),
+			_ => return
+		};
+
+		match fs::write(path, data)
+		{
+			Ok(_) => self.saved_data = data.to_vec(),
+			Err(err) => eprintln!("Failed to write save file {}: {}", path.display(), err)
+		}
+	}
+}
+
+impl Drop for Cartridge
+{
+	fn drop(&mut self)
+	{
+		self.save();
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn ines(bytes: &[u8]) -> [u8; 16]
+	{
+		let mut data = [0u8; 16];
+		data[..4].copy_from_slice(b"NES\x1A");
+		data[4..4 + bytes.len()].copy_from_slice(bytes);
+		data
+	}
+
+	#[test]
+	fn parses_ines_headers()
+	{
+		let header = Header::parse(&ines(&[2, 1, 0x43, 0x10]));
+
+		assert!(!header.nes2);
+		assert_eq!((header.prg_rom_size, header.chr_rom_size), (0x8000, 0x2000));
+		assert_eq!(header.mapper, 0x14);
+		assert_eq!(header.mirroring, Mirroring::Vertical);
+		assert!(header.battery);
+		assert_eq!((header.prg_ram_size, header.prg_nvram_size), (0, 0x2000));
+		assert_eq!(header.chr_ram_size, 0);
+	}
+
+	#[test]
+	fn parses_nes2_headers()
+	{
+		let header = Header::parse(&ines(&[0x02, 0x00, 0x08, 0x08, 0x31, 0x21, 0x70, 0x07, 0x01]));
+
+		assert!(header.nes2);
+		assert_eq!((header.prg_blocks, header.prg_rom_size), (0x102, 0x102 * 0x4000));
+		assert_eq!((header.chr_blocks, header.chr_rom_size), (0x200, 0x200 * 0x2000));
+		assert_eq!(header.mapper, 0x100);
+		assert_eq!(header.submapper, 3);
+		assert_eq!(header.mirroring, Mirroring::FourScreen);
+		assert_eq!((header.prg_ram_size, header.prg_nvram_size), (0, 0x2000));
+		assert_eq!((header.chr_ram_size, header.chr_nvram_size), (0x2000, 0));
+		assert_eq!(header.timing, Timing::Pal);
+	}
+
+	#[test]
+	fn parses_nes2_exponent_sizes()
+	{
+		// 2^10 * 3 bytes of PRG-ROM and 2^13 * 1 bytes of CHR-ROM
+		let header = Header::parse(&ines(&[(10 << 2) | 1, 13 << 2, 0x00, 0x08, 0x00, 0xFF]));
+		assert_eq!(header.prg_rom_size, 3072);
+		assert_eq!((header.chr_blocks, header.chr_rom_size), (1, 0x2000));
+
+		// Sizes that don't fit into a usize can't be in any image
+		let header = Header::parse(&ines(&[(63 << 2) | 3, 0, 0x00, 0x08, 0x00, 0x0F]));
+		assert_eq!(header.prg_rom_size, usize::MAX);
+	}
+
+	#[test]
+	fn rejects_oversized_nes2_images()
+	{
+		let mut data = ines(&[(63 << 2) | 3, 0, 0x00, 0x08, 0x00, 0x0F]).to_vec();
+		data.resize(0x10 + 0x4000, 0);
+
+		assert!(matches!(Cartridge::from_bytes(&data), Err(RomError::Truncated)));
+	}
+
+	#[test]
+	fn ignores_diskdude_signatures()
+	{
+		let mut data = ines(&[2, 1, 0x01]);
+		data[7..16].copy_from_slice(b"DiskDude!");
+
+		let header = Header::parse(&data);
+		assert!(!header.nes2);
+		assert_eq!(header.mapper, 0);
+		assert_eq!(header.mirroring, Mirroring::Vertical);
+		assert_eq!(header.prg_nvram_size + header.prg_ram_size, 0x2000);
+		assert_eq!(header.timing, Timing::Ntsc);
+	}
+}
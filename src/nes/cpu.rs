@@ -2,7 +2,8 @@ use std::cell::RefCell;
 use std::rc::{Rc, Weak};
 
 use crate::nes::bus::Bus;
-use crate::nes::instructions::INSTRUCTION_SET;
+use crate::nes::addressing::Addressing;
+use crate::nes::instructions::{Access, Instruction, INSTRUCTION_SET};
 
 pub enum FetchType
 {
@@ -10,15 +11,36 @@ pub enum FetchType
 	Mem
 }
 
+// The interrupt sequences share their timing with BRK, reset turns the stack writes into reads
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Interrupt
+{
+	Break,
+	Hardware,
+	Reset
+}
+
 pub struct CPU
 {
-	cycle: u8,
-	pub additional_cycles: u8,
+	// Cycle within the current instruction, 0 fetches the next opcode
+	step: u8,
+	done: bool,
 	total_cycles: u64,
 
+	pub instr: Instruction,
+	pub interrupt: Option<Interrupt>,
+
+	// The last opcode fetched, and the address of the JAM opcode that stopped the CPU
+	opcode: u8,
+	jammed: Option<u16>,
+
 	pub absolute_addr: u16,
+	pub base_addr: u16,
 	pub relative_addr: i8,
+	pub pointer: u8,
+	pub data: u8,
 	pub fetch_type: FetchType,
+	pub branch_taken: bool,
 
 	pub acc: u8,
 	pub x: u8,
@@ -27,21 +49,50 @@ pub struct CPU
 	pub sp: u8,
 	pub pc: u16,
 
+	// Interrupts are polled at the end of every cycle, the decision to take one at the end of an
+	// instruction uses what was seen before its last cycle. NMI is edge-triggered, so the level
+	// seen last cycle is kept to detect the rising edge
+	nmi_line: bool,
+	pub need_nmi: bool,
+	prev_need_nmi: bool,
+	run_irq: bool,
+	prev_run_irq: bool,
+	pub hold_poll: bool,
+
+	// Replaces the reset vector, for nestest's automation mode
+	entry: Option<u16>,
+
+	// The bits of A that survive XAA and LAX #imm, $EE on most consoles but $FF and $00 are also seen
+	pub magic: u8,
+
+	// Prints every instruction in nestest's log format, far too slow to leave on for games
+	pub trace: bool,
+
 	pub bus: Weak<RefCell<Bus>>,
 }
 
-impl CPU 
+impl CPU
 {
-	pub fn new(bus: &Rc<RefCell<Bus>>) -> CPU 
+	pub fn new(bus: &Rc<RefCell<Bus>>) -> CPU
 	{
 		CPU {
-			cycle: 0,
-			additional_cycles: 0,
+			step: 0,
+			done: false,
 			total_cycles: 0,
 
+			instr: INSTRUCTION_SET[0xEA],
+			interrupt: None,
+
+			opcode: 0xEA,
+			jammed: None,
+
 			absolute_addr: 0,
+			base_addr: 0,
 			relative_addr: 0,
+			pointer: 0,
+			data: 0,
 			fetch_type: FetchType::Mem,
+			branch_taken: false,
 
 			acc: 0,
 			x: 0,
@@ -51,10 +102,23 @@ impl CPU
 
 			pc: 0,
 
+			nmi_line: false,
+			need_nmi: false,
+			prev_need_nmi: false,
+			run_irq: false,
+			prev_run_irq: false,
+			hold_poll: false,
+
+			entry: None,
+
+			magic: 0xEE,
+			trace: false,
+
 			bus: Rc::downgrade(bus)
 		}
 	}
 
+	// Power-on runs the reset sequence with the stack pointer at 0, which leaves it at $FD
 	pub fn powerup(&mut self)
 	{
 		self.p = 0x34;
@@ -62,66 +126,629 @@ impl CPU
 		self.acc = 0;
 		self.x = 0;
 		self.y = 0;
-		self.sp = 0xFD;
+		self.sp = 0x00;
 
 		self.total_cycles = 0;
-		self.cycle = 6;
+		self.entry = None;
+		self.reset();
+	}
 
-		// TODO: This is just for the nestest.nes
-		self.pc = 0xC000;
+	pub fn set_entry(&mut self, entry: u16)
+	{
+		self.entry = Some(entry);
 	}
 
+	// The reset line runs the interrupt sequence with the stack writes turned into reads,
+	// so the stack pointer moves but memory and the other registers are left alone
+	pub fn reset(&mut self)
+	{
+		self.nmi_line = false;
+		self.need_nmi = false;
+		self.prev_need_nmi = false;
+		self.run_irq = false;
+		self.prev_run_irq = false;
+
+		self.interrupt = Some(Interrupt::Reset);
+		self.jammed = None;
+		self.step = 0;
+	}
+
+	// Runs a single CPU cycle, returns true if it fetched an opcode
 	pub fn cycle(&mut self) -> bool
 	{
 		self.total_cycles += 1;
 
-		if self.cycle > 0
+		// A jammed CPU ignores interrupts too, the rest of the console keeps running
+		if self.jammed.is_some()
 		{
-			self.cycle -= 1;
 			return false;
 		}
 
-		self.execute();
+		let fetched = match (self.step, self.interrupt)
+		{
+			(0, None) 	=> self.begin(),
+			_ 			=> {
+				self.advance();
+				false
+			}
+		};
 
-		self.cycle -= 1;
-		true
+		self.poll_interrupts();
+		fetched
 	}
 
+	// A jammed CPU counts as being between instructions, so stepping it doesn't hang
 	pub fn sync(&self) -> bool
 	{
-		self.cycle == 0
+		self.step == 0 && self.interrupt.is_none()
+	}
+
+	pub fn read(&mut self, addr: u16) -> u8
+	{
+		self.bus.upgrade().unwrap().borrow().read_cpu(addr)
+	}
+
+	pub fn write(&mut self, addr: u16, val: u8)
+	{
+		self.bus.upgrade().unwrap().borrow_mut().write_cpu(addr, val);
+	}
+
+	pub fn push(&mut self, val: u8)
+	{
+		self.write(0x0100 + self.sp as u16, val);
+		self.sp = self.sp.wrapping_sub(1);
+	}
+
+	// Reads the top of the stack without pulling it
+	pub fn peek_stack(&mut self) -> u8
+	{
+		self.read(0x0100 + self.sp as u16)
+	}
+
+	// Ends the current instruction, the next cycle fetches an opcode
+	pub fn finish(&mut self)
+	{
+		self.done = true;
+	}
+
+	// Drops the instruction or interrupt in progress, the next cycle fetches an opcode at PC
+	pub fn abort(&mut self)
+	{
+		self.step = 0;
+		self.done = false;
+		self.interrupt = None;
+		self.jammed = None;
+	}
+
+	// Stops fetching opcodes until the next reset
+	pub fn halt(&mut self)
+	{
+		self.jammed = Some(self.pc.wrapping_sub(1));
+		self.finish();
+	}
+
+	// The address and opcode of the JAM instruction, if the CPU is stuck on one
+	pub fn jammed(&self) -> Option<(u16, u8)>
+	{
+		self.jammed.map(|pc| (pc, self.opcode))
+	}
+
+	pub fn step(&self) -> u8
+	{
+		self.step
 	}
 
-	fn execute(&mut self)
+	// A pending interrupt replaces the opcode fetch, the opcode is read but thrown away
+	fn begin(&mut self) -> bool
+	{
+		if self.prev_need_nmi || self.prev_run_irq
+		{
+			self.read(self.pc);
+			self.interrupt = Some(Interrupt::Hardware);
+			self.step = 1;
+
+			return false;
+		}
+
+		self.opcode = self.read(self.pc);
+		self.instr = INSTRUCTION_SET[self.opcode as usize];
+
+		if self.trace
+		{
+			self.print_trace();
+		}
+
+		self.pc = self.pc.wrapping_add(1);
+		self.step = 1;
+		true
+	}
+
+	fn advance(&mut self)
+	{
+		match self.interrupt
+		{
+			Some(interrupt) => self.interrupt_sequence(interrupt),
+			None 			=> match self.instr.access
+			{
+				// Control flow and stack instructions have their own timing
+				Access::Sequence 	=> (self.instr.action)(self),
+				_ 					=> self.addressing()
+			}
+		}
+
+		match self.done
+		{
+			true => {
+				self.done = false;
+				self.step = 0;
+			},
+
+			false => self.step += 1
+		}
+	}
+
+	// Steps 0 to 6 of reset and 1 to 6 of BRK, IRQ and NMI. An NMI that shows up before the vector is
+	// fetched takes over the sequence, the pushed status still tells the handler whether it was a BRK
+	pub fn interrupt_sequence(&mut self, interrupt: Interrupt)
+	{
+		match self.step
+		{
+			0 => {
+				self.read(self.pc);
+			},
+
+			1 => {
+				self.read(self.pc);
+				if interrupt == Interrupt::Break
+				{
+					self.pc = self.pc.wrapping_add(1);
+				}
+			},
+
+			2..=4 if interrupt == Interrupt::Reset => {
+				self.peek_stack();
+				self.sp = self.sp.wrapping_sub(1);
+			},
+
+			2 => self.push((self.pc >> 8) as u8),
+			3 => self.push(self.pc as u8),
+
+			4 => {
+				let status = match interrupt
+				{
+					Interrupt::Break 	=> self.p | 0x30,
+					_ 					=> (self.p & !0x10) | 0x20
+				};
+
+				self.push(status);
+			},
+
+			5 => {
+				self.p |= 0x04;
+
+				self.absolute_addr = match interrupt
+				{
+					Interrupt::Reset 			=> 0xFFFC,
+					_ if self.need_nmi 			=> 0xFFFA,
+					_ 							=> 0xFFFE
+				};
+
+				if self.absolute_addr == 0xFFFA
+				{
+					self.need_nmi = false;
+				}
+
+				self.data = self.read(self.absolute_addr);
+			},
+
+			_ => {
+				let hi = self.read(self.absolute_addr + 1) as u16;
+				self.pc = (hi << 8) | self.data as u16;
+
+				if interrupt == Interrupt::Reset
+				{
+					if let Some(entry) = self.entry.take()
+					{
+						self.pc = entry;
+					}
+				}
+
+				self.interrupt = None;
+				self.finish();
+			}
+		}
+	}
+
+	fn poll_interrupts(&mut self)
 	{
 		let bus = self.bus.upgrade().unwrap();
-		let opcode: u8 = bus.borrow().read_cpu(self.pc);
-		let instr = INSTRUCTION_SET[opcode as usize].expect(&format!("Unimplemented opcode {:02X}", opcode));
+
+		// Taken branches that stay on the same page don't poll on their last cycle
+		if !std::mem::take(&mut self.hold_poll)
+		{
+			self.prev_need_nmi = self.need_nmi;
+			self.prev_run_irq = self.run_irq;
+		}
+
+		let nmi = bus.borrow().nmi();
+		if nmi && !self.nmi_line
+		{
+			self.need_nmi = true;
+		}
+
+		self.nmi_line = nmi;
+		self.run_irq = bus.borrow().irq() && (self.p & 0x04) == 0;
+	}
+
+	// Prints the instruction about to run with the registers before it, in nestest's log format.
+	// Operands are peeked so the trace doesn't trigger any side effects
+	fn print_trace(&self)
+	{
+		let bus = self.bus.upgrade().unwrap();
+		let bus = bus.borrow();
 
 		print!("{:04X}  ", self.pc);
 		for byte in 0..3
 		{
-			if byte < instr.length 
+			match byte < self.instr.length
 			{
-				print!("{:02X} ", bus.borrow().read_cpu(self.pc + byte as u16));
+				true 	=> print!("{:02X} ", bus.peek_cpu(self.pc.wrapping_add(byte as u16))),
+				false 	=> print!("   ")
 			}
-			else
+		}
+
+		print!(" {} ", self.instr.name.to_string().to_uppercase());
+		print!("{: <40}", self.instr.addressing.format(&bus, self.pc.wrapping_add(1), self.x, self.y));
+		print!("A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{} ", self.acc, self.x, self.y, self.p, self.sp, self.total_cycles - 1);
+	}
+
+	pub fn addressing(&mut self)
+	{
+		match self.instr.addressing
+		{
+			Addressing::Acc | Addressing::Imp 	=> self.imp(),
+			Addressing::Imm 					=> self.imm(),
+			Addressing::Zpg 					=> self.zpg(),
+			Addressing::Zpx 					=> self.zpx(),
+			Addressing::Zpy 					=> self.zpy(),
+			Addressing::Abs 					=> self.abs(),
+			Addressing::Abx 					=> self.abx(),
+			Addressing::Aby 					=> self.aby(),
+			Addressing::Ind 					=> self.ind(),
+			Addressing::Idx 					=> self.idx(),
+			Addressing::Idy 					=> self.idy(),
+			Addressing::Rel 					=> self.rel()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::nes::apu::APU;
+	use crate::nes::cartridge::Cartridge;
+	use crate::nes::nes::{NES, State};
+	use crate::nes::ppu::PPU;
+
+	const NMI: u16 = 0xE000;
+	const RESET: u16 = 0xC000;
+	const IRQ: u16 = 0xF000;
+
+	// A 16K NROM image with each piece of code at its address in $C000-$FFFF
+	fn nrom(code: &[(u16, &[u8])]) -> Cartridge
+	{
+		let mut prg = vec![0xEA; 0x4000];
+		for (addr, bytes) in code
+		{
+			let offset = (addr - 0xC000) as usize;
+			prg[offset..offset + bytes.len()].copy_from_slice(bytes);
+		}
+
+		for (offset, addr) in [(0x3FFA, NMI), (0x3FFC, RESET), (0x3FFE, IRQ)]
+		{
+			prg[offset..offset + 2].copy_from_slice(&addr.to_le_bytes());
+		}
+
+		let mut data = b"NES\x1A\x01\x01".to_vec();
+		data.resize(16, 0);
+		data.extend_from_slice(&prg);
+		data.resize(16 + 0x4000 + 0x2000, 0);
+
+		Cartridge::from_bytes(&data).unwrap()
+	}
+
+	// Wired up like NES::new, but with the CPU and RAM in reach
+	struct Console
+	{
+		bus: Rc<RefCell<Bus>>,
+		cpu: Rc<RefCell<CPU>>,
+		ppu: Rc<RefCell<PPU>>,
+		apu: Rc<RefCell<APU>>
+	}
+
+	impl Console
+	{
+		fn new(cartridge: Cartridge) -> Console
+		{
+			let bus = Rc::new(RefCell::new(Bus::new(cartridge)));
+			let cpu = Rc::new(RefCell::new(CPU::new(&bus)));
+			let ppu = Rc::new(RefCell::new(PPU::new(&bus)));
+			let apu = Rc::new(RefCell::new(APU::new(&bus)));
+
+			bus.borrow_mut().attach_cpu(&cpu);
+			bus.borrow_mut().attach_ppu(&ppu);
+			bus.borrow_mut().attach_apu(&apu);
+
+			cpu.borrow_mut().powerup();
+			Console { bus, cpu, ppu, apu }
+		}
+
+		fn clock(&self)
+		{
+			self.cpu.borrow_mut().cycle();
+			self.apu.borrow_mut().clock();
+
+			let mut ppu = self.ppu.borrow_mut();
+			ppu.dot();
+			ppu.dot();
+			ppu.dot();
+		}
+
+		// Runs until the CPU is about to fetch the opcode at addr
+		fn run_to(&self, addr: u16)
+		{
+			for _ in 0..100_000
 			{
-				print!("   ");	
+				self.clock();
+
+				let cpu = self.cpu.borrow();
+				if cpu.sync() && cpu.pc == addr
+				{
+					return;
+				}
 			}
+
+			panic!("Never reached ${:04X}", addr);
+		}
+
+		fn ram(&self, addr: u16) -> u8
+		{
+			self.bus.borrow().peek_cpu(addr)
+		}
+	}
+
+	// Runs the code at the reset vector up to the loop added at its end
+	fn run(code: &[u8]) -> Console
+	{
+		let end = RESET + code.len() as u16;
+
+		let mut code = code.to_vec();
+		code.push(0x4C);
+		code.extend_from_slice(&end.to_le_bytes());
+
+		let console = Console::new(nrom(&[(RESET, &code)]));
+		console.run_to(end);
+		console
+	}
+
+	#[test]
+	fn brk_pushes_the_break_flag()
+	{
+		// BRK skips the byte after it
+		let console = Console::new(nrom(&[(RESET, &[0x00, 0xFF])]));
+		console.run_to(IRQ);
+
+		assert_eq!(console.cpu.borrow().sp, 0xFA);
+		assert_eq!(console.cpu.borrow().p & 0x04, 0x04);
+		assert_eq!((console.ram(0x01FD), console.ram(0x01FC)), (0xC0, 0x02));
+		assert_eq!(console.ram(0x01FB) & 0x30, 0x30);
+	}
+
+	#[test]
+	fn nmi_is_edge_triggered()
+	{
+		// Turns on NMI at vblank, the handler counts how often it runs without reading $2002
+		let console = Console::new(nrom(&[
+			(RESET, &[0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0xC0]),
+			(NMI, &[0xE6, 0x00, 0x40])
+		]));
+
+		console.run_to(NMI);
+		assert_eq!(console.ram(0x01FB) & 0x30, 0x20);
+
+		// The NMI line stays up for the rest of vblank
+		console.run_to(0xC005);
+		for _ in 0..2000
+		{
+			console.clock();
 		}
 
-		print!(" {} ", instr.name.to_string().to_uppercase());
+		assert_eq!(console.ram(0x00), 1);
+	}
 
-		self.pc += 1;
+	#[test]
+	fn irq_waits_for_the_i_flag()
+	{
+		// The APU's frame counter raises an IRQ, the handler acknowledges it
+		let handler: &[u8] = &[0xE6, 0x00, 0xAD, 0x15, 0x40, 0x40];
+		let console = Console::new(nrom(&[(RESET, &[0x4C, 0x00, 0xC0]), (IRQ, handler)]));
 
-		(instr.addressing)(self);
-		(instr.action)(self);
+		for _ in 0..40_000
+		{
+			console.clock();
+		}
+
+		assert!(console.apu.borrow().irq());
+		assert_eq!(console.ram(0x00), 0);
 
-		self.cycle += instr.cycles + self.additional_cycles;
-		self.additional_cycles = 0;
+		let console = Console::new(nrom(&[(RESET, &[0x58, 0x4C, 0x01, 0xC0]), (IRQ, handler)]));
+		console.run_to(IRQ);
 
-		print!("A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{} ", self.acc, self.x, self.y, self.p, self.sp, self.total_cycles);
+		assert_eq!(console.ram(0x01FB) & 0x30, 0x20);
+		assert_eq!(console.cpu.borrow().p & 0x04, 0x04);
 	}
 
+	#[test]
+	fn nmi_hijacks_brk()
+	{
+		let console = Console::new(nrom(&[(RESET, &[0x00, 0xFF])]));
+		console.run_to(RESET);
+
+		// Shows up while BRK pushes the return address
+		console.clock();
+		console.clock();
+		console.clock();
+		console.cpu.borrow_mut().need_nmi = true;
+
+		console.run_to(NMI);
+		assert_eq!(console.ram(0x01FB) & 0x30, 0x30);
+	}
+
+	#[test]
+	fn powerup_runs_the_reset_sequence()
+	{
+		let console = Console::new(nrom(&[]));
+		console.run_to(RESET);
+
+		let cpu = console.cpu.borrow();
+		assert_eq!((cpu.sp, cpu.p), (0xFD, 0x34));
+		assert_eq!(cpu.total_cycles, 7);
+	}
+
+	#[test]
+	fn reset_keeps_ram_and_registers()
+	{
+		// LDA #$42, STA $10, CLI
+		let console = run(&[0xA9, 0x42, 0x85, 0x10, 0x58]);
+
+		console.cpu.borrow_mut().reset();
+		console.run_to(RESET);
+
+		let cpu = console.cpu.borrow();
+		assert_eq!(console.ram(0x10), 0x42);
+		assert_eq!((cpu.acc, cpu.sp), (0x42, 0xFA));
+		assert_eq!(cpu.p & 0x04, 0x04);
+	}
+
+	#[test]
+	fn passes_nestest()
+	{
+		let cartridge = Cartridge::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/roms/nestest.nes")).unwrap();
+		let console = Console::new(cartridge);
+		console.cpu.borrow_mut().set_entry(0xC000);
+
+		// The automation mode returns from $C66E with the error codes in $02 and $03
+		console.run_to(0xC66E);
+
+		assert_eq!((console.ram(0x02), console.ram(0x03)), (0, 0));
+		assert_eq!(console.cpu.borrow().total_cycles, 26554);
+	}
+
+	#[test]
+	fn page_crossing_reads_hit_the_wrong_page_first()
+	{
+		let console = run(&[
+			// Writes $41 to $2000 and points the PPU back at it
+			0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+			0xA9, 0x41, 0x8D, 0x07, 0x20,
+			0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20,
+
+			// LDA $20FF,X reads $2007 before $2107, so the buffered $41 comes back
+			0xA2, 0x08, 0xBD, 0xFF, 0x20, 0x85, 0x10
+		]);
+
+		assert_eq!(console.ram(0x10), 0x41);
+	}
+
+	#[test]
+	fn read_modify_write_writes_twice()
+	{
+		let console = run(&[
+			// INC $2007 at $2001 writes the old value to $2002 and the new one to $2003
+			0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x01, 0x8D, 0x06, 0x20,
+			0xEE, 0x07, 0x20,
+
+			// Reads $2002 and $2003 back into $10 and $11
+			0xA9, 0x20, 0x8D, 0x06, 0x20, 0xA9, 0x02, 0x8D, 0x06, 0x20,
+			0xAD, 0x07, 0x20,
+			0xAD, 0x07, 0x20, 0x85, 0x10,
+			0xAD, 0x07, 0x20, 0x85, 0x11
+		]);
+
+		assert_eq!(console.ram(0x11), console.ram(0x10).wrapping_add(1));
+	}
+
+	#[test]
+	fn anc_copies_bit_7_into_carry()
+	{
+		// LDA #$FF, ANC #$80
+		let console = run(&[0xA9, 0xFF, 0x0B, 0x80]);
+
+		let cpu = console.cpu.borrow();
+		assert_eq!(cpu.acc, 0x80);
+		assert_eq!(cpu.p & 0x81, 0x81);
+	}
+
+	#[test]
+	fn arr_sets_carry_and_overflow_from_the_result()
+	{
+		// LDA #$FF, CLC, ARR #$80
+		let console = run(&[0xA9, 0xFF, 0x18, 0x6B, 0x80]);
+
+		let cpu = console.cpu.borrow();
+		assert_eq!(cpu.acc, 0x40);
+		assert_eq!(cpu.p & 0xC3, 0x41);
+	}
+
+	#[test]
+	fn axs_subtracts_from_a_and_x()
+	{
+		// LDA #$F0, LDX #$3C, AXS #$10
+		let console = run(&[0xA9, 0xF0, 0xA2, 0x3C, 0xCB, 0x10]);
+		assert_eq!(console.cpu.borrow().x, 0x20);
+		assert_eq!(console.cpu.borrow().p & 0x01, 0x01);
+
+		// Borrows like CMP, without the carry going in
+		let console = run(&[0xA9, 0xF0, 0xA2, 0x3C, 0xCB, 0x40]);
+		assert_eq!(console.cpu.borrow().x, 0xF0);
+		assert_eq!(console.cpu.borrow().p & 0x01, 0x00);
+	}
+
+	#[test]
+	fn unstable_opcodes_use_the_magic_constant()
+	{
+		// LDA #$01, LAX #$FF, LDX #$0F, XAA #$FF
+		let code: &[u8] = &[0xA9, 0x01, 0xAB, 0xFF, 0xA9, 0x01, 0xA2, 0x0F, 0x8B, 0xFF, 0x4C, 0x0A, 0xC0];
+
+		for (magic, lax, xaa) in [(0xEE, 0xEF, 0x0F), (0xFF, 0xFF, 0x0F), (0x00, 0x01, 0x01)]
+		{
+			let console = Console::new(nrom(&[(RESET, code)]));
+			console.cpu.borrow_mut().magic = magic;
+
+			console.run_to(0xC004);
+			assert_eq!(console.cpu.borrow().x, lax);
+
+			console.run_to(0xC00A);
+			assert_eq!(console.cpu.borrow().acc, xaa);
+		}
+	}
+
+	#[test]
+	fn jams_until_reset()
+	{
+		let nes = NES::new(nrom(&[(RESET, &[0xEA, 0x02])]));
+		nes.powerup();
+
+		for _ in 0..4
+		{
+			nes.single_step();
+		}
+
+		assert_eq!(nes.state(), State::Jammed { pc: 0xC001, opcode: 0x02 });
+
+		nes.reset();
+		nes.single_step();
+		assert_eq!(nes.state(), State::Running);
+	}
 }
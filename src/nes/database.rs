@@ -0,0 +1,241 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use sha1::{Digest, Sha1};
+
+use crate::nes::cartridge::{Header, Mirroring, Timing};
+
+// Known-good board information for ROM images, identified by the CRC32 and SHA-1 of their PRG and CHR data.
+// The header is left out of the checksums since that is exactly the part that tends to be wrong
+pub struct GameInfo
+{
+	pub crc32: u32,
+	pub sha1: Option<[u8; 20]>,
+	pub title: String,
+
+	pub mapper: u16,
+	pub submapper: u8,
+	pub mirroring: Mirroring,
+	pub battery: bool,
+	pub timing: Timing
+}
+
+// The built-in database is always available, a loaded one is searched first
+static BUILT_IN: OnceLock<Vec<GameInfo>> = OnceLock::new();
+static LOADED: OnceLock<Vec<GameInfo>> = OnceLock::new();
+
+// Entries are found by their CRC32, the SHA-1 is only worked out to confirm a match for entries that have one
+pub fn lookup(prg: &[u8], chr: &[u8]) -> Option<&'static GameInfo>
+{
+	let built_in = BUILT_IN.get_or_init(|| parse(include_str!("database.xml")));
+	find(LOADED.get().into_iter().flatten().chain(built_in), prg, chr)
+}
+
+fn find<'a>(mut games: impl Iterator<Item = &'a GameInfo>, prg: &[u8], chr: &[u8]) -> Option<&'a GameInfo>
+{
+	let mut hasher = crc32fast::Hasher::new();
+	hasher.update(prg);
+	hasher.update(chr);
+
+	let crc32 = hasher.finalize();
+	let sha1 = OnceLock::new();
+
+	games.find(|game| {
+		game.crc32 == crc32 && game.sha1.is_none_or(|expected| expected == *sha1.get_or_init(|| -> [u8; 20] {
+			Sha1::new().chain_update(prg).chain_update(chr).finalize().into()
+		}))
+	})
+}
+
+// Reads an nes20db XML file (https://forums.nesdev.org/viewtopic.php?t=19940) and returns how many games it
+// knows. Only the first database loaded is used, it has to be loaded before any cartridge to have an effect
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<usize>
+{
+	let games = parse(&fs::read_to_string(path)?);
+	let count = games.len();
+
+	LOADED.set(games).map_err(|_| io::Error::new(io::ErrorKind::AlreadyExists, "a game database is already loaded"))?;
+	Ok(count)
+}
+
+// Entries without a ROM checksum or a PCB are skipped, everything else falls back to a plain NTSC console
+fn parse(xml: &str) -> Vec<GameInfo>
+{
+	xml.split("<game>").skip(1).filter_map(|game| parse_game(game.split("</game>").next().unwrap_or(game))).collect()
+}
+
+fn parse_game(game: &str) -> Option<GameInfo>
+{
+	let crc32 = u32::from_str_radix(attribute(game, "rom", "crc32")?, 16).ok()?;
+	let sha1 = attribute(game, "rom", "sha1").and_then(parse_sha1);
+	let mapper = attribute(game, "pcb", "mapper")?.parse().ok()?;
+
+	// The comment in front of the entry is the file name in the No-Intro set
+	let title = game.split_once("<!--")
+		.and_then(|(_, comment)| comment.split_once("-->"))
+		.map(|(name, _)| name.trim().rsplit(['\\', '/']).next().unwrap_or_default())
+		.map(|name| name.strip_suffix(".nes").unwrap_or(name))
+		.unwrap_or_default();
+
+	let mirroring = match attribute(game, "pcb", "mirroring")
+	{
+		Some("V") 	=> Mirroring::Vertical,
+		Some("4") 	=> Mirroring::FourScreen,
+		_ 			=> Mirroring::Horizontal
+	};
+
+	let timing = match attribute(game, "console", "region")
+	{
+		Some("1") 	=> Timing::Pal,
+		Some("2") 	=> Timing::MultiRegion,
+		Some("3") 	=> Timing::Dendy,
+		_ 			=> Timing::Ntsc
+	};
+
+	Some(GameInfo
+	{
+		crc32,
+		sha1,
+		title: title.to_string(),
+
+		mapper,
+		submapper: attribute(game, "pcb", "submapper").and_then(|submapper| submapper.parse().ok()).unwrap_or(0),
+		mirroring,
+		battery: attribute(game, "pcb", "battery") == Some("1"),
+		timing
+	})
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]>
+{
+	let mut sha1 = [0u8; 20];
+	for (i, byte) in sha1.iter_mut().enumerate()
+	{
+		*byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+	}
+
+	match hex.len()
+	{
+		40 => Some(sha1),
+		_ => None
+	}
+}
+
+// Value of an attribute on the first element with that name, the database never escapes anything in them
+fn attribute<'a>(game: &'a str, element: &str, name: &str) -> Option<&'a str>
+{
+	let start = game.find(&format!("<{} ", element))?;
+	let tag = game[start..].split('>').next()?;
+
+	let value = tag.split_once(&format!(" {}=\"", name))?.1;
+	value.split('"').next()
+}
+
+impl GameInfo
+{
+	pub fn apply(&self, header: &mut Header)
+	{
+		header.mapper = self.mapper;
+		header.submapper = self.submapper;
+		header.mirroring = self.mirroring;
+		header.four_screen = self.mirroring == Mirroring::FourScreen;
+		header.timing = self.timing;
+
+		// The RAM size stays as declared, only whether it's backed by a battery changes
+		if self.battery != header.battery
+		{
+			let size = header.prg_ram_size + header.prg_nvram_size;
+			(header.prg_ram_size, header.prg_nvram_size) = match self.battery
+			{
+				true 	=> (0, size),
+				false 	=> (size, 0)
+			};
+		}
+
+		header.battery = self.battery;
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<database>
+<game>
+	<!-- Games\Homebrew\Test Game (USA).nes -->
+	<prgrom size="16384" crc32="7C5060F0" sha1="90F98EE5BE2562533946D3F88268E6DDBC64B82C" sum16="0000"/>
+	<chrrom size="8192" crc32="6DD12DF7" sha1="670F1B8F00CDCF77AD693F4A10D11C1EBFF03CC8" sum16="0000"/>
+	<rom size="24576" crc32="158B0388" sha1="0123456789ABCDEF0123456789ABCDEF01234567"/>
+	<prgram size="8192"/>
+	<pcb mapper="4" submapper="0" mirroring="H" battery="1"/>
+	<console type="0" region="0"/>
+</game>
+<game>
+	<!-- Games\Unlicensed\Some Game (Europe).nes -->
+	<rom size="65536" crc32="0000abcd"/>
+	<pcb mapper="71" submapper="1" mirroring="4" battery="0"/>
+	<console type="0" region="1"/>
+</game>
+<game>
+	<prgrom size="32768" crc32="12345678"/>
+	<pcb mapper="0" submapper="0" mirroring="V" battery="0"/>
+</game>
+</database>
+"#;
+
+	fn nestest() -> (Vec<u8>, Vec<u8>)
+	{
+		let data = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/roms/nestest.nes")).unwrap();
+		(data[0x10..0x4010].to_vec(), data[0x4010..0x6010].to_vec())
+	}
+
+	#[test]
+	fn parses_nes20db_entries()
+	{
+		let games = parse(XML);
+		assert_eq!(games.len(), 2);
+
+		let game = &games[0];
+		assert_eq!(game.crc32, 0x158B0388);
+		assert_eq!(game.sha1.unwrap()[..4], [0x01, 0x23, 0x45, 0x67]);
+		assert_eq!(game.title, "Test Game (USA)");
+		assert_eq!((game.mapper, game.submapper), (4, 0));
+		assert_eq!(game.mirroring, Mirroring::Horizontal);
+		assert!(game.battery);
+		assert_eq!(game.timing, Timing::Ntsc);
+
+		let other = &games[1];
+		assert_eq!(other.crc32, 0x0000ABCD);
+		assert!(other.sha1.is_none());
+		assert_eq!((other.mapper, other.submapper), (71, 1));
+		assert_eq!(other.mirroring, Mirroring::FourScreen);
+		assert!(!other.battery);
+		assert_eq!(other.timing, Timing::Pal);
+	}
+
+	#[test]
+	fn finds_built_in_games()
+	{
+		let (prg, chr) = nestest();
+		let game = lookup(&prg, &chr).unwrap();
+		assert_eq!(game.title, "nestest");
+		assert_eq!(game.mapper, 0);
+	}
+
+	#[test]
+	fn needs_the_sha1_to_match_too()
+	{
+		// The test entry has nestest's CRC32 but not its SHA-1
+		let (prg, chr) = nestest();
+		let games = parse(XML);
+		assert!(find(games.iter(), &prg, &chr).is_none());
+
+		let mut games = parse(XML);
+		games[0].sha1 = None;
+		assert!(find(games.iter(), &prg, &chr).is_some_and(|game| game.title == "Test Game (USA)"));
+	}
+}
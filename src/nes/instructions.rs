@@ -1,39 +1,94 @@
-use crate::nes::cpu::{CPU, FetchType};
-use crate::nes::bus::Bus;
-use crate::nes::addressing::AddrFn;
+use crate::nes::cpu::{CPU, FetchType, Interrupt};
+use crate::nes::addressing::Addressing;
 use crate::nes::mnemonic::Mnemonic;
-use crate::instr_size;
-use std::cell::Ref;
+use crate::addressing;
 
 pub type InstrFn = fn(&mut CPU);
 
+// How an instruction uses its operand, which decides the bus accesses after the address is known
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Access
+{
+	Read,
+	Write,
+	Modify,
+	// Control flow and stack instructions run their own cycles
+	Sequence
+}
+
 #[derive(Clone, Copy)]
 pub struct Instruction
 {
 	pub action: InstrFn,
-	pub addressing: AddrFn,
-	pub cycles: u8,
+	pub addressing: Addressing,
+	pub access: Access,
 	pub length: u8,
 
 	pub name: Mnemonic
 }
 
+impl Instruction
+{
+	pub fn reads_only(&self) -> bool
+	{
+		self.access == Access::Read
+	}
+}
+
+macro_rules! access
+{
+	(brk) => { Access::Sequence };
+	(jsr) => { Access::Sequence };
+	(jmp) => { Access::Sequence };
+	(rts) => { Access::Sequence };
+	(rti) => { Access::Sequence };
+	(pha) => { Access::Sequence };
+	(php) => { Access::Sequence };
+	(pla) => { Access::Sequence };
+	(plp) => { Access::Sequence };
+	(jam) => { Access::Sequence };
+
+	(sta) => { Access::Write };
+	(stx) => { Access::Write };
+	(sty) => { Access::Write };
+	(sax) => { Access::Write };
+	(sha) => { Access::Write };
+	(shx) => { Access::Write };
+	(shy) => { Access::Write };
+	(tas) => { Access::Write };
+
+	(asl) => { Access::Modify };
+	(lsr) => { Access::Modify };
+	(rol) => { Access::Modify };
+	(ror) => { Access::Modify };
+	(inc) => { Access::Modify };
+	(dec) => { Access::Modify };
+	(slo) => { Access::Modify };
+	(rla) => { Access::Modify };
+	(sre) => { Access::Modify };
+	(rra) => { Access::Modify };
+	(dcp) => { Access::Modify };
+	(isc) => { Access::Modify };
+
+	($instr: ident) => { Access::Read };
+}
+
 macro_rules! instr 
 {
-	($instr: ident, $addr: ident, $cyc: literal, $illegal: literal) =>
+	($instr: ident, $addr: ident, $illegal: literal) =>
 	{
-		Option::Some(Instruction 
+		Instruction 
 		{
 			action: CPU::$instr,
-			addressing: CPU::$addr,
-			cycles: $cyc,
-			length: instr_size!($addr),
+			addressing: addressing!($addr),
+			access: access!($instr),
+			length: addressing!($addr).size(),
 
 			name: Mnemonic::new(stringify!($instr), $illegal)
-		})
+		}
 	};
 
-	($instr: ident, $addr: ident, $cyc: literal) => { instr!($instr, $addr, $cyc, false) };
+	($instr: ident, $addr: ident) => { instr!($instr, $addr, false) };
 }
 
 #[allow(dead_code)]
@@ -80,47 +135,13 @@ macro_rules! test_flag
 	}
 }
 
-macro_rules! push
-{
-	($bus: expr, $sp: expr, $val: expr) =>
-	{
-		$bus.write_cpu(0x0100 + $sp as u16, ($val & 0xFF) as u8);
-		$sp -= 1;
-	}
-}
-
-fn pop(bus: Ref<Bus>, sp: &mut u8) -> u8 
-{
-	*sp += 1;
-	bus.read_cpu(0x0100 + *sp as u16)
-}
-
-macro_rules! branch
-{
-	($self: ident) => 
-	{
-		let branch_target = $self.pc.wrapping_add($self.relative_addr as u16);
-			
-		$self.additional_cycles += 1;
-		if (branch_target & 0xFF00) != ($self.pc & 0xFF00)	// Branched to different page
-		{
-			$self.additional_cycles += 1;
-		}
-
-		$self.pc = branch_target;
-	}
-}
-
 macro_rules! branch_on_fn
 {
 	($name: ident, $flag: expr, $result: literal) => 
 	{
 		fn $name(&mut self)
 		{
-			if test_flag!(self.p, $flag) == $result
-			{
-				branch!(self);
-			}
+			self.branch_taken = test_flag!(self.p, $flag) == $result;
 		}
 	}
 }
@@ -160,10 +181,7 @@ macro_rules! store_fn
 	{
 		fn $name(&mut self) 
 		{
-			let bus = self.bus.upgrade().unwrap();
-			bus.borrow_mut().write_cpu(self.absolute_addr, self.$register);
-
-			self.additional_cycles = 0;
+			self.ditch(self.$register);
 		}
 	};
 }
@@ -209,7 +227,6 @@ macro_rules! inc_dec_fn
 	{
 		fn $name(&mut self)
 		{
-			let bus = self.bus.upgrade().unwrap();
 			let mut value = self.fetch();
 
 			match $increment 
@@ -221,7 +238,7 @@ macro_rules! inc_dec_fn
 			set_flag_to!(self.p, Bit::Negative, (value >> 7) == 1);
 			set_flag_to!(self.p, Bit::Zero, value == 0);
 
-			bus.borrow_mut().write_cpu(self.absolute_addr, value);
+			self.ditch(value);
 		}
 	};
 }
@@ -292,15 +309,6 @@ macro_rules! invoke_functions
 
 macro_rules! combine_instructions
 {
-	($name: ident, no_additional_cycles, $($parts: ident),+) => 
-	{
-		fn $name(&mut self)
-		{
-			invoke_functions!(self, $($parts),+);
-			self.additional_cycles = 0;
-		}
-	};
-
 	($name: ident, $($parts: ident),+) => 
 	{
 		fn $name(&mut self)
@@ -312,18 +320,14 @@ macro_rules! combine_instructions
 
 impl CPU 
 {
+	// The operand is read by the addressing mode before the instruction runs, and
+	// written back by it afterwards
 	fn fetch(&mut self) -> u8
 	{
 		match self.fetch_type
 		{
-			FetchType::Mem => {
-				let bus = self.bus.upgrade().unwrap();
-				return bus.borrow().read_cpu(self.absolute_addr);
-			},
-
-			FetchType::Acc => {
-				self.acc
-			}
+			FetchType::Mem => self.data,
+			FetchType::Acc => self.acc
 		}
 	}
 
@@ -331,14 +335,8 @@ impl CPU
 	{
 		match self.fetch_type
 		{
-			FetchType::Mem => {
-				let bus = self.bus.upgrade().unwrap();
-				bus.borrow_mut().write_cpu(self.absolute_addr, value);
-			},
-
-			FetchType::Acc => {
-				self.acc = value;
-			}
+			FetchType::Mem => self.data = value,
+			FetchType::Acc => self.acc = value
 		}
 	}
 
@@ -446,8 +444,7 @@ impl CPU
 
 	fn bit(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
-		let value = bus.borrow().read_cpu(self.absolute_addr);
+		let value = self.fetch();
 
 		set_flag_to!(self.p, Bit::Negative, (value >> 7) & 0x1);
 		set_flag_to!(self.p, Bit::Overflow, (value >> 6) & 0x1);
@@ -457,18 +454,47 @@ impl CPU
 
 	fn jmp(&mut self)
 	{
-		self.pc = self.absolute_addr;
+		match (self.instr.addressing, self.step())
+		{
+			(Addressing::Ind, _) => self.ind(),
+
+			(_, 1) => {
+				self.absolute_addr = self.read(self.pc) as u16;
+				self.pc = self.pc.wrapping_add(1);
+			},
+
+			_ => {
+				self.absolute_addr |= (self.read(self.pc) as u16) << 8;
+				self.pc = self.absolute_addr;
+				self.finish();
+			}
+		}
 	}
 
+	// The return address is pushed between reading the two bytes of the target, so it points
+	// at the last byte of the JSR
 	fn jsr(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		match self.step()
+		{
+			1 => {
+				self.absolute_addr = self.read(self.pc) as u16;
+				self.pc = self.pc.wrapping_add(1);
+			},
+
+			2 => {
+				self.peek_stack();
+			},
 
-		self.pc -= 1;
-		push!(bus.borrow_mut(), self.sp, self.pc >> 8);
-		push!(bus.borrow_mut(), self.sp, self.pc);
+			3 => self.push((self.pc >> 8) as u8),
+			4 => self.push(self.pc as u8),
 
-		self.pc = self.absolute_addr;
+			_ => {
+				self.absolute_addr |= (self.read(self.pc) as u16) << 8;
+				self.pc = self.absolute_addr;
+				self.finish();
+			}
+		}
 	}
 
 	fn nop(&mut self)
@@ -478,365 +504,564 @@ impl CPU
 
 	fn pha(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		match self.step()
+		{
+			1 => {
+				self.read(self.pc);
+			},
 
-		push!(bus.borrow_mut(), self.sp, self.acc);
+			_ => {
+				self.push(self.acc);
+				self.finish();
+			}
+		}
 	}
 
 	fn pla(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		match self.step()
+		{
+			1 => {
+				self.read(self.pc);
+			},
 
-		self.acc = pop(bus.borrow(), &mut self.sp);
-		set_flag_to!(self.p, Bit::Negative, (self.acc & (1u8 << 7)) > 0);
-		set_flag_to!(self.p, Bit::Zero, self.acc == 0);
+			2 => {
+				self.peek_stack();
+				self.sp = self.sp.wrapping_add(1);
+			},
+
+			_ => {
+				self.acc = self.peek_stack();
+				set_flag_to!(self.p, Bit::Negative, (self.acc & (1u8 << 7)) > 0);
+				set_flag_to!(self.p, Bit::Zero, self.acc == 0);
+				self.finish();
+			}
+		}
 	}
 
+	// The B flag only exists on the stack, it's set when the status is pushed by BRK or PHP
 	fn php(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		match self.step()
+		{
+			1 => {
+				self.read(self.pc);
+			},
 
-		let mut value = self.p;
-		set_flag!(value, Bit::Break);
-		set_flag!(value, 5);
+			_ => {
+				let mut value = self.p;
+				set_flag!(value, Bit::Break);
+				set_flag!(value, 5);
 
-		push!(bus.borrow_mut(), self.sp, value);
+				self.push(value);
+				self.finish();
+			}
+		}
 	}
 
 	fn plp(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		match self.step()
+		{
+			1 => {
+				self.read(self.pc);
+			},
+
+			2 => {
+				self.peek_stack();
+				self.sp = self.sp.wrapping_add(1);
+			},
 
-		let flag: u8 = pop(bus.borrow(), &mut self.sp);
-		let mask: u8 = 0b11001111;
+			_ => {
+				let flag: u8 = self.peek_stack();
+				let mask: u8 = 0b11001111;
 
-		self.p &= !mask;
-		self.p |= flag & mask;
+				self.p &= !mask;
+				self.p |= flag & mask;
+				self.finish();
+			}
+		}
 	}
 
 	fn rti(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		match self.step()
+		{
+			1 => {
+				self.read(self.pc);
+			},
+
+			2 => {
+				self.peek_stack();
+				self.sp = self.sp.wrapping_add(1);
+			},
 
-		let flag: u8 = pop(bus.borrow(), &mut self.sp);
-		let mask: u8 = 0b11001111;
+			3 => {
+				let flag: u8 = self.peek_stack();
+				let mask: u8 = 0b11001111;
 
-		self.p &= !mask;
-		self.p |= flag & mask;
+				self.p &= !mask;
+				self.p |= flag & mask;
+				self.sp = self.sp.wrapping_add(1);
+			},
 
-		let lo = pop(bus.borrow(), &mut self.sp) as u16;
-		let hi = pop(bus.borrow(), &mut self.sp) as u16;
+			4 => {
+				self.absolute_addr = self.peek_stack() as u16;
+				self.sp = self.sp.wrapping_add(1);
+			},
 
-		self.pc = (hi << 8) | lo;
+			_ => {
+				self.absolute_addr |= (self.peek_stack() as u16) << 8;
+				self.pc = self.absolute_addr;
+				self.finish();
+			}
+		}
 	}
 
+	// The last cycle moves past the last byte of the JSR that was pushed
 	fn rts(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		match self.step()
+		{
+			1 => {
+				self.read(self.pc);
+			},
+
+			2 => {
+				self.peek_stack();
+				self.sp = self.sp.wrapping_add(1);
+			},
+
+			3 => {
+				self.absolute_addr = self.peek_stack() as u16;
+				self.sp = self.sp.wrapping_add(1);
+			},
+
+			4 => {
+				self.absolute_addr |= (self.peek_stack() as u16) << 8;
+				self.pc = self.absolute_addr;
+			},
+
+			_ => {
+				self.read(self.pc);
+				self.pc = self.pc.wrapping_add(1);
+				self.finish();
+			}
+		}
+	}
+
+	// Enters a subroutine like JSR does, RTS then returns to `ret`
+	pub fn call(&mut self, addr: u16, ret: u16)
+	{
+		let ret = ret.wrapping_sub(1);
 
-		let lo = pop(bus.borrow(), &mut self.sp) as u16;
-		let hi = pop(bus.borrow(), &mut self.sp) as u16;
+		self.abort();
+		self.push((ret >> 8) as u8);
+		self.push(ret as u8);
 
-		self.pc = (hi << 8) | lo;
-		self.pc += 1;
+		self.pc = addr;
 	}
 
+	// BRK skips the padding byte after the opcode, so RTI returns past it
 	fn brk(&mut self) 
 	{
-		let bus = self.bus.upgrade().unwrap();
+		self.interrupt_sequence(Interrupt::Break);
 	}
 
 	///// ILLEGAL OPCODES
 	
-	combine_instructions!(dcp, no_additional_cycles, dec, cmp);
+	combine_instructions!(dcp, dec, cmp);
 	combine_instructions!(lax, lda, ldx);
-	combine_instructions!(isc, no_additional_cycles, inc, sbc);
-	combine_instructions!(slo, no_additional_cycles, asl, ora);
-	combine_instructions!(rla, no_additional_cycles, rol, and);
-	combine_instructions!(sre, no_additional_cycles, lsr, eor);
-	combine_instructions!(rra, no_additional_cycles, ror, adc);
+	combine_instructions!(isc, inc, sbc);
+	combine_instructions!(slo, asl, ora);
+	combine_instructions!(rla, rol, and);
+	combine_instructions!(sre, lsr, eor);
+	combine_instructions!(rra, ror, adc);
+
+	// Reads the byte after the opcode and never finishes, only a reset gets the CPU going again
+	fn jam(&mut self)
+	{
+		self.read(self.pc);
+		self.halt();
+	}
 
 	fn sax(&mut self)
 	{
-		let bus = self.bus.upgrade().unwrap();
+		self.ditch(self.acc & self.x);
+	}
+
+	fn anc(&mut self)
+	{
+		self.and();
+		set_flag_to!(self.p, Bit::Carry, test_flag!(self.p, Bit::Negative));
+	}
+
+	fn alr(&mut self)
+	{
+		self.and();
+		self.fetch_type = FetchType::Acc;
+		self.lsr();
+	}
+
+	// The carry comes from bit 6 of the result and overflow from bits 6 and 5, as if the adder had
+	// been used to rotate
+	fn arr(&mut self)
+	{
+		self.and();
+		self.fetch_type = FetchType::Acc;
+		self.ror();
+
+		set_flag_to!(self.p, Bit::Carry, (self.acc >> 6) & 0x01);
+		set_flag_to!(self.p, Bit::Overflow, ((self.acc >> 6) ^ (self.acc >> 5)) & 0x01);
+	}
+
+	// Compares like CMP, so the carry in is ignored
+	fn axs(&mut self)
+	{
+		let value = self.fetch();
+		let and = self.acc & self.x;
+
+		self.x = and.wrapping_sub(value);
+		set_flag_to!(self.p, Bit::Carry, and >= value);
+		set_flag_to!(self.p, Bit::Negative, (self.x >> 7) != 0);
+		set_flag_to!(self.p, Bit::Zero, self.x == 0);
+	}
+
+	fn las(&mut self)
+	{
+		let value = self.fetch() & self.sp;
+
+		self.acc = value;
+		self.x = value;
+		self.sp = value;
+		set_flag_to!(self.p, Bit::Negative, (value >> 7) != 0);
+		set_flag_to!(self.p, Bit::Zero, value == 0);
+	}
+
+	// XAA and LAX #imm put A on the internal bus while it's also being driven by the operand. Which bits
+	// survive depends on the chip and its temperature, modelled by ORing A with the magic constant
+	fn xaa(&mut self)
+	{
+		self.acc = (self.acc | self.magic) & self.x & self.fetch();
+		set_flag_to!(self.p, Bit::Negative, (self.acc >> 7) != 0);
+		set_flag_to!(self.p, Bit::Zero, self.acc == 0);
+	}
+
+	fn lxa(&mut self)
+	{
+		self.acc = (self.acc | self.magic) & self.fetch();
+		self.x = self.acc;
+		set_flag_to!(self.p, Bit::Negative, (self.acc >> 7) != 0);
+		set_flag_to!(self.p, Bit::Zero, self.acc == 0);
+	}
+
+	fn sha(&mut self)
+	{
+		self.unstable_store(self.acc & self.x);
+	}
+
+	fn shx(&mut self)
+	{
+		self.unstable_store(self.x);
+	}
+
+	fn shy(&mut self)
+	{
+		self.unstable_store(self.y);
+	}
+
+	fn tas(&mut self)
+	{
+		self.sp = self.acc & self.x;
+		self.unstable_store(self.sp);
+	}
 
-		bus.borrow_mut().write_cpu(self.absolute_addr, self.acc & self.x);
+	// The stores that AND the value with the high byte of the base address plus one. When the index
+	// crosses a page, the value also replaces the high byte of the address it's written to
+	fn unstable_store(&mut self, value: u8)
+	{
+		let value = value & ((self.base_addr >> 8) as u8).wrapping_add(1);
+
+		if (self.base_addr & 0xFF00) != (self.absolute_addr & 0xFF00)
+		{
+			self.absolute_addr = ((value as u16) << 8) | (self.absolute_addr & 0x00FF);
+		}
 
-		self.additional_cycles = 0;
+		self.ditch(value);
 	}
 }
 
 
-pub static INSTRUCTION_SET: [Option<Instruction>; 256] = [
-		/* 00 */ Option::None, //instr!(brk, imp, 7),
-		/* 01 */ instr!(ora, idx, 6),
-		/* 02 */ Option::None,
-		/* 03 */ instr!(slo, idx, 8, true),
-		/* 04 */ instr!(nop, zpg, 3, true),
-		/* 05 */ instr!(ora, zpg, 3),
-		/* 06 */ instr!(asl, zpg, 5),
-		/* 07 */ instr!(slo, zpg, 5, true),
-		/* 08 */ instr!(php, imp, 3),
-		/* 09 */ instr!(ora, imm, 2),
-		/* 0A */ instr!(asl, acc, 2),
-		/* 0B */ Option::None,
-		/* 0C */ instr!(nop, abs, 4, true),
-		/* 0D */ instr!(ora, abs, 4),
-		/* 0E */ instr!(asl, abs, 6),
-		/* 0F */ instr!(slo, abs, 6, true),
-
-		/* 10 */ instr!(bpl, rel, 2),
-		/* 11 */ instr!(ora, idy, 5),
-		/* 12 */ Option::None,
-		/* 13 */ instr!(slo, idy, 8, true),
-		/* 14 */ instr!(nop, zpx, 4, true),
-		/* 15 */ instr!(ora, zpx, 4),
-		/* 16 */ instr!(asl, zpx, 6),
-		/* 17 */ instr!(slo, zpx, 6, true),
-		/* 18 */ instr!(clc, imp, 2),
-		/* 19 */ instr!(ora, aby, 4),
-		/* 1A */ instr!(nop, imp, 2, true),
-		/* 1B */ instr!(slo, aby, 7, true),
-		/* 1C */ instr!(nop, abx, 4, true),
-		/* 1D */ instr!(ora, abx, 4),
-		/* 1E */ instr!(asl, abx, 7),
-		/* 1F */ instr!(slo, abx, 7, true),
-
-		/* 20 */ instr!(jsr, abs, 6),
-		/* 21 */ instr!(and, idx, 6),
-		/* 22 */ Option::None,
-		/* 23 */ instr!(rla, idx, 8, true),
-		/* 24 */ instr!(bit, zpg, 3),
-		/* 25 */ instr!(and, zpg, 3),
-		/* 26 */ instr!(rol, zpg, 5),
-		/* 27 */ instr!(rla, zpg, 5, true),
-		/* 28 */ instr!(plp, imp, 4),
-		/* 29 */ instr!(and, imm, 2),
-		/* 2A */ instr!(rol, acc, 2),
-		/* 2B */ Option::None,
-		/* 2C */ instr!(bit, abs, 4),
-		/* 2D */ instr!(and, abs, 4),
-		/* 2E */ instr!(rol, abs, 6),
-		/* 2F */ instr!(rla, abs, 6, true),
-
-		/* 30 */ instr!(bmi, rel, 2),
-		/* 31 */ instr!(and, idy, 5),
-		/* 32 */ Option::None,
-		/* 33 */ instr!(rla, idy, 8, true),
-		/* 34 */ instr!(nop, zpx, 4, true),
-		/* 35 */ instr!(and, zpx, 4),
-		/* 36 */ instr!(rol, zpx, 6),
-		/* 37 */ instr!(rla, zpx, 6, true),
-		/* 38 */ instr!(sec, imp, 2),
-		/* 39 */ instr!(and, aby, 4),
-		/* 3A */ instr!(nop, imp, 2, true),
-		/* 3B */ instr!(rla, aby, 7, true),
-		/* 3C */ instr!(nop, abx, 4, true),
-		/* 3D */ instr!(and, abx, 4),
-		/* 3E */ instr!(rol, abx, 7),
-		/* 3F */ instr!(rla, abx, 7, true),
-
-		/* 40 */ instr!(rti, imp, 6),
-		/* 41 */ instr!(eor, idx, 6),
-		/* 42 */ Option::None,
-		/* 43 */ instr!(sre, idx, 8, true),
-		/* 44 */ instr!(nop, zpg, 3, true),
-		/* 45 */ instr!(eor, zpg, 3),
-		/* 46 */ instr!(lsr, zpg, 5),
-		/* 47 */ instr!(sre, zpg, 5, true),
-		/* 48 */ instr!(pha, imp, 3),
-		/* 49 */ instr!(eor, imm, 2),
-		/* 4A*/  instr!(lsr, acc, 2),
-		/* 4B */ Option::None,
-		/* 4C */ instr!(jmp, abs, 3),
-		/* 4D */ instr!(eor, abs, 4),
-		/* 4E */ instr!(lsr, abs, 6),
-		/* 4F */ instr!(sre, abs, 6, true),
-
-		/* 50 */ instr!(bvc, rel, 2),
-		/* 51 */ instr!(eor, idy, 5),
-		/* 52 */ Option::None,
-		/* 53 */ instr!(sre, idy, 8, true),
-		/* 54 */ instr!(nop, zpx, 4, true),
-		/* 55 */ instr!(eor, zpx, 4),
-		/* 56 */ instr!(lsr, zpx, 6),
-		/* 57 */ instr!(sre, zpx, 6, true),
-		/* 58 */ instr!(sre, aby, 7, true),
-		/* 59 */ instr!(eor, aby, 4),
-		/* 5A */ instr!(nop, imp, 2, true),
-		/* 5B */ instr!(sre, aby, 7, true),
-		/* 5C */ instr!(nop, abx, 4, true),
-		/* 5D */ instr!(eor, abx, 4),
-		/* 5E */ instr!(lsr, abx, 7),
-		/* 5F */ instr!(sre, abx, 7, true),
-
-		/* 60 */ instr!(rts, imp, 6),
-		/* 61 */ instr!(adc, idx, 6),
-		/* 62 */ Option::None,
-		/* 63 */ instr!(rra, idx, 8, true),
-		/* 64 */ instr!(nop, zpg, 3, true),
-		/* 65 */ instr!(adc, zpg, 3),
-		/* 66 */ instr!(ror, zpg, 5),
-		/* 67 */ instr!(rra, zpg, 5, true),
-		/* 68 */ instr!(pla, imp, 4),
-		/* 69 */ instr!(adc, imm, 2),
-		/* 6A */ instr!(ror, acc, 2),
-		/* 6B */ Option::None,
-		/* 6C */ instr!(jmp, ind, 5),
-		/* 6D */ instr!(adc, abs, 4),
-		/* 6E */ instr!(ror, abs, 6),
-		/* 6F */ instr!(rra, abs, 6, true),
+pub static INSTRUCTION_SET: [Instruction; 256] = [
+		/* 00 */ instr!(brk, imp),
+		/* 01 */ instr!(ora, idx),
+		/* 02 */ instr!(jam, imp, true),
+		/* 03 */ instr!(slo, idx, true),
+		/* 04 */ instr!(nop, zpg, true),
+		/* 05 */ instr!(ora, zpg),
+		/* 06 */ instr!(asl, zpg),
+		/* 07 */ instr!(slo, zpg, true),
+		/* 08 */ instr!(php, imp),
+		/* 09 */ instr!(ora, imm),
+		/* 0A */ instr!(asl, acc),
+		/* 0B */ instr!(anc, imm, true),
+		/* 0C */ instr!(nop, abs, true),
+		/* 0D */ instr!(ora, abs),
+		/* 0E */ instr!(asl, abs),
+		/* 0F */ instr!(slo, abs, true),
+
+		/* 10 */ instr!(bpl, rel),
+		/* 11 */ instr!(ora, idy),
+		/* 12 */ instr!(jam, imp, true),
+		/* 13 */ instr!(slo, idy, true),
+		/* 14 */ instr!(nop, zpx, true),
+		/* 15 */ instr!(ora, zpx),
+		/* 16 */ instr!(asl, zpx),
+		/* 17 */ instr!(slo, zpx, true),
+		/* 18 */ instr!(clc, imp),
+		/* 19 */ instr!(ora, aby),
+		/* 1A */ instr!(nop, imp, true),
+		/* 1B */ instr!(slo, aby, true),
+		/* 1C */ instr!(nop, abx, true),
+		/* 1D */ instr!(ora, abx),
+		/* 1E */ instr!(asl, abx),
+		/* 1F */ instr!(slo, abx, true),
+
+		/* 20 */ instr!(jsr, abs),
+		/* 21 */ instr!(and, idx),
+		/* 22 */ instr!(jam, imp, true),
+		/* 23 */ instr!(rla, idx, true),
+		/* 24 */ instr!(bit, zpg),
+		/* 25 */ instr!(and, zpg),
+		/* 26 */ instr!(rol, zpg),
+		/* 27 */ instr!(rla, zpg, true),
+		/* 28 */ instr!(plp, imp),
+		/* 29 */ instr!(and, imm),
+		/* 2A */ instr!(rol, acc),
+		/* 2B */ instr!(anc, imm, true),
+		/* 2C */ instr!(bit, abs),
+		/* 2D */ instr!(and, abs),
+		/* 2E */ instr!(rol, abs),
+		/* 2F */ instr!(rla, abs, true),
+
+		/* 30 */ instr!(bmi, rel),
+		/* 31 */ instr!(and, idy),
+		/* 32 */ instr!(jam, imp, true),
+		/* 33 */ instr!(rla, idy, true),
+		/* 34 */ instr!(nop, zpx, true),
+		/* 35 */ instr!(and, zpx),
+		/* 36 */ instr!(rol, zpx),
+		/* 37 */ instr!(rla, zpx, true),
+		/* 38 */ instr!(sec, imp),
+		/* 39 */ instr!(and, aby),
+		/* 3A */ instr!(nop, imp, true),
+		/* 3B */ instr!(rla, aby, true),
+		/* 3C */ instr!(nop, abx, true),
+		/* 3D */ instr!(and, abx),
+		/* 3E */ instr!(rol, abx),
+		/* 3F */ instr!(rla, abx, true),
+
+		/* 40 */ instr!(rti, imp),
+		/* 41 */ instr!(eor, idx),
+		/* 42 */ instr!(jam, imp, true),
+		/* 43 */ instr!(sre, idx, true),
+		/* 44 */ instr!(nop, zpg, true),
+		/* 45 */ instr!(eor, zpg),
+		/* 46 */ instr!(lsr, zpg),
+		/* 47 */ instr!(sre, zpg, true),
+		/* 48 */ instr!(pha, imp),
+		/* 49 */ instr!(eor, imm),
+		/* 4A*/  instr!(lsr, acc),
+		/* 4B */ instr!(alr, imm, true),
+		/* 4C */ instr!(jmp, abs),
+		/* 4D */ instr!(eor, abs),
+		/* 4E */ instr!(lsr, abs),
+		/* 4F */ instr!(sre, abs, true),
+
+		/* 50 */ instr!(bvc, rel),
+		/* 51 */ instr!(eor, idy),
+		/* 52 */ instr!(jam, imp, true),
+		/* 53 */ instr!(sre, idy, true),
+		/* 54 */ instr!(nop, zpx, true),
+		/* 55 */ instr!(eor, zpx),
+		/* 56 */ instr!(lsr, zpx),
+		/* 57 */ instr!(sre, zpx, true),
+		/* 58 */ instr!(cli, imp),
+		/* 59 */ instr!(eor, aby),
+		/* 5A */ instr!(nop, imp, true),
+		/* 5B */ instr!(sre, aby, true),
+		/* 5C */ instr!(nop, abx, true),
+		/* 5D */ instr!(eor, abx),
+		/* 5E */ instr!(lsr, abx),
+		/* 5F */ instr!(sre, abx, true),
+
+		/* 60 */ instr!(rts, imp),
+		/* 61 */ instr!(adc, idx),
+		/* 62 */ instr!(jam, imp, true),
+		/* 63 */ instr!(rra, idx, true),
+		/* 64 */ instr!(nop, zpg, true),
+		/* 65 */ instr!(adc, zpg),
+		/* 66 */ instr!(ror, zpg),
+		/* 67 */ instr!(rra, zpg, true),
+		/* 68 */ instr!(pla, imp),
+		/* 69 */ instr!(adc, imm),
+		/* 6A */ instr!(ror, acc),
+		/* 6B */ instr!(arr, imm, true),
+		/* 6C */ instr!(jmp, ind),
+		/* 6D */ instr!(adc, abs),
+		/* 6E */ instr!(ror, abs),
+		/* 6F */ instr!(rra, abs, true),
 		
-		/* 70 */ instr!(bvs, rel, 2),
-		/* 71 */ instr!(adc, idy, 5),
-		/* 72 */ Option::None,
-		/* 73 */ instr!(rra, idy, 8, true),
-		/* 74 */ instr!(nop, zpx, 4, true),
-		/* 75 */ instr!(adc, zpx, 4),
-		/* 76 */ instr!(ror, zpx, 6),
-		/* 77 */ instr!(rra, zpx, 6, true),
-		/* 78 */ instr!(sei, imp, 2),
-		/* 79 */ instr!(adc, aby, 4),
-		/* 7A */ instr!(nop, imp, 2, true),
-		/* 7B */ instr!(rra, aby, 7, true),
-		/* 7C */ instr!(nop, abx, 4, true),
-		/* 7D */ instr!(adc, abx, 4),
-		/* 7E */ instr!(ror, abx, 7),
-		/* 7F */ instr!(rra, abx, 7, true),
-
-		/* 80 */ instr!(nop, imm, 2, true),
-		/* 81 */ instr!(sta, idx, 6),
-		/* 82 */ instr!(nop, imm, 2, true),
-		/* 83 */ instr!(sax, idx, 6, true),
-		/* 84 */ instr!(sty, zpg, 3),
-		/* 85 */ instr!(sta, zpg, 3),
-		/* 86 */ instr!(stx, zpg, 3),
-		/* 87 */ instr!(sax, zpg, 3, true),
-		/* 88 */ instr!(dey, imp, 2),
-		/* 89 */ instr!(nop, imm, 2, true),
-		/* 8A */ instr!(txa, imp, 2),
-		/* 8B */ Option::None,
-		/* 8C */ instr!(sty, abs, 4),
-		/* 8D */ instr!(sta, abs, 4),
-		/* 8E */ instr!(stx, abs, 4),
-		/* 8F */ instr!(sax, abs, 4, true),
-
-		/* 90 */ instr!(bcc, rel, 2),
-		/* 91 */ instr!(sta, idy, 6),
-		/* 92 */ Option::None,
-		/* 93 */ Option::None,
-		/* 94 */ instr!(sty, zpx, 4),
-		/* 95 */ instr!(sta, zpx, 4),
-		/* 96 */ instr!(stx, zpy, 4),
-		/* 97 */ instr!(sax, zpy, 4, true),
-		/* 98 */ instr!(tya, imp, 2),
-		/* 99 */ instr!(sta, aby, 5),
-		/* 9A */ instr!(txs, imp, 2),
-		/* 9B */ Option::None,
-		/* 9C */ Option::None,
-		/* 9D */ instr!(sta, abx, 5),
-		/* 9E */ Option::None,
-		/* 9F */ Option::None,
-
-		/* A0 */ instr!(ldy, imm, 2),
-		/* A1 */ instr!(lda, idx, 6),
-		/* A2 */ instr!(ldx, imm, 2),
-		/* A3 */ instr!(lax, idx, 6, true),
-		/* A4 */ instr!(ldy, zpg, 3),
-		/* A5 */ instr!(lda, zpg, 3),
-		/* A6 */ instr!(ldx, zpg, 3),
-		/* A7 */ instr!(lax, zpg, 3, true),
-		/* A8 */ instr!(tay, imp, 2),
-		/* A9 */ instr!(lda, imm, 2),
-		/* AA */ instr!(tax, imp, 2),
-		/* AB */ Option::None,
-		/* AC */ instr!(ldy, abs, 4),
-		/* AD */ instr!(lda, abs, 4),
-		/* AE */ instr!(ldx, abs, 4),
-		/* AF */ instr!(lax, abs, 4, true),
+		/* 70 */ instr!(bvs, rel),
+		/* 71 */ instr!(adc, idy),
+		/* 72 */ instr!(jam, imp, true),
+		/* 73 */ instr!(rra, idy, true),
+		/* 74 */ instr!(nop, zpx, true),
+		/* 75 */ instr!(adc, zpx),
+		/* 76 */ instr!(ror, zpx),
+		/* 77 */ instr!(rra, zpx, true),
+		/* 78 */ instr!(sei, imp),
+		/* 79 */ instr!(adc, aby),
+		/* 7A */ instr!(nop, imp, true),
+		/* 7B */ instr!(rra, aby, true),
+		/* 7C */ instr!(nop, abx, true),
+		/* 7D */ instr!(adc, abx),
+		/* 7E */ instr!(ror, abx),
+		/* 7F */ instr!(rra, abx, true),
+
+		/* 80 */ instr!(nop, imm, true),
+		/* 81 */ instr!(sta, idx),
+		/* 82 */ instr!(nop, imm, true),
+		/* 83 */ instr!(sax, idx, true),
+		/* 84 */ instr!(sty, zpg),
+		/* 85 */ instr!(sta, zpg),
+		/* 86 */ instr!(stx, zpg),
+		/* 87 */ instr!(sax, zpg, true),
+		/* 88 */ instr!(dey, imp),
+		/* 89 */ instr!(nop, imm, true),
+		/* 8A */ instr!(txa, imp),
+		/* 8B */ instr!(xaa, imm, true),
+		/* 8C */ instr!(sty, abs),
+		/* 8D */ instr!(sta, abs),
+		/* 8E */ instr!(stx, abs),
+		/* 8F */ instr!(sax, abs, true),
+
+		/* 90 */ instr!(bcc, rel),
+		/* 91 */ instr!(sta, idy),
+		/* 92 */ instr!(jam, imp, true),
+		/* 93 */ instr!(sha, idy, true),
+		/* 94 */ instr!(sty, zpx),
+		/* 95 */ instr!(sta, zpx),
+		/* 96 */ instr!(stx, zpy),
+		/* 97 */ instr!(sax, zpy, true),
+		/* 98 */ instr!(tya, imp),
+		/* 99 */ instr!(sta, aby),
+		/* 9A */ instr!(txs, imp),
+		/* 9B */ instr!(tas, aby, true),
+		/* 9C */ instr!(shy, abx, true),
+		/* 9D */ instr!(sta, abx),
+		/* 9E */ instr!(shx, aby, true),
+		/* 9F */ instr!(sha, aby, true),
+
+		/* A0 */ instr!(ldy, imm),
+		/* A1 */ instr!(lda, idx),
+		/* A2 */ instr!(ldx, imm),
+		/* A3 */ instr!(lax, idx, true),
+		/* A4 */ instr!(ldy, zpg),
+		/* A5 */ instr!(lda, zpg),
+		/* A6 */ instr!(ldx, zpg),
+		/* A7 */ instr!(lax, zpg, true),
+		/* A8 */ instr!(tay, imp),
+		/* A9 */ instr!(lda, imm),
+		/* AA */ instr!(tax, imp),
+		/* AB */ instr!(lxa, imm, true),
+		/* AC */ instr!(ldy, abs),
+		/* AD */ instr!(lda, abs),
+		/* AE */ instr!(ldx, abs),
+		/* AF */ instr!(lax, abs, true),
 		
-		/* B0 */ instr!(bcs, rel, 2),
-		/* B1 */ instr!(lda, idy, 5),
-		/* B2 */ Option::None,
-		/* B3 */ instr!(lax, idy, 5, true),
-		/* B4 */ instr!(ldy, zpx, 4),
-		/* B5 */ instr!(lda, zpx, 4),
-		/* B6 */ instr!(ldx, zpy, 4),
-		/* B7 */ instr!(lax, zpy, 4, true),
-		/* B8 */ instr!(clv, imp, 2),
-		/* B9 */ instr!(lda, aby, 4),
-		/* BA */ instr!(tsx, imp, 2),
-		/* BB */ Option::None,
-		/* BC */ instr!(ldy, abx, 4),
-		/* BD */ instr!(lda, abx, 4),
-		/* BE */ instr!(ldx, aby, 4),
-		/* BF */ instr!(lax, aby, 4, true),
-
-		/* C0 */ instr!(cpy, imm, 2),
-		/* C1 */ instr!(cmp, idx, 6),
-		/* C2 */ instr!(nop, imm, 2, true),
-		/* C3 */ instr!(dcp, idx, 8, true),
-		/* C4 */ instr!(cpy, zpg, 3),
-		/* C5 */ instr!(cmp, zpg, 3),
-		/* C6 */ instr!(dec, zpg, 5),
-		/* C7 */ instr!(dcp, zpg, 5, true),
-		/* C8 */ instr!(iny, imp, 2),
-		/* C9 */ instr!(cmp, imm, 2),
-		/* CA */ instr!(dex, imp, 2),
-		/* CB */ Option::None,
-		/* CC */ instr!(cpy, abs, 4),
-		/* CD */ instr!(cmp, abs, 4),
-		/* CE */ instr!(dec, abs, 6),
-		/* CF */ instr!(dcp, abs, 6, true),
-
-		/* D0 */ instr!(bne, rel, 2),
-		/* D1 */ instr!(cmp, idy, 5),
-		/* D2 */ Option::None,
-		/* D3 */ instr!(dcp, idy, 8, true),
-		/* D4 */ instr!(nop, zpx, 4, true),
-		/* D5 */ instr!(cmp, zpx, 4),
-		/* D6 */ instr!(dec, zpx, 6),
-		/* D7 */ instr!(dcp, zpx, 6, true),
-		/* D8 */ instr!(cld, imp, 2),
-		/* D9 */ instr!(cmp, aby, 4),
-		/* DA */ instr!(nop, imp, 2, true),
-		/* DB */ instr!(dcp, aby, 7, true),
-		/* DC */ instr!(nop, abx, 4, true),
-		/* DD */ instr!(cmp, abx, 4),
-		/* DE */ instr!(dec, abx, 7),
-		/* DF */ instr!(dcp, abx, 7, true),
-
-		/* E0 */ instr!(cpx, imm, 2),
-		/* E1 */ instr!(sbc, idx, 6),
-		/* E2 */ instr!(nop, imm, 2, true),
-		/* E3 */ instr!(isc, idx, 8, true),
-		/* E4 */ instr!(cpx, zpg, 3),
-		/* E5 */ instr!(sbc, zpg, 3),
-		/* E6 */ instr!(inc, zpg, 5),
-		/* E7 */ instr!(isc, zpg, 5, true),
-		/* E8 */ instr!(inx, imp, 2),
-		/* E9 */ instr!(sbc, imm, 2),
-		/* EA */ instr!(nop, imp, 2),
-		/* EB */ instr!(sbc, imm, 2, true),
-		/* EC */ instr!(cpx, abs, 4),
-		/* ED */ instr!(sbc, abs, 4),
-		/* EE */ instr!(inc, abs, 6),
-		/* EF */ instr!(isc, abs, 6, true),
-
-		/* F0 */ instr!(beq, rel, 2),
-		/* F1 */ instr!(sbc, idy, 5),
-		/* F2 */ Option::None,
-		/* F3 */ instr!(isc, idy, 8, true),
-		/* F4 */ instr!(nop, zpx, 4, true),
-		/* F5 */ instr!(sbc, zpx, 4),
-		/* F6 */ instr!(inc, zpx, 6),
-		/* F7 */ instr!(isc, zpx, 6, true),
-		/* F8 */ instr!(sed, imp, 2),
-		/* F9 */ instr!(sbc, aby, 4),
-		/* FA */ instr!(nop, imp, 2, true),
-		/* FB */ instr!(isc, aby, 7, true),
-		/* FC */ instr!(nop, abx, 4, true),
-		/* FD */ instr!(sbc, abx, 4),
-		/* FE */ instr!(inc, abx, 7),
-		/* FF */ instr!(isc, abx, 7, true),
+		/* B0 */ instr!(bcs, rel),
+		/* B1 */ instr!(lda, idy),
+		/* B2 */ instr!(jam, imp, true),
+		/* B3 */ instr!(lax, idy, true),
+		/* B4 */ instr!(ldy, zpx),
+		/* B5 */ instr!(lda, zpx),
+		/* B6 */ instr!(ldx, zpy),
+		/* B7 */ instr!(lax, zpy, true),
+		/* B8 */ instr!(clv, imp),
+		/* B9 */ instr!(lda, aby),
+		/* BA */ instr!(tsx, imp),
+		/* BB */ instr!(las, aby, true),
+		/* BC */ instr!(ldy, abx),
+		/* BD */ instr!(lda, abx),
+		/* BE */ instr!(ldx, aby),
+		/* BF */ instr!(lax, aby, true),
+
+		/* C0 */ instr!(cpy, imm),
+		/* C1 */ instr!(cmp, idx),
+		/* C2 */ instr!(nop, imm, true),
+		/* C3 */ instr!(dcp, idx, true),
+		/* C4 */ instr!(cpy, zpg),
+		/* C5 */ instr!(cmp, zpg),
+		/* C6 */ instr!(dec, zpg),
+		/* C7 */ instr!(dcp, zpg, true),
+		/* C8 */ instr!(iny, imp),
+		/* C9 */ instr!(cmp, imm),
+		/* CA */ instr!(dex, imp),
+		/* CB */ instr!(axs, imm, true),
+		/* CC */ instr!(cpy, abs),
+		/* CD */ instr!(cmp, abs),
+		/* CE */ instr!(dec, abs),
+		/* CF */ instr!(dcp, abs, true),
+
+		/* D0 */ instr!(bne, rel),
+		/* D1 */ instr!(cmp, idy),
+		/* D2 */ instr!(jam, imp, true),
+		/* D3 */ instr!(dcp, idy, true),
+		/* D4 */ instr!(nop, zpx, true),
+		/* D5 */ instr!(cmp, zpx),
+		/* D6 */ instr!(dec, zpx),
+		/* D7 */ instr!(dcp, zpx, true),
+		/* D8 */ instr!(cld, imp),
+		/* D9 */ instr!(cmp, aby),
+		/* DA */ instr!(nop, imp, true),
+		/* DB */ instr!(dcp, aby, true),
+		/* DC */ instr!(nop, abx, true),
+		/* DD */ instr!(cmp, abx),
+		/* DE */ instr!(dec, abx),
+		/* DF */ instr!(dcp, abx, true),
+
+		/* E0 */ instr!(cpx, imm),
+		/* E1 */ instr!(sbc, idx),
+		/* E2 */ instr!(nop, imm, true),
+		/* E3 */ instr!(isc, idx, true),
+		/* E4 */ instr!(cpx, zpg),
+		/* E5 */ instr!(sbc, zpg),
+		/* E6 */ instr!(inc, zpg),
+		/* E7 */ instr!(isc, zpg, true),
+		/* E8 */ instr!(inx, imp),
+		/* E9 */ instr!(sbc, imm),
+		/* EA */ instr!(nop, imp),
+		/* EB */ instr!(sbc, imm, true),
+		/* EC */ instr!(cpx, abs),
+		/* ED */ instr!(sbc, abs),
+		/* EE */ instr!(inc, abs),
+		/* EF */ instr!(isc, abs, true),
+
+		/* F0 */ instr!(beq, rel),
+		/* F1 */ instr!(sbc, idy),
+		/* F2 */ instr!(jam, imp, true),
+		/* F3 */ instr!(isc, idy, true),
+		/* F4 */ instr!(nop, zpx, true),
+		/* F5 */ instr!(sbc, zpx),
+		/* F6 */ instr!(inc, zpx),
+		/* F7 */ instr!(isc, zpx, true),
+		/* F8 */ instr!(sed, imp),
+		/* F9 */ instr!(sbc, aby),
+		/* FA */ instr!(nop, imp, true),
+		/* FB */ instr!(isc, aby, true),
+		/* FC */ instr!(nop, abx, true),
+		/* FD */ instr!(sbc, abx),
+		/* FE */ instr!(inc, abx),
+		/* FF */ instr!(isc, abx, true),
 ];
\ No newline at end of file
@@ -0,0 +1,273 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset};
+use crate::nes::mappers::eeprom::{Eeprom, EepromKind};
+
+// Bandai FCG-1/FCG-2 and LZ93D50, mappers 16 and 159
+pub struct Bandai
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+
+	// The FCG chips decode their registers at $6000-$7FFF, the LZ93D50 at $8000-$FFFF
+	fcg_registers: bool,
+	lz93d50_registers: bool,
+
+	prg_bank: u8,
+	chr_banks: [u8; 8],
+	mirroring: Mirroring,
+
+	irq_enabled: bool,
+	irq_counter: u16,
+	irq_latch: u16,
+	irq_pending: bool,
+
+	eeprom: Option<Eeprom>
+}
+
+impl Bandai
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> Bandai
+	{
+		let (fcg_registers, lz93d50_registers) = match header.submapper
+		{
+			4 => (true, false),
+			5 => (false, true),
+			_ => (header.mapper == 16, true)
+		};
+
+		// Mapper 159 always has a 24C01 and submapper 5 a 24C02. Without a submapper only the
+		// battery flag tells the boards with an EEPROM apart from the ones without
+		let eeprom = match (header.mapper, header.submapper)
+		{
+			(159, _) 							=> Some(Eeprom::new(EepromKind::X24C01)),
+			(_, 5) 								=> Some(Eeprom::new(EepromKind::X24C02)),
+			(_, 0) if header.battery 			=> Some(Eeprom::new(EepromKind::X24C02)),
+			(_, _) 								=> None
+		};
+
+		Bandai
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+
+			fcg_registers,
+			lz93d50_registers,
+
+			prg_bank: 0,
+			chr_banks: [0; 8],
+			mirroring: header.mirroring,
+
+			irq_enabled: false,
+			irq_counter: 0,
+			irq_latch: 0,
+			irq_pending: false,
+
+			eeprom
+		}
+	}
+
+	fn write_register(&mut self, register: u16, val: u8)
+	{
+		match register
+		{
+			0x0..=0x7 => self.chr_banks[register as usize] = val,
+			0x8 => self.prg_bank = val & 0x0F,
+
+			0x9 => {
+				self.mirroring = match val & 0x03
+				{
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenLower,
+					_ => Mirroring::SingleScreenUpper
+				};
+			},
+
+			0xA => {
+				self.irq_enabled = (val & 0x01) == 0x01;
+				self.irq_pending = false;
+
+				// The LZ93D50 reloads its counter from the latch, the FCG chips are written directly
+				if self.lz93d50_registers
+				{
+					self.irq_counter = self.irq_latch;
+				}
+			},
+
+			0xB | 0xC => {
+				let target = match self.lz93d50_registers
+				{
+					true 	=> &mut self.irq_latch,
+					false 	=> &mut self.irq_counter
+				};
+
+				*target = match register
+				{
+					0xB => (*target & 0xFF00) | val as u16,
+					_ 	=> (*target & 0x00FF) | ((val as u16) << 8)
+				};
+			},
+
+			0xD => {
+				if let Some(eeprom) = &mut self.eeprom
+				{
+					eeprom.write_lines((val & 0x20) == 0x20, (val & 0x40) == 0x40);
+				}
+			},
+
+			_ => { }
+		}
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let bank = self.chr_banks[((addr >> 10) & 0x07) as usize];
+		bank_offset(self.chr.len(), 0x0400, bank as usize, addr)
+	}
+}
+
+impl Mapper for Bandai
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			// Only the EEPROM's data line can be read back
+			0x6000..=0x7FFF => match &self.eeprom
+			{
+				Some(eeprom) => (eeprom.output() as u8) << 4,
+				None => 0
+			},
+
+			0x8000..=0xBFFF => self.prg[bank_offset(self.prg.len(), 0x4000, self.prg_bank as usize, addr)],
+			0xC000..=0xFFFF => self.prg[bank_offset(self.prg.len(), 0x4000, (self.prg.len() / 0x4000).saturating_sub(1), addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.fcg_registers => self.write_register(addr & 0x0F, val),
+			0x8000..=0xFFFF if self.lz93d50_registers => self.write_register(addr & 0x0F, val),
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq_pending
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		if !self.irq_enabled
+		{
+			return;
+		}
+
+		if self.irq_counter == 0
+		{
+			self.irq_pending = true;
+		}
+
+		self.irq_counter = self.irq_counter.wrapping_sub(1);
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		self.eeprom.as_ref().map(|eeprom| eeprom.data())
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		if let Some(eeprom) = &mut self.eeprom
+		{
+			eeprom.load(data);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn bandai(mapper: u16, submapper: u8) -> Bandai
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.mapper = mapper;
+		header.submapper = submapper;
+
+		Bandai::new(&header, vec![0u8; 0x20000], vec![0u8; 0x20000])
+	}
+
+	#[test]
+	fn lz93d50_counts_down_from_the_latch()
+	{
+		let mut bandai = bandai(16, 5);
+
+		bandai.write_cpu(0x800B, 0x02);
+		bandai.write_cpu(0x800C, 0x00);
+		bandai.write_cpu(0x800A, 0x01);
+
+		bandai.cpu_clock();
+		bandai.cpu_clock();
+		assert!(!bandai.irq());
+
+		bandai.cpu_clock();
+		assert!(bandai.irq());
+
+		bandai.write_cpu(0x800A, 0x00);
+		assert!(!bandai.irq());
+	}
+
+	#[test]
+	fn fcg_writes_the_counter_directly()
+	{
+		let mut bandai = bandai(16, 4);
+
+		// Only the FCG registers at $6000 are decoded
+		bandai.write_cpu(0x800B, 0x10);
+		bandai.write_cpu(0x600B, 0x01);
+		bandai.write_cpu(0x600A, 0x01);
+
+		bandai.cpu_clock();
+		assert!(!bandai.irq());
+
+		bandai.cpu_clock();
+		assert!(bandai.irq());
+	}
+
+	#[test]
+	fn only_gives_eeprom_boards_a_save()
+	{
+		assert_eq!(bandai(159, 0).battery_data().map(|data| data.len()), Some(0x80));
+		assert_eq!(bandai(16, 5).battery_data().map(|data| data.len()), Some(0x100));
+		assert!(bandai(16, 4).battery_data().is_none());
+		assert!(bandai(16, 0).battery_data().is_none());
+	}
+}
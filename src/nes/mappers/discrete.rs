@@ -0,0 +1,226 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset};
+
+// Boards built from discrete logic chips, which all latch a single register written anywhere in $8000-$FFFF
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Board
+{
+	UxROM,
+	CNROM,
+	AxROM,
+	GxROM,
+	ColorDreams
+}
+
+pub struct Discrete
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+
+	board: Board,
+	bus_conflicts: bool,
+
+	prg_bank: usize,
+	chr_bank: usize,
+	mirroring: Mirroring
+}
+
+impl Discrete
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>, board: Board) -> Discrete
+	{
+		// NES 2.0 submapper 1 denotes a board without bus conflicts and submapper 2 one with
+		// AND-type conflicts, otherwise go with what most of the board's games were built on
+		let bus_conflicts = match header.submapper
+		{
+			1 => false,
+			2 => true,
+			_ => matches!(board, Board::UxROM | Board::CNROM | Board::GxROM)
+		};
+
+		let mirroring = match board
+		{
+			Board::AxROM => Mirroring::SingleScreenLower,
+			_ => header.mirroring
+		};
+
+		Discrete
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+
+			board,
+			bus_conflicts,
+
+			prg_bank: 0,
+			chr_bank: 0,
+			mirroring
+		}
+	}
+
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		match self.board
+		{
+			Board::UxROM => match addr
+			{
+				0x8000..=0xBFFF => bank_offset(self.prg.len(), 0x4000, self.prg_bank, addr),
+				_ => bank_offset(self.prg.len(), 0x4000, (self.prg.len() / 0x4000).saturating_sub(1), addr)
+			},
+
+			Board::CNROM => (addr as usize - 0x8000) % self.prg.len(),
+			_ => bank_offset(self.prg.len(), 0x8000, self.prg_bank, addr)
+		}
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		bank_offset(self.chr.len(), 0x2000, self.chr_bank, addr)
+	}
+}
+
+impl Mapper for Discrete
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x8000..=0xFFFF => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		if addr < 0x8000
+		{
+			return;
+		}
+
+		// The ROM drives the data bus at the same time as the CPU, the result is the AND of both
+		let val = match self.bus_conflicts
+		{
+			true 	=> val & self.prg[self.prg_offset(addr)],
+			false 	=> val
+		};
+
+		match self.board
+		{
+			Board::UxROM => self.prg_bank = val as usize,
+			Board::CNROM => self.chr_bank = val as usize,
+
+			Board::AxROM => {
+				self.prg_bank = (val & 0x07) as usize;
+				self.mirroring = match val & 0x10
+				{
+					0 => Mirroring::SingleScreenLower,
+					_ => Mirroring::SingleScreenUpper
+				};
+			},
+
+			Board::GxROM => {
+				self.prg_bank = ((val >> 4) & 0x03) as usize;
+				self.chr_bank = (val & 0x03) as usize;
+			},
+
+			Board::ColorDreams => {
+				self.prg_bank = (val & 0x03) as usize;
+				self.chr_bank = (val >> 4) as usize;
+			}
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM and 32K of CHR-ROM, every byte holds the number of its 16K or 8K bank
+	fn discrete(board: Board, submapper: u8) -> Discrete
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.submapper = submapper;
+
+		let prg = (0..8).flat_map(|bank| [bank; 0x4000]).collect();
+		let chr = (0..4).flat_map(|bank| [bank; 0x2000]).collect();
+
+		Discrete::new(&header, prg, chr, board)
+	}
+
+	#[test]
+	fn switches_uxrom_below_the_fixed_bank()
+	{
+		let mut uxrom = discrete(Board::UxROM, 1);
+
+		uxrom.write_cpu(0x8000, 5);
+		assert_eq!((uxrom.read_cpu(0x8000), uxrom.read_cpu(0xC000)), (5, 7));
+	}
+
+	#[test]
+	fn ands_writes_with_the_rom_on_bus_conflicts()
+	{
+		// The byte at $8000 is 0 in the first bank, the write is lost
+		let mut uxrom = discrete(Board::UxROM, 2);
+		uxrom.write_cpu(0x8000, 5);
+		assert_eq!(uxrom.read_cpu(0x8000), 0);
+
+		// The last bank holds 7 everywhere, the write comes through
+		uxrom.write_cpu(0xC000, 5);
+		assert_eq!(uxrom.read_cpu(0x8000), 5);
+	}
+
+	#[test]
+	fn switches_cnrom_chr()
+	{
+		let mut cnrom = discrete(Board::CNROM, 1);
+
+		cnrom.write_cpu(0x8000, 3);
+		assert_eq!(cnrom.read_ppu(0x1FFF), 3);
+	}
+
+	#[test]
+	fn selects_the_axrom_screen()
+	{
+		let mut axrom = discrete(Board::AxROM, 0);
+		assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+		axrom.write_cpu(0x8000, 0x12);
+		assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+		assert_eq!(axrom.read_cpu(0x8000), 4);
+	}
+
+	#[test]
+	fn splits_gxrom_and_color_dreams_registers()
+	{
+		let mut gxrom = discrete(Board::GxROM, 1);
+		gxrom.write_cpu(0x8000, 0x21);
+		assert_eq!((gxrom.read_cpu(0x8000), gxrom.read_ppu(0x0000)), (4, 1));
+
+		let mut color_dreams = discrete(Board::ColorDreams, 0);
+		color_dreams.write_cpu(0x8000, 0x21);
+		assert_eq!((color_dreams.read_cpu(0x8000), color_dreams.read_ppu(0x0000)), (2, 2));
+	}
+}
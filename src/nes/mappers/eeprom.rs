@@ -0,0 +1,355 @@
+use crate::nes::mappers::load_ram;
+
+// Serial EEPROMs found on Bandai FCG boards. Both are driven over an I2C-like two-wire bus,
+// but the 24C01 uses a simplified protocol without a device address and sends bits LSB first
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum EepromKind
+{
+	X24C01,
+	X24C02
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Mode
+{
+	Idle,
+	Device,
+	Address,
+	Write,
+	Read
+}
+
+pub struct Eeprom
+{
+	kind: EepromKind,
+	data: Vec<u8>,
+
+	mode: Mode,
+	bit: u8,
+	shift: u8,
+	addr: u8,
+
+	scl: bool,
+	sda: bool,
+	output: bool
+}
+
+impl Eeprom
+{
+	pub fn new(kind: EepromKind) -> Eeprom
+	{
+		let size = match kind
+		{
+			EepromKind::X24C01 => 0x80,
+			EepromKind::X24C02 => 0x100
+		};
+
+		Eeprom
+		{
+			kind,
+			data: vec![0u8; size],
+
+			mode: Mode::Idle,
+			bit: 0,
+			shift: 0,
+			addr: 0,
+
+			scl: false,
+			sda: false,
+			output: true
+		}
+	}
+
+	pub fn data(&self) -> &[u8]
+	{
+		&self.data
+	}
+
+	pub fn load(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.data, data);
+	}
+
+	pub fn output(&self) -> bool
+	{
+		self.output
+	}
+
+	pub fn write_lines(&mut self, scl: bool, sda: bool)
+	{
+		if self.scl && scl && self.sda != sda
+		{
+			// Changing SDA while SCL is high signals a start (falling) or stop (rising) condition
+			match sda
+			{
+				false => self.start(),
+				true => {
+					self.mode = Mode::Idle;
+					self.output = true;
+				}
+			}
+		}
+		else if !self.scl && scl
+		{
+			self.clock(sda);
+		}
+
+		self.scl = scl;
+		self.sda = sda;
+	}
+
+	fn start(&mut self)
+	{
+		self.mode = match self.kind
+		{
+			EepromKind::X24C01 => Mode::Address,
+			EepromKind::X24C02 => Mode::Device
+		};
+
+		self.bit = 0;
+		self.shift = 0;
+		self.output = true;
+	}
+
+	fn data_bit(&self, val: u8, bit: u8) -> bool
+	{
+		match self.kind
+		{
+			EepromKind::X24C01 => (val >> bit) & 0x01 == 0x01,
+			EepromKind::X24C02 => (val >> (7 - bit)) & 0x01 == 0x01
+		}
+	}
+
+	fn shift_in(&mut self, sda: bool)
+	{
+		match self.kind
+		{
+			EepromKind::X24C01 => self.shift |= (sda as u8) << self.bit,
+			EepromKind::X24C02 => self.shift = (self.shift << 1) | sda as u8
+		}
+	}
+
+	// Handles a rising edge on SCL, the ninth clock of every byte is the acknowledge
+	fn clock(&mut self, sda: bool)
+	{
+		if self.mode == Mode::Idle
+		{
+			return;
+		}
+
+		if self.mode == Mode::Read
+		{
+			match self.bit
+			{
+				0..=7 => {
+					self.output = self.data_bit(self.data[self.addr as usize], self.bit);
+					self.bit += 1;
+				},
+
+				// The master acknowledges every byte it wants to keep reading
+				_ => {
+					self.output = true;
+					self.bit = 0;
+					self.addr = self.addr.wrapping_add(1) & (self.data.len() - 1) as u8;
+
+					if sda
+					{
+						self.mode = Mode::Idle;
+					}
+				}
+			}
+
+			return;
+		}
+
+		if self.bit < 8
+		{
+			self.output = true;
+			self.shift_in(sda);
+			self.bit += 1;
+
+			return;
+		}
+
+		let byte = self.shift;
+		self.output = false;
+		self.bit = 0;
+		self.shift = 0;
+
+		self.mode = match (self.mode, self.kind)
+		{
+			(Mode::Device, _) if (byte & 0xF0) != 0xA0 => {
+				self.output = true;
+				Mode::Idle
+			},
+
+			(Mode::Device, _) => match byte & 0x01
+			{
+				0 => Mode::Address,
+				_ => Mode::Read
+			},
+
+			(Mode::Address, EepromKind::X24C01) => {
+				self.addr = byte & 0x7F;
+				match byte & 0x80
+				{
+					0 => Mode::Write,
+					_ => Mode::Read
+				}
+			},
+
+			(Mode::Address, EepromKind::X24C02) => {
+				self.addr = byte;
+				Mode::Write
+			},
+
+			_ => {
+				self.data[self.addr as usize] = byte;
+
+				// Writes wrap around inside a page of 4 (24C01) or 8 (24C02) bytes
+				let page_mask = match self.kind
+				{
+					EepromKind::X24C01 => 0x03,
+					EepromKind::X24C02 => 0x07
+				};
+
+				self.addr = (self.addr & !page_mask) | (self.addr.wrapping_add(1) & page_mask);
+				Mode::Write
+			}
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// SDA falls while SCL is high
+	fn start(eeprom: &mut Eeprom)
+	{
+		eeprom.write_lines(false, true);
+		eeprom.write_lines(true, true);
+		eeprom.write_lines(true, false);
+		eeprom.write_lines(false, false);
+	}
+
+	// SDA rises while SCL is high
+	fn stop(eeprom: &mut Eeprom)
+	{
+		eeprom.write_lines(false, false);
+		eeprom.write_lines(true, false);
+		eeprom.write_lines(true, true);
+	}
+
+	fn clock(eeprom: &mut Eeprom, sda: bool)
+	{
+		eeprom.write_lines(false, sda);
+		eeprom.write_lines(true, sda);
+		eeprom.write_lines(false, sda);
+	}
+
+	// Sends a byte, which the EEPROM has to acknowledge
+	fn send(eeprom: &mut Eeprom, byte: u8)
+	{
+		for bit in 0..8
+		{
+			let sda = match eeprom.kind
+			{
+				EepromKind::X24C01 => (byte >> bit) & 0x01 == 0x01,
+				EepromKind::X24C02 => (byte >> (7 - bit)) & 0x01 == 0x01
+			};
+
+			clock(eeprom, sda);
+		}
+
+		eeprom.write_lines(false, true);
+		eeprom.write_lines(true, true);
+		assert!(!eeprom.output(), "${:02X} was not acknowledged", byte);
+		eeprom.write_lines(false, true);
+	}
+
+	// Reads a byte, the last one is not acknowledged
+	fn receive(eeprom: &mut Eeprom, last: bool) -> u8
+	{
+		let mut byte = 0;
+		for bit in 0..8
+		{
+			eeprom.write_lines(false, true);
+			eeprom.write_lines(true, true);
+
+			let shift = match eeprom.kind
+			{
+				EepromKind::X24C01 => bit,
+				EepromKind::X24C02 => 7 - bit
+			};
+
+			byte |= (eeprom.output() as u8) << shift;
+			eeprom.write_lines(false, true);
+		}
+
+		clock(eeprom, last);
+		byte
+	}
+
+	#[test]
+	fn writes_and_reads_the_24c02()
+	{
+		let mut eeprom = Eeprom::new(EepromKind::X24C02);
+
+		start(&mut eeprom);
+		send(&mut eeprom, 0xA0);
+		send(&mut eeprom, 0x10);
+		send(&mut eeprom, 0x5A);
+		send(&mut eeprom, 0x3C);
+		stop(&mut eeprom);
+
+		assert_eq!(&eeprom.data()[0x10..0x12], &[0x5A, 0x3C]);
+
+		// A write of just the address, then a repeated start to read from there
+		start(&mut eeprom);
+		send(&mut eeprom, 0xA0);
+		send(&mut eeprom, 0x10);
+		start(&mut eeprom);
+		send(&mut eeprom, 0xA1);
+
+		assert_eq!(receive(&mut eeprom, false), 0x5A);
+		assert_eq!(receive(&mut eeprom, true), 0x3C);
+		stop(&mut eeprom);
+	}
+
+	#[test]
+	fn ignores_other_devices()
+	{
+		let mut eeprom = Eeprom::new(EepromKind::X24C02);
+
+		start(&mut eeprom);
+		for _ in 0..9
+		{
+			clock(&mut eeprom, true);
+		}
+
+		assert!(eeprom.output());
+		assert!(eeprom.mode == Mode::Idle);
+	}
+
+	#[test]
+	fn writes_and_reads_the_24c01()
+	{
+		let mut eeprom = Eeprom::new(EepromKind::X24C01);
+
+		// The address byte carries the read bit, writes wrap around inside a page of 4 bytes
+		start(&mut eeprom);
+		send(&mut eeprom, 0x03);
+		send(&mut eeprom, 0x77);
+		send(&mut eeprom, 0x88);
+		stop(&mut eeprom);
+
+		assert_eq!((eeprom.data()[0x00], eeprom.data()[0x03], eeprom.data()[0x04]), (0x88, 0x77, 0x00));
+
+		start(&mut eeprom);
+		send(&mut eeprom, 0x83);
+		assert_eq!(receive(&mut eeprom, true), 0x77);
+		stop(&mut eeprom);
+	}
+}
@@ -0,0 +1,641 @@
+use crate::nes::cartridge::Mirroring;
+use crate::nes::mappers::Mapper;
+use crate::nes::patch;
+
+pub const SIDE_SIZE: usize = 65500;
+
+// A byte passes under the head about every 150 CPU cycles, and it takes a while after the head returns to the start
+const BYTE_CYCLES: u32 = 150;
+const REWIND_CYCLES: u32 = 50000;
+
+// Long enough for the BIOS to notice the disk was ejected before the next one goes in
+const INSERT_CYCLES: u32 = 1000000;
+
+// Gaps in bytes, before the first block and between blocks
+const LEAD_IN: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+
+// Famicom Disk System RAM adapter. Disk sides are kept the way the drive sees them, with the gaps,
+// block start marks and CRCs that the .fds format leaves out
+pub struct FDS
+{
+	bios: Vec<u8>,
+	prg_ram: Vec<u8>,
+	chr_ram: Vec<u8>,
+
+	// The sides as they are in the .fds image
+	original_sides: Vec<Vec<u8>>,
+	sides: Vec<Vec<u8>>,
+
+	// IPS patch from the original .fds sides to the current disk in the same layout, so other tools can apply
+	// it to the image. Refreshed whenever the drive stops writing
+	diff: Vec<u8>,
+	dirty: bool,
+
+	disk: Option<usize>,
+	insert_delay: u32,
+
+	disk_registers_enabled: bool,
+
+	timer_reload: u16,
+	timer_counter: u16,
+	timer_repeat: bool,
+	timer_enabled: bool,
+	timer_irq: bool,
+
+	motor_on: bool,
+	reset_transfer: bool,
+	read_mode: bool,
+	mirroring: Mirroring,
+	crc_control: bool,
+	transfer_start: bool,
+	disk_irq_enabled: bool,
+
+	disk_irq: bool,
+	transfer_complete: bool,
+	read_data: u8,
+	write_data: u8,
+
+	position: usize,
+	delay: u32,
+	end_of_head: bool,
+	scanning: bool,
+	gap_ended: bool,
+	previous_crc_control: bool,
+	crc: u16
+}
+
+// FDS images may start with a 16 byte fwNES header, the sides themselves are always 65500 bytes
+pub fn is_disk_image(data: &[u8]) -> bool
+{
+	data.starts_with(b"FDS\x1A") || data.starts_with(b"\x01*NINTENDO-HVC*")
+}
+
+pub fn split_sides(data: &[u8]) -> Vec<&[u8]>
+{
+	let data = match data.starts_with(b"FDS\x1A")
+	{
+		true 	=> &data[16.min(data.len())..],
+		false 	=> data
+	};
+
+	data.chunks_exact(SIDE_SIZE).collect()
+}
+
+fn update_crc(crc: u16, val: u8) -> u16
+{
+	let mut crc = crc;
+
+	for bit in 0..8
+	{
+		let carry = (crc & 0x01) == 0x01;
+		crc >>= 1;
+
+		if carry
+		{
+			crc ^= 0x8408;
+		}
+
+		if (val >> bit) & 0x01 == 0x01
+		{
+			crc ^= 0x8000;
+		}
+	}
+
+	crc
+}
+
+// Adds the lead-in, the start mark and CRC of every block and the gaps between them.
+// Parsing stops at the first invalid block type, which marks the end of the used space
+fn add_gaps(side: &[u8]) -> Vec<u8>
+{
+	let mut raw = vec![0u8; LEAD_IN];
+	let mut offset = 0;
+
+	while offset < side.len()
+	{
+		let len = match side[offset]
+		{
+			1 => 56,
+			2 => 2,
+			3 => 16,
+			4 if offset >= 3 => 1 + (side[offset - 3] as usize | (side[offset - 2] as usize) << 8),
+			_ => break
+		};
+
+		let block = &side[offset..(offset + len).min(side.len())];
+		let crc = [0x80].iter().chain(block).chain(&[0, 0]).fold(0, |crc, &val| update_crc(crc, val));
+
+		raw.push(0x80);
+		raw.extend_from_slice(block);
+		raw.extend_from_slice(&crc.to_le_bytes());
+		raw.resize(raw.len() + BLOCK_GAP, 0);
+
+		offset += len;
+	}
+
+	raw.resize(raw.len().max(LEAD_IN + SIDE_SIZE), 0);
+	raw
+}
+
+// Takes a side the drive wrote back to the .fds layout, the blocks are found by their start marks
+// after the gaps. Anything after the last valid block is left empty like in the image
+fn strip_gaps(raw: &[u8]) -> Vec<u8>
+{
+	let mut side = Vec::with_capacity(SIDE_SIZE);
+	let mut offset = LEAD_IN;
+	let mut file_size = None;
+
+	while let Some(start) = raw[offset.min(raw.len())..].iter().position(|&val| val != 0)
+	{
+		let mark = offset + start;
+		if raw[mark] != 0x80
+		{
+			break;
+		}
+
+		let len = match raw.get(mark + 1)
+		{
+			Some(1) => 56,
+			Some(2) => 2,
+			Some(3) => 16,
+			Some(4) => match file_size.take()
+			{
+				Some(size) => 1 + size,
+				None => break
+			},
+			_ => break
+		};
+
+		let block = &raw[mark + 1..(mark + 1 + len).min(raw.len())];
+		if block[0] == 3 && block.len() == 16
+		{
+			file_size = Some(block[13] as usize | (block[14] as usize) << 8);
+		}
+
+		side.extend_from_slice(block);
+		offset = mark + 1 + len + 2;
+	}
+
+	side.resize(SIDE_SIZE, 0);
+	side
+}
+
+impl FDS
+{
+	pub fn new(bios: Vec<u8>, sides: Vec<&[u8]>) -> FDS
+	{
+		FDS
+		{
+			bios,
+			prg_ram: vec![0u8; 0x8000],
+			chr_ram: vec![0u8; 0x2000],
+
+			original_sides: sides.iter().map(|side| side.to_vec()).collect(),
+			sides: sides.iter().map(|side| add_gaps(side)).collect(),
+
+			diff: b"PATCHEOF".to_vec(),
+			dirty: false,
+
+			disk: match sides.is_empty()
+			{
+				true 	=> None,
+				false 	=> Some(0)
+			},
+
+			insert_delay: 0,
+
+			disk_registers_enabled: false,
+
+			timer_reload: 0,
+			timer_counter: 0,
+			timer_repeat: false,
+			timer_enabled: false,
+			timer_irq: false,
+
+			motor_on: false,
+			reset_transfer: false,
+			read_mode: true,
+			mirroring: Mirroring::Horizontal,
+			crc_control: false,
+			transfer_start: false,
+			disk_irq_enabled: false,
+
+			disk_irq: false,
+			transfer_complete: false,
+			read_data: 0,
+			write_data: 0,
+
+			position: 0,
+			delay: 0,
+			end_of_head: true,
+			scanning: false,
+			gap_ended: false,
+			previous_crc_control: false,
+			crc: 0
+		}
+	}
+
+	fn disk_inserted(&self) -> bool
+	{
+		self.disk.is_some() && self.insert_delay == 0
+	}
+
+	fn update_diff(&mut self)
+	{
+		if self.dirty
+		{
+			let modified: Vec<u8> = self.sides.iter().flat_map(|side| strip_gaps(side)).collect();
+			match patch::create_ips(&self.original_sides.concat(), &modified)
+			{
+				Ok(diff) 	=> self.diff = diff,
+				Err(err) 	=> eprintln!("Failed to save the disk changes: {}", err)
+			}
+
+			self.dirty = false;
+		}
+	}
+
+	fn clock_timer(&mut self)
+	{
+		if !self.timer_enabled
+		{
+			return;
+		}
+
+		if self.timer_counter == 0
+		{
+			self.timer_irq = true;
+			self.timer_counter = self.timer_reload;
+
+			if !self.timer_repeat
+			{
+				self.timer_enabled = false;
+			}
+		}
+		else
+		{
+			self.timer_counter -= 1;
+		}
+	}
+
+	// Moves the head along by one byte, reading it into or writing it from the data registers
+	fn transfer_byte(&mut self, disk: usize)
+	{
+		let mut val = 0;
+		let mut raise_irq = self.disk_irq_enabled;
+
+		if self.read_mode
+		{
+			val = self.sides[disk][self.position];
+			if !self.previous_crc_control
+			{
+				self.crc = update_crc(self.crc, val);
+			}
+
+			// Data starts with the first non-zero byte after the gap, which is the block's start mark
+			if !self.transfer_start
+			{
+				self.gap_ended = false;
+				self.crc = 0;
+			}
+			else if val != 0 && !self.gap_ended
+			{
+				self.gap_ended = true;
+				raise_irq = false;
+			}
+
+			if self.gap_ended
+			{
+				self.transfer_complete = true;
+				self.read_data = val;
+				self.disk_irq |= raise_irq;
+			}
+		}
+		else
+		{
+			if !self.crc_control
+			{
+				self.transfer_complete = true;
+				val = self.write_data;
+				self.disk_irq |= raise_irq;
+			}
+
+			if !self.transfer_start
+			{
+				val = 0;
+			}
+
+			// With CRC control set the drive writes out the checksum of the block instead of the data register
+			if !self.crc_control
+			{
+				self.crc = update_crc(self.crc, val);
+			}
+			else
+			{
+				if !self.previous_crc_control
+				{
+					self.crc = update_crc(update_crc(self.crc, 0), 0);
+				}
+
+				val = self.crc as u8;
+				self.crc >>= 8;
+			}
+
+			self.sides[disk][self.position] = val;
+			self.dirty = true;
+			self.gap_ended = false;
+		}
+
+		self.previous_crc_control = self.crc_control;
+		self.position += 1;
+
+		if self.position >= self.sides[disk].len()
+		{
+			self.motor_on = false;
+			self.end_of_head = true;
+			self.update_diff();
+		}
+		else
+		{
+			self.delay = BYTE_CYCLES;
+		}
+	}
+}
+
+impl Mapper for FDS
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			// Reading the status acknowledges both interrupts
+			0x4030 => {
+				let val = self.timer_irq as u8
+					| (self.transfer_complete as u8) << 1
+					| (self.end_of_head as u8) << 6;
+
+				self.timer_irq = false;
+				self.disk_irq = false;
+				self.transfer_complete = false;
+
+				val
+			},
+
+			0x4031 => {
+				self.transfer_complete = false;
+				self.disk_irq = false;
+
+				self.read_data
+			},
+
+			// Bit 6 is open bus, which usually holds the $40 of the address
+			0x4032 => {
+				let inserted = self.disk_inserted();
+
+				0x40 | (!inserted) as u8
+					| ((!inserted || !self.scanning) as u8) << 1
+					| ((!inserted) as u8) << 2
+			},
+
+			// Bit 7 reports the battery as good, the rest is the unconnected expansion port
+			0x4033 => 0x80,
+
+			0x6000..=0xDFFF => self.prg_ram[(addr - 0x6000) as usize],
+			0xE000..=0xFFFF => self.bios[(addr - 0xE000) as usize],
+			_ => 0
+		}
+	}
+
+	// The sound registers at $4040-$4097 are accepted but the wavetable channel is not emulated
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x4023 => {
+				self.disk_registers_enabled = (val & 0x01) == 0x01;
+				if !self.disk_registers_enabled
+				{
+					self.timer_enabled = false;
+					self.timer_irq = false;
+					self.disk_irq = false;
+				}
+			},
+
+			0x4020..=0x4026 if !self.disk_registers_enabled => { },
+
+			0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | val as u16,
+			0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | ((val as u16) << 8),
+
+			0x4022 => {
+				self.timer_repeat = (val & 0x01) == 0x01;
+				self.timer_enabled = (val & 0x02) == 0x02;
+
+				match self.timer_enabled
+				{
+					true 	=> self.timer_counter = self.timer_reload,
+					false 	=> self.timer_irq = false
+				};
+			},
+
+			0x4024 => {
+				self.write_data = val;
+				self.transfer_complete = false;
+				self.disk_irq = false;
+			},
+
+			0x4025 => {
+				self.motor_on = (val & 0x01) == 0x01;
+				self.reset_transfer = (val & 0x02) == 0x02;
+				self.read_mode = (val & 0x04) == 0x04;
+				self.mirroring = match val & 0x08
+				{
+					0 => Mirroring::Vertical,
+					_ => Mirroring::Horizontal
+				};
+				self.crc_control = (val & 0x10) == 0x10;
+				self.transfer_start = (val & 0x40) == 0x40;
+				self.disk_irq_enabled = (val & 0x80) == 0x80;
+				self.disk_irq = false;
+
+				if self.read_mode || !self.motor_on
+				{
+					self.update_diff();
+				}
+			},
+
+			0x6000..=0xDFFF => self.prg_ram[(addr - 0x6000) as usize] = val,
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr_ram[(addr & 0x1FFF) as usize]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		self.chr_ram[(addr & 0x1FFF) as usize] = val;
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.timer_irq || self.disk_irq
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		self.clock_timer();
+
+		if self.insert_delay > 0
+		{
+			self.insert_delay -= 1;
+			return;
+		}
+
+		let disk = match self.disk
+		{
+			Some(disk) if self.motor_on => disk,
+			_ => {
+				self.end_of_head = true;
+				self.scanning = false;
+				return;
+			}
+		};
+
+		if self.reset_transfer && !self.scanning
+		{
+			return;
+		}
+
+		// The head went back to the start of the disk, which takes a moment before data shows up
+		if self.end_of_head
+		{
+			self.delay = REWIND_CYCLES;
+			self.end_of_head = false;
+			self.position = 0;
+			self.gap_ended = false;
+			return;
+		}
+
+		if self.delay > 0
+		{
+			self.delay -= 1;
+			return;
+		}
+
+		self.scanning = true;
+		self.transfer_byte(disk);
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		Some(&self.diff)
+	}
+
+	// The save is the diff of the disk, a patch that doesn't fit the disk is ignored
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		let modified = match patch::apply(&self.original_sides.concat(), data)
+		{
+			Ok(modified) => modified,
+			Err(err) => {
+				eprintln!("Failed to apply the saved disk changes: {}", err);
+				return;
+			}
+		};
+
+		for (side, data) in self.sides.iter_mut().zip(modified.chunks_exact(SIDE_SIZE))
+		{
+			*side = add_gaps(data);
+		}
+
+		self.diff = data.to_vec();
+	}
+
+	fn disk_sides(&self) -> usize
+	{
+		self.sides.len()
+	}
+
+	fn insert_disk(&mut self, side: Option<usize>)
+	{
+		self.update_diff();
+
+		self.disk = side.filter(|&side| side < self.sides.len());
+		self.insert_delay = INSERT_CYCLES;
+		self.motor_on = false;
+		self.end_of_head = true;
+		self.scanning = false;
+	}
+
+	fn current_disk(&self) -> Option<usize>
+	{
+		self.disk
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn side() -> Vec<u8>
+	{
+		let mut side = vec![0u8; 56];
+		side[0] = 1;
+		side[1..15].copy_from_slice(b"*NINTENDO-HVC*");
+
+		side.extend_from_slice(&[2, 1]);
+
+		let mut header = [0u8; 16];
+		header[0] = 3;
+		header[13] = 4;
+		side.extend_from_slice(&header);
+		side.extend_from_slice(&[4, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+		side.resize(SIDE_SIZE, 0);
+		side
+	}
+
+	#[test]
+	fn gaps_come_back_out()
+	{
+		let side = side();
+		let raw = add_gaps(&side);
+
+		assert_eq!(raw[LEAD_IN], 0x80);
+		assert_eq!(raw[LEAD_IN + 1 + 56 + 2 + BLOCK_GAP], 0x80);
+		assert_eq!(strip_gaps(&raw), side);
+	}
+
+	#[test]
+	fn saves_patch_the_image()
+	{
+		let side = side();
+		let mut fds = FDS::new(vec![0; 0x2000], vec![&side]);
+		assert_eq!(fds.battery_data(), Some(&b"PATCHEOF"[..]));
+
+		// Change the last byte of the file as if the drive had written it
+		let file_data = LEAD_IN + (1 + 56 + 2 + BLOCK_GAP) + (1 + 2 + 2 + BLOCK_GAP) + (1 + 16 + 2 + BLOCK_GAP) + 1 + 4;
+		fds.sides[0][file_data] = 0x42;
+		fds.dirty = true;
+		fds.update_diff();
+
+		let mut modified = side.clone();
+		modified[56 + 2 + 16 + 4] = 0x42;
+
+		let diff = fds.battery_data().unwrap().to_vec();
+		assert_eq!(patch::apply(&side, &diff).unwrap(), modified);
+
+		let mut reloaded = FDS::new(vec![0; 0x2000], vec![&side]);
+		reloaded.load_battery_data(&diff);
+		assert_eq!(reloaded.sides, fds.sides.iter().map(|side| add_gaps(&strip_gaps(side))).collect::<Vec<_>>());
+	}
+}
@@ -0,0 +1,236 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+// Sunsoft FME-7 and 5B, mapper 69. The 5B's audio registers at $C000/$E000 are accepted but not emulated
+pub struct FME7
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	command: u8,
+	chr_banks: [u8; 8],
+	prg_banks: [u8; 4],
+	mirroring: Mirroring,
+
+	irq_enabled: bool,
+	irq_counter_enabled: bool,
+	irq_counter: u16,
+	irq_pending: bool
+}
+
+impl FME7
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> FME7
+	{
+		FME7
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			command: 0,
+			chr_banks: [0; 8],
+			prg_banks: [0; 4],
+			mirroring: header.mirroring,
+
+			irq_enabled: false,
+			irq_counter_enabled: false,
+			irq_counter: 0,
+			irq_pending: false
+		}
+	}
+
+	fn write_parameter(&mut self, val: u8)
+	{
+		match self.command
+		{
+			0x0..=0x7 => self.chr_banks[self.command as usize] = val,
+			0x8..=0xB => self.prg_banks[(self.command - 0x8) as usize] = val,
+
+			0xC => {
+				self.mirroring = match val & 0x03
+				{
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenLower,
+					_ => Mirroring::SingleScreenUpper
+				};
+			},
+
+			0xD => {
+				self.irq_enabled = (val & 0x01) == 0x01;
+				self.irq_counter_enabled = (val & 0x80) == 0x80;
+				self.irq_pending = false;
+			},
+
+			0xE => self.irq_counter = (self.irq_counter & 0xFF00) | val as u16,
+			_ 	=> self.irq_counter = (self.irq_counter & 0x00FF) | ((val as u16) << 8)
+		}
+	}
+
+	// Bank register 8 maps either ROM or RAM into $6000-$7FFF, RAM only answers if enabled
+	fn read_low_bank(&self, addr: u16) -> u8
+	{
+		let register = self.prg_banks[0];
+
+		match (register & 0x40, register & 0x80)
+		{
+			(0, _) 								=> self.prg[bank_offset(self.prg.len(), 0x2000, (register & 0x3F) as usize, addr)],
+			(_, 0) 								=> 0,
+			_ if self.prg_ram.is_empty() 		=> 0,
+			_ 									=> self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, (register & 0x3F) as usize, addr)]
+		}
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let bank = self.chr_banks[((addr >> 10) & 0x07) as usize];
+		bank_offset(self.chr.len(), 0x0400, bank as usize, addr)
+	}
+}
+
+impl Mapper for FME7
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		let bank = match addr
+		{
+			0x6000..=0x7FFF => return self.read_low_bank(addr),
+			0x8000..=0x9FFF => self.prg_banks[1] as usize,
+			0xA000..=0xBFFF => self.prg_banks[2] as usize,
+			0xC000..=0xDFFF => self.prg_banks[3] as usize,
+			0xE000..=0xFFFF => (self.prg.len() / 0x2000).saturating_sub(1),
+			_ => return 0
+		};
+
+		self.prg[bank_offset(self.prg.len(), 0x2000, bank & 0x3F, addr)]
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if (self.prg_banks[0] & 0xC0) == 0xC0 && !self.prg_ram.is_empty() => {
+				let offset = bank_offset(self.prg_ram.len(), 0x2000, (self.prg_banks[0] & 0x3F) as usize, addr);
+				self.prg_ram[offset] = val;
+			},
+
+			0x8000..=0x9FFF => self.command = val & 0x0F,
+			0xA000..=0xBFFF => self.write_parameter(val),
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq_pending
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		if !self.irq_counter_enabled
+		{
+			return;
+		}
+
+		self.irq_counter = self.irq_counter.wrapping_sub(1);
+		if self.irq_counter == 0xFFFF && self.irq_enabled
+		{
+			self.irq_pending = true;
+		}
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM with 8K of PRG-RAM, every byte of PRG-ROM holds the number of its 8K bank
+	fn fme7() -> FME7
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x00\x50\x40\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.prg_ram_size = 0x2000;
+
+		let prg = (0..16).flat_map(|bank| [bank; 0x2000]).collect();
+		FME7::new(&header, prg, vec![0u8; 0x2000])
+	}
+
+	fn command(fme7: &mut FME7, command: u8, val: u8)
+	{
+		fme7.write_cpu(0x8000, command);
+		fme7.write_cpu(0xA000, val);
+	}
+
+	#[test]
+	fn maps_rom_or_ram_at_6000()
+	{
+		let mut fme7 = fme7();
+
+		command(&mut fme7, 0x8, 0x05);
+		assert_eq!(fme7.read_cpu(0x6000), 5);
+
+		// RAM that isn't enabled reads as open bus, and ignores writes
+		command(&mut fme7, 0x8, 0x40);
+		fme7.write_cpu(0x6000, 0x42);
+		assert_eq!(fme7.read_cpu(0x6000), 0);
+
+		command(&mut fme7, 0x8, 0xC0);
+		fme7.write_cpu(0x6000, 0x42);
+		assert_eq!(fme7.read_cpu(0x6000), 0x42);
+	}
+
+	#[test]
+	fn fires_when_the_counter_wraps()
+	{
+		let mut fme7 = fme7();
+
+		command(&mut fme7, 0xE, 0x01);
+		command(&mut fme7, 0xF, 0x00);
+		command(&mut fme7, 0xD, 0x81);
+
+		fme7.cpu_clock();
+		assert!(!fme7.irq());
+
+		fme7.cpu_clock();
+		assert!(fme7.irq());
+
+		command(&mut fme7, 0xD, 0x81);
+		assert!(!fme7.irq());
+	}
+}
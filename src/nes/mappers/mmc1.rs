@@ -0,0 +1,306 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+pub struct MMC1
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	shift: u8,
+	shift_count: u8,
+
+	// CPU cycles seen so far and the one the last register write happened on
+	cycle: u64,
+	last_write: Option<u64>,
+
+	control: u8,
+	chr_bank_0: u8,
+	chr_bank_1: u8,
+	prg_bank: u8
+}
+
+impl MMC1
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> MMC1
+	{
+		MMC1
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			shift: 0,
+			shift_count: 0,
+
+			cycle: 0,
+			last_write: None,
+
+			// The board powers up in 16K PRG mode with the last bank fixed at $C000
+			control: 0x0C,
+			chr_bank_0: 0,
+			chr_bank_1: 0,
+			prg_bank: 0
+		}
+	}
+
+	fn write_register(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x8000..=0x9FFF => self.control = val,
+			0xA000..=0xBFFF => self.chr_bank_0 = val,
+			0xC000..=0xDFFF => self.chr_bank_1 = val,
+			_ 				=> self.prg_bank = val
+		}
+	}
+
+	// The CHR bank registers double as outer bank selects on the larger SxROM boards.
+	// Bit 4 picks the 256K PRG half on SUROM, bits 2-3 pick the 8K PRG-RAM bank on SOROM/SXROM
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		let outer = (self.chr_bank_0 & 0x10) as usize;
+		let bank = (self.prg_bank & 0x0F) as usize;
+
+		let bank_16k = match (self.control >> 2) & 0x03
+		{
+			0 | 1 => (bank & 0x0E) | ((addr as usize >> 14) & 0x01),
+			2 => match addr { 0x8000..=0xBFFF => 0, _ => bank },
+			_ => match addr { 0x8000..=0xBFFF => bank, _ => 0x0F }
+		};
+
+		bank_offset(self.prg.len(), 0x4000, outer | bank_16k, addr)
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		match self.control & 0x10
+		{
+			0 => bank_offset(self.chr.len(), 0x2000, (self.chr_bank_0 >> 1) as usize, addr),
+			_ => match addr
+			{
+				0x0000..=0x0FFF => bank_offset(self.chr.len(), 0x1000, self.chr_bank_0 as usize, addr),
+				_ 				=> bank_offset(self.chr.len(), 0x1000, self.chr_bank_1 as usize, addr)
+			}
+		}
+	}
+
+	fn prg_ram_offset(&self, addr: u16) -> usize
+	{
+		let bank = ((self.chr_bank_0 >> 2) & 0x03) as usize;
+		bank_offset(self.prg_ram.len(), 0x2000, bank, addr)
+	}
+
+	fn prg_ram_enabled(&self) -> bool
+	{
+		!self.prg_ram.is_empty() && (self.prg_bank & 0x10) == 0
+	}
+}
+
+impl Mapper for MMC1
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[self.prg_ram_offset(addr)],
+			0x8000..=0xFFFF => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.prg_ram_enabled() => {
+				let offset = self.prg_ram_offset(addr);
+				self.prg_ram[offset] = val;
+			},
+
+			0x8000..=0xFFFF => {
+				// The serial port ignores a write on the cycle right after another one, so of the two
+				// writes made by a read-modify-write instruction only the first one counts
+				let consecutive = self.last_write.is_some_and(|cycle| cycle + 1 == self.cycle);
+				self.last_write = Some(self.cycle);
+
+				if consecutive
+				{
+					return;
+				}
+
+				// Writing a value with bit 7 set resets the shift register and locks the
+				// PRG mode to 16K with the last bank fixed
+				if (val & 0x80) == 0x80
+				{
+					self.shift = 0;
+					self.shift_count = 0;
+					self.control |= 0x0C;
+
+					return;
+				}
+
+				self.shift |= (val & 0x01) << self.shift_count;
+				self.shift_count += 1;
+
+				// The register is selected by the address of the fifth write
+				if self.shift_count == 5
+				{
+					self.write_register(addr, self.shift);
+
+					self.shift = 0;
+					self.shift_count = 0;
+				}
+			},
+
+			_ => { }
+		}
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		self.cycle += 1;
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		match self.control & 0x03
+		{
+			0 => Mirroring::SingleScreenLower,
+			1 => Mirroring::SingleScreenUpper,
+			2 => Mirroring::Vertical,
+			_ => Mirroring::Horizontal
+		}
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM and 32K of CHR-ROM, every byte holds the number of its 16K or 4K bank
+	fn mmc1() -> MMC1
+	{
+		let header = Header::parse(b"NES\x1A\x08\x04\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+		let prg = (0..8).flat_map(|bank| [bank; 0x4000]).collect();
+		let chr = (0..8).flat_map(|bank| [bank; 0x1000]).collect();
+
+		MMC1::new(&header, prg, chr)
+	}
+
+	// Shifts a value into the register at addr, lowest bit first
+	fn write_serial(mmc1: &mut MMC1, addr: u16, val: u8)
+	{
+		for bit in 0..5
+		{
+			mmc1.cpu_clock();
+			mmc1.cpu_clock();
+			mmc1.write_cpu(addr, val >> bit);
+		}
+	}
+
+	#[test]
+	fn takes_five_writes_to_load_a_register()
+	{
+		let mut mmc1 = mmc1();
+
+		for bit in 0..4
+		{
+			mmc1.cpu_clock();
+			mmc1.cpu_clock();
+			mmc1.write_cpu(0xE000, 0x03 >> bit);
+		}
+
+		assert_eq!(mmc1.read_cpu(0x8000), 0);
+
+		mmc1.cpu_clock();
+		mmc1.cpu_clock();
+		mmc1.write_cpu(0xE000, 0);
+
+		assert_eq!(mmc1.read_cpu(0x8000), 3);
+		assert_eq!(mmc1.read_cpu(0xC000), 7);
+	}
+
+	#[test]
+	fn bit_7_resets_the_shift_register()
+	{
+		let mut mmc1 = mmc1();
+
+		// 32K mode, then a reset in the middle of the next write goes back to 16K mode
+		write_serial(&mut mmc1, 0x8000, 0x00);
+		assert_eq!(mmc1.read_cpu(0xC000), 1);
+
+		mmc1.write_cpu(0xE000, 0x01);
+		mmc1.cpu_clock();
+		mmc1.cpu_clock();
+		mmc1.write_cpu(0xE000, 0x80);
+
+		write_serial(&mut mmc1, 0xE000, 0x02);
+		assert_eq!(mmc1.read_cpu(0x8000), 2);
+		assert_eq!(mmc1.read_cpu(0xC000), 7);
+	}
+
+	#[test]
+	fn switches_chr_in_4k_and_8k_banks()
+	{
+		let mut mmc1 = mmc1();
+
+		write_serial(&mut mmc1, 0xA000, 5);
+		write_serial(&mut mmc1, 0xC000, 2);
+		assert_eq!((mmc1.read_ppu(0x0000), mmc1.read_ppu(0x1000)), (4, 5));
+
+		write_serial(&mut mmc1, 0x8000, 0x1C);
+		assert_eq!((mmc1.read_ppu(0x0000), mmc1.read_ppu(0x1000)), (5, 2));
+	}
+
+	#[test]
+	fn ignores_writes_on_consecutive_cycles()
+	{
+		let mut mmc1 = mmc1();
+
+		// Like the two writes of an INC, only the first 1 gets shifted in
+		mmc1.cpu_clock();
+		mmc1.write_cpu(0xE000, 1);
+		mmc1.cpu_clock();
+		mmc1.write_cpu(0xE000, 1);
+
+		for _ in 0..4
+		{
+			mmc1.cpu_clock();
+			mmc1.cpu_clock();
+			mmc1.write_cpu(0xE000, 0);
+		}
+
+		assert_eq!(mmc1.read_cpu(0x8000), 1);
+	}
+}
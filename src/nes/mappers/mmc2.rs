@@ -0,0 +1,218 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+// MMC2 and MMC4 only differ in their PRG banking and the exact addresses that trigger latch 0,
+// so both are handled here
+pub struct MMC2
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	mmc4: bool,
+
+	prg_bank: u8,
+	chr_banks: [[u8; 2]; 2],
+	latches: [usize; 2],
+
+	mirroring: Mirroring
+}
+
+impl MMC2
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>, mmc4: bool) -> MMC2
+	{
+		MMC2
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			mmc4,
+
+			prg_bank: 0,
+			chr_banks: [[0; 2]; 2],
+			latches: [1, 1],
+
+			mirroring: header.mirroring
+		}
+	}
+
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		let banks = self.prg.len() / 0x2000;
+
+		match (self.mmc4, addr)
+		{
+			(false, 0x8000..=0x9FFF) 	=> bank_offset(self.prg.len(), 0x2000, self.prg_bank as usize, addr),
+			(false, _) 					=> bank_offset(self.prg.len(), 0x2000, banks.saturating_sub(4) + ((addr as usize - 0x8000) >> 13), addr),
+			(true, 0x8000..=0xBFFF) 	=> bank_offset(self.prg.len(), 0x4000, self.prg_bank as usize, addr),
+			(true, _) 					=> bank_offset(self.prg.len(), 0x4000, (banks / 2).saturating_sub(1), addr)
+		}
+	}
+
+	// The latches flip after the PPU fetched the high plane of tile $FD or $FE. On the MMC2
+	// latch 0 only reacts to the very first row of the tile, the MMC4 and latch 1 to all of them
+	fn update_latches(&mut self, addr: u16)
+	{
+		let table = ((addr >> 12) & 0x01) as usize;
+		let exact = table == 0 && !self.mmc4;
+
+		match (addr & 0x0FF8, exact && (addr & 0x0007) != 0)
+		{
+			(0x0FD8, false) => self.latches[table] = 0,
+			(0x0FE8, false) => self.latches[table] = 1,
+			_ => { }
+		}
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let table = ((addr >> 12) & 0x01) as usize;
+		let bank = self.chr_banks[table][self.latches[table]];
+		bank_offset(self.chr.len(), 0x1000, bank as usize, addr)
+	}
+}
+
+impl Mapper for MMC2
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.mmc4 && !self.prg_ram.is_empty() => {
+				self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, 0, addr)]
+			},
+
+			0x8000..=0xFFFF => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.mmc4 && !self.prg_ram.is_empty() => {
+				let offset = bank_offset(self.prg_ram.len(), 0x2000, 0, addr);
+				self.prg_ram[offset] = val;
+			},
+
+			0xA000..=0xAFFF => self.prg_bank = val & 0x0F,
+			0xB000..=0xBFFF => self.chr_banks[0][0] = val & 0x1F,
+			0xC000..=0xCFFF => self.chr_banks[0][1] = val & 0x1F,
+			0xD000..=0xDFFF => self.chr_banks[1][0] = val & 0x1F,
+			0xE000..=0xEFFF => self.chr_banks[1][1] = val & 0x1F,
+
+			0xF000..=0xFFFF => {
+				self.mirroring = match val & 0x01
+				{
+					0 => Mirroring::Vertical,
+					_ => Mirroring::Horizontal
+				};
+			},
+
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		let val = self.chr[self.chr_offset(addr)];
+
+		self.update_latches(addr);
+		val
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM and 128K of CHR-ROM, every byte holds the number of its 8K or 4K bank
+	fn board(mmc4: bool) -> MMC2
+	{
+		let header = Header::parse(b"NES\x1A\x08\x10\x90\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+		let prg = (0..16).flat_map(|bank| [bank; 0x2000]).collect();
+		let chr = (0..32).flat_map(|bank| [bank; 0x1000]).collect();
+
+		let mut mmc2 = MMC2::new(&header, prg, chr, mmc4);
+		for (addr, bank) in [(0xB000, 1), (0xC000, 2), (0xD000, 3), (0xE000, 4)]
+		{
+			mmc2.write_cpu(addr, bank);
+		}
+
+		mmc2
+	}
+
+	#[test]
+	fn fixes_the_last_three_prg_banks()
+	{
+		let mut mmc2 = board(false);
+
+		mmc2.write_cpu(0xA000, 5);
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc2.read_cpu(addr)), [5, 13, 14, 15]);
+	}
+
+	#[test]
+	fn flips_the_latches_after_tiles_fd_and_fe()
+	{
+		let mut mmc2 = board(false);
+		assert_eq!((mmc2.read_ppu(0x0000), mmc2.read_ppu(0x1000)), (2, 4));
+
+		// The fetch that flips the latch still uses the old bank
+		assert_eq!(mmc2.read_ppu(0x0FD8), 2);
+		assert_eq!(mmc2.read_ppu(0x0000), 1);
+
+		mmc2.read_ppu(0x1FD8);
+		assert_eq!(mmc2.read_ppu(0x1000), 3);
+
+		mmc2.read_ppu(0x1FEF);
+		assert_eq!(mmc2.read_ppu(0x1000), 4);
+	}
+
+	#[test]
+	fn mmc4_flips_latch_0_on_every_row()
+	{
+		// The MMC2 only reacts to the first row of tile $FE in the left pattern table
+		let mut mmc2 = board(false);
+		mmc2.read_ppu(0x0FD8);
+		mmc2.read_ppu(0x0FE9);
+		assert_eq!(mmc2.read_ppu(0x0000), 1);
+
+		let mut mmc4 = board(true);
+		mmc4.read_ppu(0x0FD8);
+		mmc4.read_ppu(0x0FE9);
+		assert_eq!(mmc4.read_ppu(0x0000), 2);
+	}
+}
@@ -0,0 +1,345 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+pub struct MMC3
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	bank_select: u8,
+	registers: [u8; 8],
+
+	mirroring: Mirroring,
+	four_screen: bool,
+	prg_ram_enabled: bool,
+	prg_ram_protected: bool,
+
+	irq_latch: u8,
+	irq_counter: u8,
+	irq_reload: bool,
+	irq_enabled: bool,
+	irq_pending: bool,
+
+	// The counter is clocked by rising edges on PPU A12, filtered by the number
+	// of M2 cycles A12 has been low before
+	a12: bool,
+	a12_low_cycles: u8
+}
+
+impl MMC3
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> MMC3
+	{
+		MMC3
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			bank_select: 0,
+			registers: [0, 2, 4, 5, 6, 7, 0, 1],
+
+			mirroring: header.mirroring,
+			four_screen: header.four_screen,
+			prg_ram_enabled: true,
+			prg_ram_protected: false,
+
+			irq_latch: 0,
+			irq_counter: 0,
+			irq_reload: false,
+			irq_enabled: false,
+			irq_pending: false,
+
+			a12: false,
+			a12_low_cycles: 0
+		}
+	}
+
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		let second_last = (self.prg.len() / 0x2000).saturating_sub(2);
+		let swap = (self.bank_select & 0x40) == 0x40;
+
+		let bank = match (addr, swap)
+		{
+			(0x8000..=0x9FFF, false) 	=> self.registers[6] as usize,
+			(0x8000..=0x9FFF, true) 	=> second_last,
+			(0xA000..=0xBFFF, _) 		=> self.registers[7] as usize,
+			(0xC000..=0xDFFF, false) 	=> second_last,
+			(0xC000..=0xDFFF, true) 	=> self.registers[6] as usize,
+			_ 							=> second_last + 1
+		};
+
+		bank_offset(self.prg.len(), 0x2000, bank & 0x3F, addr)
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		// A12 inversion swaps the two 2K banks with the four 1K banks
+		let addr = match self.bank_select & 0x80
+		{
+			0 => addr,
+			_ => addr ^ 0x1000
+		};
+
+		let bank = match addr & 0x1FFF
+		{
+			0x0000..=0x03FF => self.registers[0] & 0xFE,
+			0x0400..=0x07FF => self.registers[0] | 0x01,
+			0x0800..=0x0BFF => self.registers[1] & 0xFE,
+			0x0C00..=0x0FFF => self.registers[1] | 0x01,
+			0x1000..=0x13FF => self.registers[2],
+			0x1400..=0x17FF => self.registers[3],
+			0x1800..=0x1BFF => self.registers[4],
+			_ 				=> self.registers[5]
+		};
+
+		bank_offset(self.chr.len(), 0x0400, bank as usize, addr)
+	}
+
+	fn clock_irq_counter(&mut self)
+	{
+		match self.irq_counter == 0 || self.irq_reload
+		{
+			true 	=> self.irq_counter = self.irq_latch,
+			false 	=> self.irq_counter -= 1
+		}
+
+		self.irq_reload = false;
+
+		if self.irq_counter == 0 && self.irq_enabled
+		{
+			self.irq_pending = true;
+		}
+	}
+}
+
+impl Mapper for MMC3
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+				self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, 0, addr)]
+			},
+
+			0x8000..=0xFFFF => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match (addr, addr & 0x01)
+		{
+			(0x6000..=0x7FFF, _) if self.prg_ram_enabled && !self.prg_ram_protected && !self.prg_ram.is_empty() => {
+				let offset = bank_offset(self.prg_ram.len(), 0x2000, 0, addr);
+				self.prg_ram[offset] = val;
+			},
+
+			(0x8000..=0x9FFF, 0) => self.bank_select = val,
+			(0x8000..=0x9FFF, _) => self.registers[(self.bank_select & 0x07) as usize] = val,
+
+			(0xA000..=0xBFFF, 0) if !self.four_screen => {
+				self.mirroring = match val & 0x01
+				{
+					0 => Mirroring::Vertical,
+					_ => Mirroring::Horizontal
+				};
+			},
+
+			(0xA000..=0xBFFF, 1) => {
+				self.prg_ram_enabled = (val & 0x80) == 0x80;
+				self.prg_ram_protected = (val & 0x40) == 0x40;
+			},
+
+			(0xC000..=0xDFFF, 0) => self.irq_latch = val,
+			(0xC000..=0xDFFF, _) => {
+				self.irq_counter = 0;
+				self.irq_reload = true;
+			},
+
+			(0xE000..=0xFFFF, 0) => {
+				self.irq_enabled = false;
+				self.irq_pending = false;
+			},
+
+			(0xE000..=0xFFFF, _) => self.irq_enabled = true,
+
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq_pending
+	}
+
+	fn ppu_address(&mut self, addr: u16)
+	{
+		let a12 = (addr & 0x1000) == 0x1000;
+
+		if a12 && !self.a12 && self.a12_low_cycles >= 3
+		{
+			self.clock_irq_counter();
+		}
+
+		if !a12 && self.a12
+		{
+			self.a12_low_cycles = 0;
+		}
+
+		self.a12 = a12;
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		if !self.a12
+		{
+			self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+		}
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM and 128K of CHR-ROM, every byte holds the number of its 8K or 1K bank
+	fn mmc3() -> MMC3
+	{
+		let header = Header::parse(b"NES\x1A\x08\x10\x40\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+		let prg = (0..16).flat_map(|bank| [bank; 0x2000]).collect();
+		let chr = (0..128).flat_map(|bank| [bank; 0x0400]).collect();
+
+		MMC3::new(&header, prg, chr)
+	}
+
+	// A12 goes low for the background fetches and rises for the sprites, once per scanline
+	fn scanline(mmc3: &mut MMC3)
+	{
+		mmc3.ppu_address(0x0000);
+		for _ in 0..100
+		{
+			mmc3.cpu_clock();
+		}
+
+		mmc3.ppu_address(0x1000);
+	}
+
+	#[test]
+	fn switches_prg_banks()
+	{
+		let mut mmc3 = mmc3();
+
+		mmc3.write_cpu(0x8000, 0x06);
+		mmc3.write_cpu(0x8001, 3);
+		mmc3.write_cpu(0x8000, 0x07);
+		mmc3.write_cpu(0x8001, 5);
+
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc3.read_cpu(addr)), [3, 5, 14, 15]);
+
+		// The second last bank and R6 swap places
+		mmc3.write_cpu(0x8000, 0x46);
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc3.read_cpu(addr)), [14, 5, 3, 15]);
+	}
+
+	#[test]
+	fn counts_scanlines_down_from_the_latch()
+	{
+		let mut mmc3 = mmc3();
+
+		mmc3.write_cpu(0xC000, 2);
+		mmc3.write_cpu(0xC001, 0);
+		mmc3.write_cpu(0xE001, 0);
+
+		// Reloads to 2, counts down to 1 and fires on 0
+		scanline(&mut mmc3);
+		scanline(&mut mmc3);
+		assert!(!mmc3.irq());
+
+		scanline(&mut mmc3);
+		assert!(mmc3.irq());
+
+		mmc3.write_cpu(0xE000, 0);
+		assert!(!mmc3.irq());
+	}
+
+	#[test]
+	fn reloads_the_counter_on_request()
+	{
+		let mut mmc3 = mmc3();
+
+		mmc3.write_cpu(0xC000, 5);
+		mmc3.write_cpu(0xE001, 0);
+		scanline(&mut mmc3);
+		scanline(&mut mmc3);
+
+		// The new latch is taken on the next scanline instead of counting down to 3
+		mmc3.write_cpu(0xC000, 1);
+		mmc3.write_cpu(0xC001, 0);
+		scanline(&mut mmc3);
+		assert!(!mmc3.irq());
+
+		scanline(&mut mmc3);
+		assert!(mmc3.irq());
+	}
+
+	#[test]
+	fn filters_quick_a12_rises()
+	{
+		let mut mmc3 = mmc3();
+
+		// A latch of 0 fires on every clock of the counter
+		mmc3.write_cpu(0xC000, 0);
+		mmc3.write_cpu(0xE001, 0);
+		scanline(&mut mmc3);
+		assert!(mmc3.irq());
+
+		mmc3.write_cpu(0xE000, 0);
+		mmc3.write_cpu(0xE001, 0);
+
+		// The PPU toggling A12 within a couple of cycles, like reading CHR through $2007
+		mmc3.ppu_address(0x0000);
+		mmc3.cpu_clock();
+		mmc3.ppu_address(0x1000);
+		assert!(!mmc3.irq());
+	}
+}
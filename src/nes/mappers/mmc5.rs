@@ -0,0 +1,576 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+// Number of PPU fetches in a rendered scanline, counted from the first background fetch at dot 1
+const SPRITE_FETCHES_START: usize = 128;
+const SPRITE_FETCHES_END: usize = 160;
+const PREFETCH_END: usize = 168;
+
+pub struct MMC5
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+	exram: Vec<u8>,
+
+	prg_mode: u8,
+	chr_mode: u8,
+	prg_ram_protect: [u8; 2],
+	exram_mode: u8,
+	nametable_mapping: u8,
+	fill_tile: u8,
+	fill_attribute: u8,
+
+	prg_banks: [u8; 5],
+	chr_banks: [u16; 12],
+	chr_upper: u16,
+	last_chr_set_b: bool,
+
+	split_control: u8,
+	split_scroll: u8,
+	split_bank: u8,
+
+	irq_compare: u8,
+	irq_enabled: bool,
+	irq_pending: bool,
+
+	multiplicand: u8,
+	multiplier: u8,
+
+	// Snooped from the PPU registers
+	large_sprites: bool,
+	rendering: bool,
+
+	// Scanline detection, the MMC5 watches for three consecutive fetches from the same
+	// nametable address, which only happen at the end of a rendered scanline
+	in_frame: bool,
+	scanline: u8,
+	last_ppu_addr: u16,
+	match_count: u8,
+	fetch_index: usize,
+	idle_cycles: u8,
+	tile_offset: u16
+}
+
+impl MMC5
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> MMC5
+	{
+		MMC5
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+			exram: vec![0u8; 0x400],
+
+			prg_mode: 3,
+			chr_mode: 0,
+			prg_ram_protect: [0; 2],
+			exram_mode: 0,
+			nametable_mapping: 0,
+			fill_tile: 0,
+			fill_attribute: 0,
+
+			prg_banks: [0, 0, 0, 0, 0xFF],
+			chr_banks: [0; 12],
+			chr_upper: 0,
+			last_chr_set_b: false,
+
+			split_control: 0,
+			split_scroll: 0,
+			split_bank: 0,
+
+			irq_compare: 0,
+			irq_enabled: false,
+			irq_pending: false,
+
+			multiplicand: 0xFF,
+			multiplier: 0xFF,
+
+			large_sprites: false,
+			rendering: false,
+
+			in_frame: false,
+			scanline: 0,
+			last_ppu_addr: 0,
+			match_count: 0,
+			fetch_index: 0,
+			idle_cycles: 0,
+			tile_offset: 0
+		}
+	}
+
+	// Returns the bank register and its size in 8K units for a CPU address
+	fn prg_register(&self, addr: u16) -> (u8, usize)
+	{
+		match (self.prg_mode, addr)
+		{
+			(_, 0x6000..=0x7FFF) 		=> (self.prg_banks[0] & 0x7F, 1),
+			(0, _) 						=> (self.prg_banks[4] | 0x80, 4),
+			(1, 0x8000..=0xBFFF) 		=> (self.prg_banks[2], 2),
+			(1, _) 						=> (self.prg_banks[4] | 0x80, 2),
+			(2, 0x8000..=0xBFFF) 		=> (self.prg_banks[2], 2),
+			(2, 0xC000..=0xDFFF) 		=> (self.prg_banks[3], 1),
+			(2, _) 						=> (self.prg_banks[4] | 0x80, 1),
+			(_, 0x8000..=0x9FFF) 		=> (self.prg_banks[1], 1),
+			(_, 0xA000..=0xBFFF) 		=> (self.prg_banks[2], 1),
+			(_, 0xC000..=0xDFFF) 		=> (self.prg_banks[3], 1),
+			(_, _) 						=> (self.prg_banks[4] | 0x80, 1)
+		}
+	}
+
+	// Resolves a CPU address to either ROM (true) or RAM (false) and an offset into it
+	fn prg_offset(&self, addr: u16) -> (bool, usize)
+	{
+		let (register, size) = self.prg_register(addr);
+		let rom = (register & 0x80) == 0x80;
+		let base = match addr
+		{
+			0x6000..=0x7FFF => 0x6000,
+			_ => 0x8000 + ((addr as usize - 0x8000) & !(size * 0x2000 - 1)) as u16
+		};
+
+		let bank = ((register & 0x7F) as usize & !(size - 1)) + ((addr - base) as usize >> 13);
+
+		match rom
+		{
+			true 	=> (true, bank_offset(self.prg.len(), 0x2000, bank, addr)),
+			false 	=> (false, bank_offset(self.prg_ram.len(), 0x2000, bank & 0x07, addr))
+		}
+	}
+
+	fn prg_ram_writable(&self) -> bool
+	{
+		self.prg_ram_protect == [0x02, 0x01]
+	}
+
+	fn sprite_fetch(&self) -> bool
+	{
+		self.in_frame && (SPRITE_FETCHES_START..SPRITE_FETCHES_END).contains(&self.fetch_index)
+	}
+
+	fn background_fetch(&self) -> bool
+	{
+		self.in_frame && !self.sprite_fetch() && self.fetch_index < PREFETCH_END
+	}
+
+	// Background tiles are fetched two tiles ahead, the first two are fetched at the end of the previous scanline
+	fn tile_column(&self) -> usize
+	{
+		match self.fetch_index
+		{
+			0..=127 => self.fetch_index / 4 + 2,
+			_ 		=> (self.fetch_index - SPRITE_FETCHES_END) / 4
+		}
+	}
+
+	fn in_split(&self) -> bool
+	{
+		if (self.split_control & 0x80) == 0 || self.exram_mode >= 2 || !self.background_fetch()
+		{
+			return false;
+		}
+
+		let threshold = (self.split_control & 0x1F) as usize;
+		match self.split_control & 0x40
+		{
+			0 => self.tile_column() < threshold,
+			_ => self.tile_column() >= threshold
+		}
+	}
+
+	fn split_row(&self) -> u16
+	{
+		let scanline = match self.fetch_index >= SPRITE_FETCHES_END
+		{
+			true 	=> self.scanline as u16 + 1,
+			false 	=> self.scanline as u16
+		};
+
+		(scanline + self.split_scroll as u16) % 240
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let use_set_b = match self.large_sprites && self.in_frame
+		{
+			true 	=> !self.sprite_fetch(),
+			false 	=> self.last_chr_set_b
+		};
+
+		let (bank, size) = match (self.chr_mode, use_set_b)
+		{
+			(0, false) => (self.chr_banks[7], 0x2000),
+			(0, true)  => (self.chr_banks[11], 0x2000),
+			(1, false) => (self.chr_banks[3 + 4 * ((addr >> 12) & 0x01) as usize], 0x1000),
+			(1, true)  => (self.chr_banks[11], 0x1000),
+			(2, false) => (self.chr_banks[1 + 2 * ((addr >> 11) & 0x03) as usize], 0x0800),
+			(2, true)  => (self.chr_banks[9 + 2 * ((addr >> 11) & 0x01) as usize], 0x0800),
+			(_, false) => (self.chr_banks[((addr >> 10) & 0x07) as usize], 0x0400),
+			(_, true)  => (self.chr_banks[8 + ((addr >> 10) & 0x03) as usize], 0x0400)
+		};
+
+		bank_offset(self.chr.len(), size, bank as usize, addr)
+	}
+
+	fn detect_scanline(&mut self, addr: u16)
+	{
+		self.idle_cycles = 0;
+
+		match (0x2000..=0x2FFF).contains(&addr) && addr == self.last_ppu_addr
+		{
+			true 	=> self.match_count += 1,
+			false 	=> self.match_count = 0
+		}
+
+		self.last_ppu_addr = addr;
+		self.fetch_index += 1;
+
+		if self.match_count != 2
+		{
+			return;
+		}
+
+		self.fetch_index = 0;
+		self.match_count = 0;
+
+		match self.in_frame
+		{
+			false => {
+				self.in_frame = true;
+				self.scanline = 0;
+				self.irq_pending = false;
+			},
+
+			true => {
+				self.scanline = self.scanline.wrapping_add(1);
+				if self.scanline == self.irq_compare
+				{
+					self.irq_pending = true;
+				}
+			}
+		}
+	}
+}
+
+impl Mapper for MMC5
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x5204 => {
+				let val = ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6);
+				self.irq_pending = false;
+
+				val
+			},
+
+			0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+			0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+
+			0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+
+			0x6000..=0xFFFF => {
+				// The NMI vector fetch marks the end of the frame
+				if addr == 0xFFFA || addr == 0xFFFB
+				{
+					self.in_frame = false;
+				}
+
+				match self.prg_offset(addr)
+				{
+					(true, offset) 								=> self.prg[offset],
+					(false, _) if self.prg_ram.is_empty() 		=> 0,
+					(false, offset) 							=> self.prg_ram[offset]
+				}
+			},
+
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x2000 => self.large_sprites = (val & 0x20) == 0x20,
+			0x2001 => {
+				self.rendering = (val & 0x18) != 0;
+				if !self.rendering
+				{
+					self.in_frame = false;
+				}
+			},
+
+			0x5100 => self.prg_mode = val & 0x03,
+			0x5101 => self.chr_mode = val & 0x03,
+			0x5102 => self.prg_ram_protect[0] = val & 0x03,
+			0x5103 => self.prg_ram_protect[1] = val & 0x03,
+			0x5104 => self.exram_mode = val & 0x03,
+			0x5105 => self.nametable_mapping = val,
+			0x5106 => self.fill_tile = val,
+			0x5107 => self.fill_attribute = (val & 0x03) * 0x55,
+			0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = val,
+
+			0x5120..=0x512B => {
+				self.chr_banks[(addr - 0x5120) as usize] = (self.chr_upper << 8) | val as u16;
+				self.last_chr_set_b = addr >= 0x5128;
+			},
+
+			0x5130 => self.chr_upper = (val & 0x03) as u16,
+			0x5200 => self.split_control = val,
+			0x5201 => self.split_scroll = val,
+			0x5202 => self.split_bank = val,
+			0x5203 => self.irq_compare = val,
+			0x5204 => self.irq_enabled = (val & 0x80) == 0x80,
+			0x5205 => self.multiplicand = val,
+			0x5206 => self.multiplier = val,
+
+			0x5C00..=0x5FFF if self.exram_mode != 3 => self.exram[(addr - 0x5C00) as usize] = val,
+
+			0x6000..=0xFFFF if self.prg_ram_writable() => {
+				if let (false, offset) = self.prg_offset(addr)
+				{
+					if !self.prg_ram.is_empty()
+					{
+						self.prg_ram[offset] = val;
+					}
+				}
+			},
+
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		if self.in_split()
+		{
+			let row = self.split_row() & 0x07;
+			return self.chr[bank_offset(self.chr.len(), 0x1000, self.split_bank as usize, (addr & 0x0FF8) | row)];
+		}
+
+		// Extended attribute mode supplies a 4K CHR bank for every background tile
+		if self.exram_mode == 1 && self.background_fetch()
+		{
+			let bank = (self.chr_upper << 6) | (self.exram[self.tile_offset as usize] & 0x3F) as u16;
+			return self.chr[bank_offset(self.chr.len(), 0x1000, bank as usize, addr)];
+		}
+
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		match self.nametable_mapping
+		{
+			0x50 => Mirroring::Horizontal,
+			0x44 => Mirroring::Vertical,
+			0x00 => Mirroring::SingleScreenLower,
+			0x55 => Mirroring::SingleScreenUpper,
+			_ => Mirroring::FourScreen
+		}
+	}
+
+	fn read_nametable(&mut self, addr: u16, vram: &[u8]) -> u8
+	{
+		let offset = addr & 0x03FF;
+		let attribute = offset >= 0x3C0;
+
+		if self.in_split()
+		{
+			let row = self.split_row() / 8;
+			let column = self.tile_column() as u16 & 0x1F;
+
+			return match attribute
+			{
+				false 	=> self.exram[(row * 32 + column) as usize],
+				true 	=> {
+					let val = self.exram[(0x3C0 + (row / 4) * 8 + column / 4) as usize];
+					let shift = ((row & 0x02) << 1) | (column & 0x02);
+
+					((val >> shift) & 0x03) * 0x55
+				}
+			};
+		}
+
+		if !attribute && self.background_fetch()
+		{
+			self.tile_offset = offset;
+		}
+
+		if attribute && self.exram_mode == 1 && self.background_fetch()
+		{
+			return (self.exram[self.tile_offset as usize] >> 6) * 0x55;
+		}
+
+		let table = (addr >> 10) & 0x03;
+		match (self.nametable_mapping >> (table * 2)) & 0x03
+		{
+			0 => vram[offset as usize],
+			1 => vram[0x400 + offset as usize],
+			2 => match self.exram_mode
+			{
+				0 | 1 	=> self.exram[offset as usize],
+				_ 		=> 0
+			},
+			_ => match attribute
+			{
+				false 	=> self.fill_tile,
+				true 	=> self.fill_attribute
+			}
+		}
+	}
+
+	fn write_nametable(&mut self, addr: u16, val: u8, vram: &mut [u8])
+	{
+		let offset = (addr & 0x03FF) as usize;
+		let table = (addr >> 10) & 0x03;
+
+		match (self.nametable_mapping >> (table * 2)) & 0x03
+		{
+			0 => vram[offset] = val,
+			1 => vram[0x400 + offset] = val,
+			2 if self.exram_mode <= 1 => self.exram[offset] = val,
+			_ => { }
+		}
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq_pending && self.irq_enabled
+	}
+
+	fn ppu_address(&mut self, addr: u16)
+	{
+		self.detect_scanline(addr);
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		// The PPU has stopped rendering if it did not fetch anything for a few cycles
+		self.idle_cycles = self.idle_cycles.saturating_add(1);
+		if self.idle_cycles >= 3
+		{
+			self.in_frame = false;
+		}
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM with 8K of PRG-RAM, every byte of PRG-ROM holds the number of its 8K bank
+	fn mmc5() -> MMC5
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x00\x50\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.prg_ram_size = 0x2000;
+
+		let prg = (0..16).flat_map(|bank| [bank; 0x2000]).collect();
+		MMC5::new(&header, prg, vec![0u8; 0x2000])
+	}
+
+	// Three fetches from the same nametable address end every rendered scanline
+	fn scanline(mmc5: &mut MMC5)
+	{
+		mmc5.ppu_address(0x1000);
+		for _ in 0..3
+		{
+			mmc5.ppu_address(0x2000);
+		}
+	}
+
+	#[test]
+	fn switches_prg_in_every_mode()
+	{
+		let mut mmc5 = mmc5();
+		mmc5.write_cpu(0x5114, 0x83);
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc5.read_cpu(addr)), [3, 0, 0, 15]);
+
+		mmc5.write_cpu(0x5100, 0);
+		mmc5.write_cpu(0x5117, 0x85);
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc5.read_cpu(addr)), [4, 5, 6, 7]);
+
+		mmc5.write_cpu(0x5100, 1);
+		mmc5.write_cpu(0x5115, 0x83);
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mmc5.read_cpu(addr)), [2, 3, 4, 5]);
+	}
+
+	#[test]
+	fn protects_prg_ram_until_unlocked()
+	{
+		let mut mmc5 = mmc5();
+
+		mmc5.write_cpu(0x6000, 0x42);
+		assert_eq!(mmc5.read_cpu(0x6000), 0);
+
+		mmc5.write_cpu(0x5102, 0x02);
+		mmc5.write_cpu(0x5103, 0x01);
+		mmc5.write_cpu(0x6000, 0x42);
+		assert_eq!(mmc5.read_cpu(0x6000), 0x42);
+	}
+
+	#[test]
+	fn multiplies()
+	{
+		let mut mmc5 = mmc5();
+
+		mmc5.write_cpu(0x5205, 0x12);
+		mmc5.write_cpu(0x5206, 0x34);
+		assert_eq!((mmc5.read_cpu(0x5205), mmc5.read_cpu(0x5206)), (0xA8, 0x03));
+	}
+
+	#[test]
+	fn fires_on_the_compared_scanline()
+	{
+		let mut mmc5 = mmc5();
+		mmc5.write_cpu(0x5203, 2);
+		mmc5.write_cpu(0x5204, 0x80);
+
+		// The first match starts the frame at scanline 0
+		scanline(&mut mmc5);
+		scanline(&mut mmc5);
+		assert!(!mmc5.irq());
+
+		scanline(&mut mmc5);
+		assert!(mmc5.irq());
+
+		// Reading the status acknowledges, and the frame ends once the PPU stops fetching
+		assert_eq!(mmc5.read_cpu(0x5204), 0xC0);
+		assert!(!mmc5.irq());
+
+		for _ in 0..3
+		{
+			mmc5.cpu_clock();
+		}
+
+		assert_eq!(mmc5.read_cpu(0x5204), 0x00);
+	}
+}
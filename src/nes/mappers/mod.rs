@@ -0,0 +1,227 @@
+mod nrom;
+mod mmc1;
+mod mmc3;
+mod discrete;
+mod mmc2;
+mod mmc5;
+mod vrc;
+mod vrc6;
+mod vrc7;
+mod eeprom;
+mod bandai;
+mod fme7;
+mod namco163;
+mod multicart;
+pub mod fds;
+pub mod nsf;
+
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::nrom::NROM;
+use crate::nes::mappers::mmc1::MMC1;
+use crate::nes::mappers::mmc3::MMC3;
+use crate::nes::mappers::discrete::{Discrete, Board};
+use crate::nes::mappers::mmc2::MMC2;
+use crate::nes::mappers::mmc5::MMC5;
+use crate::nes::mappers::vrc::VRC;
+use crate::nes::mappers::vrc6::VRC6;
+use crate::nes::mappers::vrc7::VRC7;
+use crate::nes::mappers::bandai::Bandai;
+use crate::nes::mappers::fme7::FME7;
+use crate::nes::mappers::namco163::Namco163;
+use crate::nes::mappers::multicart::Multicart;
+
+pub trait Mapper
+{
+	fn read_cpu(&mut self, addr: u16) -> u8;
+	fn write_cpu(&mut self, addr: u16, val: u8);
+
+	fn read_ppu(&mut self, addr: u16) -> u8;
+	fn write_ppu(&mut self, addr: u16, val: u8);
+
+	fn mirroring(&self) -> Mirroring;
+
+	// The cartridge is in control of the nametable memory, by default it selects
+	// a page of the console's VRAM according to the mirroring
+	fn read_nametable(&mut self, addr: u16, vram: &[u8]) -> u8
+	{
+		vram[nametable_index(self.mirroring(), addr)]
+	}
+
+	fn write_nametable(&mut self, addr: u16, val: u8, vram: &mut [u8])
+	{
+		vram[nametable_index(self.mirroring(), addr)] = val;
+	}
+
+	fn irq(&self) -> bool
+	{
+		false
+	}
+
+	// Called for every address the PPU puts on its bus, including nametable and
+	// palette accesses that never reach the cartridge
+	fn ppu_address(&mut self, _addr: u16)
+	{
+
+	}
+
+	// Called once per CPU cycle (M2)
+	fn cpu_clock(&mut self)
+	{
+
+	}
+
+	// Current level of the cartridge's expansion audio, between 0.0 and 1.0
+	fn audio_output(&self) -> f32
+	{
+		0.0
+	}
+
+	// Memory that keeps its contents while the console is off and gets written to the save file
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		None
+	}
+
+	fn load_battery_data(&mut self, _data: &[u8])
+	{
+
+	}
+
+	// Only the Famicom Disk System has disks, whose sides are numbered from 0
+	fn disk_sides(&self) -> usize
+	{
+		0
+	}
+
+	// Ejects the current disk and inserts the given side, if any
+	fn insert_disk(&mut self, _side: Option<usize>)
+	{
+
+	}
+
+	fn current_disk(&self) -> Option<usize>
+	{
+		None
+	}
+}
+
+// Translates an address inside a switchable bank into an offset into the backing memory.
+// Bank numbers that exceed the available memory wrap around, like the unconnected upper
+// address lines on the real boards
+pub fn bank_offset(len: usize, bank_size: usize, bank: usize, addr: u16) -> usize
+{
+	match len
+	{
+		0 => 0,
+		_ => (bank * bank_size + (addr as usize & (bank_size - 1))) % len
+	}
+}
+
+// Work RAM is only kept between sessions if the header says a battery is backing it
+pub fn battery_ram(battery: bool, ram: &[u8]) -> Option<&[u8]>
+{
+	match battery && !ram.is_empty()
+	{
+		true 	=> Some(ram),
+		false 	=> None
+	}
+}
+
+// Save files that don't match the size of the memory are truncated or only fill the start
+pub fn load_ram(ram: &mut [u8], data: &[u8])
+{
+	let len = data.len().min(ram.len());
+	ram[..len].copy_from_slice(&data[..len]);
+}
+
+pub fn nametable_index(mirroring: Mirroring, addr: u16) -> usize
+{
+	let table = match mirroring
+	{
+		Mirroring::Horizontal 			=> (addr >> 11) & 0x01,
+		Mirroring::Vertical 			=> (addr >> 10) & 0x01,
+		Mirroring::FourScreen 			=> (addr >> 10) & 0x03,
+		Mirroring::SingleScreenLower 	=> 0,
+		Mirroring::SingleScreenUpper 	=> 1
+	};
+
+	((table << 10) | (addr & 0x03FF)) as usize
+}
+
+// Common names of the boards behind iNES mapper numbers, for the error message of unsupported ones
+static BOARD_NAMES: &[(u16, &str)] = &[
+	(0, "NROM"), (1, "MMC1"), (2, "UxROM"), (3, "CNROM"), (4, "MMC3"), (5, "MMC5"), (6, "FFE F4xxx"),
+	(7, "AxROM"), (8, "FFE F3xxx"), (9, "MMC2"), (10, "MMC4"), (11, "Color Dreams"), (12, "MMC3 clone"),
+	(13, "CPROM"), (15, "100-in-1 Contra Function 16"), (16, "Bandai FCG"), (18, "Jaleco SS88006"),
+	(19, "Namco 163"), (20, "Famicom Disk System"), (21, "VRC4a/VRC4c"), (22, "VRC2a"), (23, "VRC2b/VRC4e"),
+	(24, "VRC6a"), (25, "VRC4b/VRC4d"), (26, "VRC6b"), (28, "Action 53"), (30, "UNROM 512"),
+	(31, "NSF"), (32, "Irem G-101"), (33, "Taito TC0190"), (34, "BNROM/NINA-001"), (36, "TXC 01-22000-400"),
+	(37, "PAL-ZZ SMB/Tetris/NWC"), (38, "Bit Corp. Crime Busters"), (40, "NTDEC 2722"), (41, "Caltron 6-in-1"),
+	(42, "FDS conversion"), (44, "Super Big 7-in-1"), (45, "GA23C multicart"), (46, "Rumble Station"),
+	(47, "Super Spike V'Ball + Nintendo World Cup"), (48, "Taito TC0690"), (49, "Super HIK 4-in-1"),
+	(57, "GK 6-in-1"), (58, "GK 68-in-1"), (60, "Reset-based 4-in-1"), (61, "20-in-1"), (62, "Super 700-in-1"),
+	(64, "RAMBO-1"), (65, "Irem H3001"), (66, "GxROM"), (67, "Sunsoft-3"), (68, "Sunsoft-4"), (69, "Sunsoft FME-7"),
+	(70, "Bandai 74161/32"), (71, "Camerica/Codemasters"), (72, "Jaleco JF-17"), (73, "VRC3"), (74, "Waixing MMC3 clone"),
+	(75, "VRC1"), (76, "Namco 3446"), (77, "Irem LROG017"), (78, "Irem 74HC161/32"), (79, "NINA-03/NINA-06"),
+	(80, "Taito X1-005"), (82, "Taito X1-017"), (85, "VRC7"), (86, "Jaleco JF-13"), (87, "Jaleco JF-xx"),
+	(88, "Namco 118"), (89, "Sunsoft-2 (Tenka no Goikenban)"), (92, "Jaleco JF-19"), (93, "Sunsoft-2"),
+	(94, "UN1ROM"), (95, "Namco 3425"), (97, "Irem TAM-S1"), (99, "Vs. System"), (105, "NES-EVENT"),
+	(107, "Magic Dragon"), (112, "NTDEC/Asder"), (113, "NINA-03/06 multicart"), (115, "Kasheng SFC-02B"),
+	(118, "TxSROM"), (119, "TQROM"), (140, "Jaleco JF-11/JF-14"), (152, "Bandai 74161/32 single-screen"),
+	(154, "Namco 3453"), (155, "MMC1A"), (159, "Bandai LZ93D50 + 24C01"), (180, "UNROM (Crazy Climber)"),
+	(184, "Sunsoft-1"), (185, "CNROM with copy protection"), (201, "21-in-1"), (202, "150-in-1"),
+	(203, "35-in-1"), (206, "Namco 118/DxROM"), (210, "Namco 175/340"), (212, "Super HIK 300-in-1"),
+	(225, "64-in-1/72-in-1"), (226, "76-in-1"), (227, "1200-in-1"), (228, "Action 52"), (229, "31-in-1"),
+	(230, "22-in-1"), (231, "20-in-1"), (232, "Camerica Quattro"), (233, "42-in-1"), (234, "Maxi 15"),
+	(235, "Golden Game multicart"), (240, "C&E"), (241, "BxROM with WRAM"), (242, "Waixing 43-in-1"),
+	(245, "Waixing MMC3 clone"), (246, "C&E (Feng Shen Bang)"), (255, "110-in-1")
+];
+
+pub fn board_name(mapper: u16) -> Option<&'static str>
+{
+	BOARD_NAMES.iter().find(|&&(number, _)| number == mapper).map(|&(_, name)| name)
+}
+
+// Returns None if the header's mapper number isn't implemented, unless it should be run as NROM instead
+pub fn create(header: &Header, mut prg: Vec<u8>, chr: Vec<u8>, nrom_fallback: bool) -> Option<Box<dyn Mapper>>
+{
+	let mapper: Box<dyn Mapper> = match header.mapper
+	{
+		0 => Box::new(NROM::new(header, prg, chr)),
+		1 => Box::new(MMC1::new(header, prg, chr)),
+		2 => Box::new(Discrete::new(header, prg, chr, Board::UxROM)),
+		3 => Box::new(Discrete::new(header, prg, chr, Board::CNROM)),
+		4 => Box::new(MMC3::new(header, prg, chr)),
+		5 => Box::new(MMC5::new(header, prg, chr)),
+		7 => Box::new(Discrete::new(header, prg, chr, Board::AxROM)),
+		9 => Box::new(MMC2::new(header, prg, chr, false)),
+		10 => Box::new(MMC2::new(header, prg, chr, true)),
+		11 => Box::new(Discrete::new(header, prg, chr, Board::ColorDreams)),
+		16 | 159 => Box::new(Bandai::new(header, prg, chr)),
+		19 => Box::new(Namco163::new(header, prg, chr)),
+		21 | 22 | 23 | 25 => Box::new(VRC::new(header, prg, chr)),
+		24 | 26 => Box::new(VRC6::new(header, prg, chr)),
+		66 => Box::new(Discrete::new(header, prg, chr, Board::GxROM)),
+		69 => Box::new(FME7::new(header, prg, chr)),
+		85 => Box::new(VRC7::new(header, prg, chr)),
+		225 | 255 => Box::new(Multicart::new(header, prg, chr, multicart::Board::Mapper225)),
+		227 => Box::new(Multicart::new(header, prg, chr, multicart::Board::Mapper227)),
+		228 => Box::new(Multicart::new(header, prg, chr, multicart::Board::Mapper228)),
+
+		// Most boards power up with the last bank at $C000-$FFFF, which is where the reset vector is
+		_ if nrom_fallback => {
+			match board_name(header.mapper)
+			{
+				Some(board) => eprintln!("Warning: mapper {} ({}) is not supported, running it as NROM", header.mapper, board),
+				None 		=> eprintln!("Warning: mapper {} is not supported, running it as NROM", header.mapper)
+			}
+
+			let prg = prg.split_off(prg.len().saturating_sub(0x8000));
+			Box::new(NROM::new(header, prg, chr))
+		},
+
+		_ => return None
+	};
+
+	Some(mapper)
+}
@@ -0,0 +1,267 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset};
+
+// Pirate multicarts that select the game through the address lines of a write to $8000-$FFFF,
+// the value written is mostly ignored
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Board
+{
+	// 64-in-1 and similar, with 4 nibbles of RAM at $5800-$5FFF
+	Mapper225,
+	// 1200-in-1 and other UNROM-based carts with CHR-RAM
+	Mapper227,
+	// Action 52 and Cheetahmen II, with 4 nibbles of RAM at $4020-$5FFF
+	Mapper228
+}
+
+pub struct Multicart
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+
+	board: Board,
+	latch: u16,
+	data: u8,
+	ram: [u8; 4]
+}
+
+impl Multicart
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>, board: Board) -> Multicart
+	{
+		Multicart
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+
+			board,
+			latch: 0,
+			data: 0,
+			ram: [0; 4]
+		}
+	}
+
+	// Returns the 16K banks at $8000 and $C000
+	fn prg_banks(&self) -> (usize, usize)
+	{
+		let latch = self.latch as usize;
+
+		match self.board
+		{
+			// A~[.HMO PPPP PPCC CCCC], H extends the PRG bank
+			Board::Mapper225 => {
+				let bank = ((latch >> 8) & 0x40) | ((latch >> 6) & 0x3F);
+				match latch & 0x1000
+				{
+					0 => (bank & !1, bank | 1),
+					_ => (bank, bank)
+				}
+			},
+
+			// A~[.... ..LP OPPP PPMS], O selects between NROM and UNROM with the last bank fixed
+			// to the last or first bank of the 128K block selected by L
+			Board::Mapper227 => {
+				let bank = ((latch >> 2) & 0x1F) | ((latch >> 3) & 0x20);
+				let last = match latch & 0x200
+				{
+					0 => bank & 0x38,
+					_ => bank | 0x07
+				};
+
+				match (latch & 0x80 != 0, latch & 0x01 != 0)
+				{
+					(true, true) 	=> (bank & !1, bank | 1),
+					(true, false) 	=> (bank, bank),
+					(false, true) 	=> (bank & 0x3E, last),
+					(false, false) 	=> (bank, last)
+				}
+			},
+
+			// A~[..MH HPPP PPO. CCCC], H selects one of the 512K chips, of which the third is
+			// missing on Action 52 and the fourth takes its place in the address space
+			Board::Mapper228 => {
+				let mut page = (latch >> 7) & 0x3F;
+				if (page & 0x30) == 0x30
+				{
+					page -= 0x10;
+				}
+
+				let bank = (page << 1) | ((latch >> 6) & (latch >> 5) & 0x01);
+				match latch & 0x20
+				{
+					0 => (bank, bank + 1),
+					_ => (bank, bank)
+				}
+			}
+		}
+	}
+
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		let (low, high) = self.prg_banks();
+		let bank = match addr
+		{
+			0x8000..=0xBFFF => low,
+			_ => high
+		};
+
+		bank_offset(self.prg.len(), 0x4000, bank, addr)
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let latch = self.latch as usize;
+		let bank = match self.board
+		{
+			Board::Mapper225 => ((latch >> 8) & 0x40) | (latch & 0x3F),
+			Board::Mapper227 => 0,
+			Board::Mapper228 => ((latch & 0x0F) << 2) | (self.data & 0x03) as usize
+		};
+
+		bank_offset(self.chr.len(), 0x2000, bank, addr)
+	}
+}
+
+impl Mapper for Multicart
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match (self.board, addr)
+		{
+			(Board::Mapper225, 0x5800..=0x5FFF) | (Board::Mapper228, 0x4020..=0x5FFF) => self.ram[(addr & 0x03) as usize],
+			(_, 0x8000..=0xFFFF) => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match (self.board, addr)
+		{
+			(Board::Mapper225, 0x5800..=0x5FFF) | (Board::Mapper228, 0x4020..=0x5FFF) => self.ram[(addr & 0x03) as usize] = val & 0x0F,
+
+			(_, 0x8000..=0xFFFF) => {
+				self.latch = addr & 0x7FFF;
+				self.data = val;
+			},
+
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		let horizontal = match self.board
+		{
+			Board::Mapper225 | Board::Mapper228 => self.latch & 0x2000,
+			Board::Mapper227 => self.latch & 0x02
+		};
+
+		match horizontal
+		{
+			0 => Mirroring::Vertical,
+			_ => Mirroring::Horizontal
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// Every byte holds the number of its 16K PRG or 8K CHR bank
+	fn multicart(board: Board, prg_banks: usize, chr_banks: usize) -> Multicart
+	{
+		let header = Header::parse(b"NES\x1A\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+		let prg = (0..prg_banks).flat_map(|bank| [bank as u8; 0x4000]).collect();
+		let chr = (0..chr_banks).flat_map(|bank| [bank as u8; 0x2000]).collect();
+
+		Multicart::new(&header, prg, chr, board)
+	}
+
+	fn banks(multicart: &mut Multicart) -> (u8, u8)
+	{
+		(multicart.read_cpu(0x8000), multicart.read_cpu(0xC000))
+	}
+
+	#[test]
+	fn switches_mapper_225_banks()
+	{
+		let mut multicart = multicart(Board::Mapper225, 128, 128);
+
+		// Bank 5 as 32K, then bank $45 in the upper half as 16K with horizontal mirroring
+		multicart.write_cpu(0x8140, 0);
+		assert_eq!(banks(&mut multicart), (4, 5));
+		assert_eq!(multicart.mirroring(), Mirroring::Vertical);
+
+		multicart.write_cpu(0xF143, 0);
+		assert_eq!(banks(&mut multicart), (0x45, 0x45));
+		assert_eq!(multicart.read_ppu(0x0000), 0x43);
+		assert_eq!(multicart.mirroring(), Mirroring::Horizontal);
+
+		// The RAM only keeps nibbles
+		multicart.write_cpu(0x5801, 0xFF);
+		assert_eq!(multicart.read_cpu(0x5801), 0x0F);
+	}
+
+	#[test]
+	fn switches_mapper_227_banks()
+	{
+		let mut multicart = multicart(Board::Mapper227, 64, 0);
+
+		multicart.write_cpu(0x808D, 0);
+		assert_eq!(banks(&mut multicart), (2, 3));
+
+		// UNROM with the first or last bank of the 128K block fixed at $C000
+		multicart.write_cpu(0x8114, 0);
+		assert_eq!(banks(&mut multicart), (0x25, 0x20));
+
+		multicart.write_cpu(0x8314, 0);
+		assert_eq!(banks(&mut multicart), (0x25, 0x27));
+	}
+
+	#[test]
+	fn switches_mapper_228_banks()
+	{
+		// Action 52's three 512K chips
+		let mut multicart = multicart(Board::Mapper228, 96, 64);
+
+		multicart.write_cpu(0x8900, 0);
+		assert_eq!(banks(&mut multicart), (0x24, 0x25));
+
+		multicart.write_cpu(0x8160, 0);
+		assert_eq!(banks(&mut multicart), (5, 5));
+
+		// The CHR bank takes its low bits from the value written
+		multicart.write_cpu(0x8003, 0x02);
+		assert_eq!(multicart.read_ppu(0x0000), 0x0E);
+	}
+
+	#[test]
+	fn maps_the_fourth_action_52_chip_over_the_missing_third()
+	{
+		// The image holds chips 0, 1 and 3, so the last 512K of it belong to chip 3
+		let mut multicart = multicart(Board::Mapper228, 96, 64);
+
+		multicart.write_cpu(0x9900, 0);
+		assert_eq!(banks(&mut multicart), (0x44, 0x45));
+	}
+}
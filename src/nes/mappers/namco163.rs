@@ -0,0 +1,296 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+// Namco 163, mapper 19
+pub struct Namco163
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+
+	// 128 bytes of internal RAM, shared with the wavetable sound channels
+	internal_ram: Vec<u8>,
+	ram_addr: u8,
+	auto_increment: bool,
+
+	prg_banks: [u8; 3],
+	chr_banks: [u8; 8],
+	nametable_banks: [u8; 4],
+	write_protect: u8,
+
+	irq_counter: u16,
+	irq_enabled: bool,
+	irq_pending: bool,
+
+	battery: bool
+}
+
+impl Namco163
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> Namco163
+	{
+		Namco163
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+
+			internal_ram: vec![0u8; 0x80],
+			ram_addr: 0,
+			auto_increment: false,
+
+			prg_banks: [0; 3],
+			chr_banks: [0; 8],
+			nametable_banks: [0; 4],
+			write_protect: 0,
+
+			irq_counter: 0,
+			irq_enabled: false,
+			irq_pending: false,
+
+			battery: header.battery
+		}
+	}
+
+	fn access_internal_ram(&mut self) -> usize
+	{
+		let index = (self.ram_addr & 0x7F) as usize;
+		if self.auto_increment
+		{
+			self.ram_addr = (self.ram_addr + 1) & 0x7F;
+		}
+
+		index
+	}
+
+	// PRG-RAM is only writable if the upper nibble of $F800 is %0100, the lower bits protect the 2K quarters
+	fn prg_ram_writable(&self, addr: u16) -> bool
+	{
+		let quarter = (addr - 0x6000) >> 11;
+		(self.write_protect & 0xF0) == 0x40 && (self.write_protect >> quarter) & 0x01 == 0
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let bank = self.chr_banks[((addr >> 10) & 0x07) as usize];
+		bank_offset(self.chr.len(), 0x0400, bank as usize, addr)
+	}
+}
+
+impl Mapper for Namco163
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x4800..=0x4FFF => {
+				let index = self.access_internal_ram();
+				self.internal_ram[index]
+			},
+
+			0x5000..=0x57FF => self.irq_counter as u8,
+			0x5800..=0x5FFF => ((self.irq_enabled as u8) << 7) | (self.irq_counter >> 8) as u8,
+
+			0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+				self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, 0, addr)]
+			},
+
+			0x8000..=0xDFFF => {
+				let bank = self.prg_banks[((addr - 0x8000) >> 13) as usize];
+				self.prg[bank_offset(self.prg.len(), 0x2000, (bank & 0x3F) as usize, addr)]
+			},
+
+			0xE000..=0xFFFF => self.prg[bank_offset(self.prg.len(), 0x2000, (self.prg.len() / 0x2000).saturating_sub(1), addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x4800..=0x4FFF => {
+				let index = self.access_internal_ram();
+				self.internal_ram[index] = val;
+			},
+
+			0x5000..=0x57FF => {
+				self.irq_counter = (self.irq_counter & 0x7F00) | val as u16;
+				self.irq_pending = false;
+			},
+
+			0x5800..=0x5FFF => {
+				self.irq_counter = (self.irq_counter & 0x00FF) | (((val & 0x7F) as u16) << 8);
+				self.irq_enabled = (val & 0x80) == 0x80;
+				self.irq_pending = false;
+			},
+
+			0x6000..=0x7FFF if !self.prg_ram.is_empty() && self.prg_ram_writable(addr) => {
+				let offset = bank_offset(self.prg_ram.len(), 0x2000, 0, addr);
+				self.prg_ram[offset] = val;
+			},
+
+			0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = val,
+			0xC000..=0xDFFF => self.nametable_banks[((addr - 0xC000) >> 11) as usize] = val,
+			0xE000..=0xE7FF => self.prg_banks[0] = val & 0x3F,
+			0xE800..=0xEFFF => self.prg_banks[1] = val & 0x3F,
+			0xF000..=0xF7FF => self.prg_banks[2] = val & 0x3F,
+
+			0xF800..=0xFFFF => {
+				self.write_protect = val;
+				self.ram_addr = val & 0x7F;
+				self.auto_increment = (val & 0x80) == 0x80;
+			},
+
+			_ => { }
+		}
+	}
+
+	// Bank numbers $E0 and above can map the console's nametable RAM into the pattern tables,
+	// which no known game relies on and is not supported here
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		Mirroring::FourScreen
+	}
+
+	fn read_nametable(&mut self, addr: u16, vram: &[u8]) -> u8
+	{
+		let bank = self.nametable_banks[((addr >> 10) & 0x03) as usize];
+		let offset = (addr & 0x03FF) as usize;
+
+		match bank
+		{
+			0xE0..=0xFF => vram[((bank & 0x01) as usize) << 10 | offset],
+			_ => self.chr[bank_offset(self.chr.len(), 0x0400, bank as usize, addr)]
+		}
+	}
+
+	fn write_nametable(&mut self, addr: u16, val: u8, vram: &mut [u8])
+	{
+		let bank = self.nametable_banks[((addr >> 10) & 0x03) as usize];
+
+		if bank >= 0xE0
+		{
+			vram[((bank & 0x01) as usize) << 10 | (addr & 0x03FF) as usize] = val;
+		}
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq_pending
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		if !self.irq_enabled || self.irq_counter >= 0x7FFF
+		{
+			return;
+		}
+
+		self.irq_counter += 1;
+		if self.irq_counter == 0x7FFF
+		{
+			self.irq_pending = true;
+		}
+	}
+
+	// Boards without PRG-RAM keep their save in the internal RAM, backed by the same battery
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		match self.prg_ram.is_empty()
+		{
+			true 	=> battery_ram(self.battery, &self.internal_ram),
+			false 	=> battery_ram(self.battery, &self.prg_ram)
+		}
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		match self.prg_ram.is_empty()
+		{
+			true 	=> load_ram(&mut self.internal_ram, data),
+			false 	=> load_ram(&mut self.prg_ram, data)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// A battery but no PRG-RAM, so the internal RAM is saved
+	fn namco163() -> Namco163
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x10\x32\x10\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.prg_nvram_size = 0;
+
+		Namco163::new(&header, vec![0u8; 0x20000], vec![0u8; 0x20000])
+	}
+
+	#[test]
+	fn increments_the_internal_ram_address()
+	{
+		let mut namco163 = namco163();
+
+		namco163.write_cpu(0xF800, 0x90);
+		for val in 1..=3
+		{
+			namco163.write_cpu(0x4800, val);
+		}
+
+		namco163.write_cpu(0xF800, 0x90);
+		assert_eq!([(); 3].map(|_| namco163.read_cpu(0x4800)), [1, 2, 3]);
+
+		// Without the increment the same byte is read over and over
+		namco163.write_cpu(0xF800, 0x11);
+		assert_eq!([(); 3].map(|_| namco163.read_cpu(0x4800)), [2, 2, 2]);
+	}
+
+	#[test]
+	fn fires_when_the_counter_reaches_7fff()
+	{
+		let mut namco163 = namco163();
+
+		namco163.write_cpu(0x5000, 0xFD);
+		namco163.write_cpu(0x5800, 0xFF);
+
+		namco163.cpu_clock();
+		assert!(!namco163.irq());
+
+		namco163.cpu_clock();
+		assert!(namco163.irq());
+
+		// The counter stops there
+		namco163.cpu_clock();
+		assert_eq!((namco163.read_cpu(0x5000), namco163.read_cpu(0x5800)), (0xFF, 0xFF));
+	}
+
+	#[test]
+	fn keeps_the_save_in_internal_ram_without_prg_ram()
+	{
+		let mut namco163 = namco163();
+
+		namco163.write_cpu(0xF800, 0x00);
+		namco163.write_cpu(0x4800, 0x42);
+
+		assert_eq!(namco163.battery_data().map(|data| data[0]), Some(0x42));
+	}
+}
@@ -0,0 +1,93 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+// NROM-128 carries a single 16K PRG bank that is mirrored into $C000-$FFFF,
+// NROM-256 fills the entire $8000-$FFFF range with 32K of PRG. Family BASIC adds work RAM at $6000-$7FFF
+pub struct NROM
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	mirroring: Mirroring
+}
+
+impl NROM
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> NROM
+	{
+		NROM
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			mirroring: header.mirroring
+		}
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		bank_offset(self.chr.len(), 0x2000, 0, addr)
+	}
+}
+
+impl Mapper for NROM
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+				self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, 0, addr)]
+			},
+
+			0x8000..=0xFFFF => self.prg[(addr as usize - 0x8000) % self.prg.len()],
+			_ => 0
+		}
+	}
+
+	// NROM has no registers, writes can only reach the work RAM
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		if (0x6000..=0x7FFF).contains(&addr) && !self.prg_ram.is_empty()
+		{
+			let offset = bank_offset(self.prg_ram.len(), 0x2000, 0, addr);
+			self.prg_ram[offset] = val;
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
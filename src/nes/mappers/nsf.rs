@@ -0,0 +1,288 @@
+use crate::nes::cartridge::{Mirroring, RomError, Timing};
+use crate::nes::mappers::{Mapper, bank_offset};
+
+// Address of the idle loop the player sits in between calls to the tune's routines.
+// Nothing else lives in this part of the expansion area
+pub const IDLE_LOOP: u16 = 0x4100;
+
+// Default play rates in microseconds, used if a file doesn't give one
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+
+#[derive(Clone, Debug)]
+pub struct NsfInfo
+{
+	pub title: String,
+	pub artist: String,
+	pub copyright: String,
+
+	// Songs are numbered from 0, even though the NSF header counts from 1
+	pub songs: u8,
+	pub start_song: u8,
+
+	pub init: u16,
+	pub play: u16,
+	pub timing: Timing,
+	pub expansion: u8,
+
+	// Banks written to $5FF8-$5FFF before a song starts
+	pub banks: [u8; 8],
+
+	// Time between calls to PLAY, in microseconds
+	pub speed: u16
+}
+
+impl NsfInfo
+{
+	// The console always runs at the NTSC clock, so PAL-only tunes only get their slower play rate
+	pub fn play_period(&self) -> u32
+	{
+		(self.speed as f64 * 1.789773) as u32
+	}
+}
+
+// Maps the tune's data into $8000-$FFFF in 4K banks selected through $5FF8-$5FFF, with 8K of work
+// RAM at $6000. Files that don't use bankswitching are laid out at their load address instead, and
+// banked in with fixed registers. The player's idle loop is served from IDLE_LOOP
+pub struct NSF
+{
+	rom: Vec<u8>,
+	prg_ram: Vec<u8>,
+	banks: [usize; 8]
+}
+
+fn string(data: &[u8]) -> String
+{
+	let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+	String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn word(data: &[u8], offset: usize) -> u16
+{
+	u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn timing(region: u8) -> Timing
+{
+	match region & 0x03
+	{
+		0x01 	=> Timing::Pal,
+		0x00 	=> Timing::Ntsc,
+		_ 		=> Timing::MultiRegion
+	}
+}
+
+pub fn is_nsf(data: &[u8]) -> bool
+{
+	data.starts_with(b"NESM\x1A") || data.starts_with(b"NSFE")
+}
+
+// Returns the tune's information and the mapper that plays it
+pub fn parse(data: &[u8]) -> Result<(NsfInfo, NSF), RomError>
+{
+	match data.starts_with(b"NSFE")
+	{
+		true 	=> parse_nsfe(data),
+		false 	=> parse_nsf(data)
+	}
+}
+
+// The 128 byte header is followed by the data, which starts at the load address
+fn parse_nsf(data: &[u8]) -> Result<(NsfInfo, NSF), RomError>
+{
+	let header = data.get(..0x80).ok_or(RomError::Truncated)?;
+	let timing = timing(header[0x7A]);
+
+	let speed = match timing
+	{
+		Timing::Pal => word(header, 0x78),
+		_ 			=> word(header, 0x6E)
+	};
+
+	let mut info = NsfInfo
+	{
+		title: string(&header[0x0E..0x2E]),
+		artist: string(&header[0x2E..0x4E]),
+		copyright: string(&header[0x4E..0x6E]),
+
+		songs: header[0x06].max(1),
+		start_song: header[0x07].saturating_sub(1),
+
+		init: word(header, 0x0A),
+		play: word(header, 0x0C),
+		timing,
+		expansion: header[0x7B],
+
+		banks: header[0x70..0x78].try_into().unwrap(),
+		speed
+	};
+
+	if info.speed == 0
+	{
+		info.speed = default_speed(timing);
+	}
+
+	let mapper = NSF::new(&mut info, word(header, 0x08), &data[0x80..]);
+	Ok((info, mapper))
+}
+
+fn default_speed(timing: Timing) -> u16
+{
+	match timing
+	{
+		Timing::Pal => PAL_SPEED,
+		_ 			=> NTSC_SPEED
+	}
+}
+
+// NSFe stores the same information in chunks of a 32-bit length, a 4 character ID and the data.
+// Chunks starting with a lowercase letter are optional, everything but INFO and DATA gets defaults
+fn parse_nsfe(data: &[u8]) -> Result<(NsfInfo, NSF), RomError>
+{
+	let mut offset = 4;
+
+	let mut info_chunk = None;
+	let mut data_chunk = None;
+	let mut banks = [0u8; 8];
+	let mut rate = None;
+	let mut strings = Vec::new();
+
+	while offset < data.len()
+	{
+		let chunk_header = data.get(offset..offset + 8).ok_or(RomError::Truncated)?;
+		let len = u32::from_le_bytes([chunk_header[0], chunk_header[1], chunk_header[2], chunk_header[3]]) as usize;
+		let chunk = data.get(offset + 8..offset + 8 + len).ok_or(RomError::Truncated)?;
+		offset += 8 + len;
+
+		match &chunk_header[4..]
+		{
+			b"INFO" => info_chunk = Some(chunk),
+			b"DATA" => data_chunk = Some(chunk),
+			b"BANK" => banks[..len.min(8)].copy_from_slice(&chunk[..len.min(8)]),
+			b"RATE" => rate = Some(chunk),
+			b"auth" => strings = chunk.split(|&byte| byte == 0).map(string).collect(),
+			b"NEND" => break,
+
+			_ => { }
+		}
+	}
+
+	let info_chunk = info_chunk.filter(|chunk| chunk.len() >= 9).ok_or(RomError::Truncated)?;
+	let data_chunk = data_chunk.ok_or(RomError::Truncated)?;
+	let timing = timing(info_chunk[6]);
+
+	let speed = rate.and_then(|rate| match timing
+	{
+		Timing::Pal => rate.get(2..4),
+		_ 			=> rate.get(0..2)
+	}).map(|speed| word(speed, 0)).filter(|&speed| speed != 0);
+
+	let mut info = NsfInfo
+	{
+		title: strings.first().cloned().unwrap_or_default(),
+		artist: strings.get(1).cloned().unwrap_or_default(),
+		copyright: strings.get(2).cloned().unwrap_or_default(),
+
+		songs: info_chunk.get(8).copied().unwrap_or(1).max(1),
+		start_song: info_chunk.get(9).copied().unwrap_or(0),
+
+		init: word(info_chunk, 2),
+		play: word(info_chunk, 4),
+		timing,
+		expansion: info_chunk[7],
+
+		banks,
+		speed: speed.unwrap_or_else(|| default_speed(timing))
+	};
+
+	let mapper = NSF::new(&mut info, word(info_chunk, 0), data_chunk);
+	Ok((info, mapper))
+}
+
+impl NSF
+{
+	// Files that don't use bankswitching get the banks that cover $8000-$FFFF in order
+	fn new(info: &mut NsfInfo, load: u16, data: &[u8]) -> NSF
+	{
+		let bankswitched = info.banks.iter().any(|&bank| bank != 0);
+
+		let mut rom = match bankswitched
+		{
+			// The data is padded so that the load address lines up with the start of a bank
+			true => {
+				let mut rom = vec![0u8; (load & 0x0FFF) as usize];
+				rom.extend_from_slice(data);
+				rom
+			},
+
+			false => {
+				info.banks = [0, 1, 2, 3, 4, 5, 6, 7];
+
+				let start = load.max(0x8000) as usize - 0x8000;
+				let mut rom = vec![0u8; 0x8000];
+				let len = data.len().min(rom.len() - start);
+				rom[start..start + len].copy_from_slice(&data[..len]);
+				rom
+			}
+		};
+
+		let len = (rom.len() + 0x0FFF) & !0x0FFF;
+		rom.resize(len.max(0x1000), 0);
+
+		NSF
+		{
+			rom,
+			prg_ram: vec![0u8; 0x2000],
+			banks: [0, 1, 2, 3, 4, 5, 6, 7]
+		}
+	}
+}
+
+impl Mapper for NSF
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			// JMP IDLE_LOOP
+			IDLE_LOOP 			=> 0x4C,
+			0x4101 				=> (IDLE_LOOP & 0xFF) as u8,
+			0x4102 				=> (IDLE_LOOP >> 8) as u8,
+
+			0x6000..=0x7FFF 	=> self.prg_ram[(addr & 0x1FFF) as usize],
+			0x8000..=0xFFFF 	=> {
+				let bank = self.banks[((addr - 0x8000) >> 12) as usize];
+				self.rom[bank_offset(self.rom.len(), 0x1000, bank, addr)]
+			},
+
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		match addr
+		{
+			0x5FF8..=0x5FFF => self.banks[(addr - 0x5FF8) as usize] = val as usize,
+			0x6000..=0x7FFF => self.prg_ram[(addr & 0x1FFF) as usize] = val,
+
+			_ => { }
+		}
+	}
+
+	// There is nothing to draw
+	fn read_ppu(&mut self, _addr: u16) -> u8
+	{
+		0
+	}
+
+	fn write_ppu(&mut self, _addr: u16, _val: u8)
+	{
+
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		Mirroring::Horizontal
+	}
+}
@@ -0,0 +1,425 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+
+// The Konami chips only see two of the CPU address lines as their register select inputs,
+// but which ones depends on the board. Each pair names the lines wired to register bit 0 and 1
+pub fn register_select(addr: u16, pins: &[(u8, u8)]) -> usize
+{
+	pins.iter().fold(0, |register, (lo, hi)| {
+		register | ((addr >> lo) & 0x01) as usize | ((((addr >> hi) & 0x01) as usize) << 1)
+	})
+}
+
+// IRQ counter shared by the VRC4, VRC6 and VRC7. It either counts CPU cycles or emulates
+// scanlines with a prescaler that is clocked three times per CPU cycle
+pub struct IrqCounter
+{
+	latch: u8,
+	counter: u8,
+	prescaler: i16,
+
+	enabled: bool,
+	enabled_after_ack: bool,
+	cycle_mode: bool,
+	pending: bool
+}
+
+impl IrqCounter
+{
+	pub fn new() -> IrqCounter
+	{
+		IrqCounter
+		{
+			latch: 0,
+			counter: 0,
+			prescaler: 341,
+
+			enabled: false,
+			enabled_after_ack: false,
+			cycle_mode: false,
+			pending: false
+		}
+	}
+
+	pub fn write_latch(&mut self, val: u8)
+	{
+		self.latch = val;
+	}
+
+	pub fn write_latch_nibble(&mut self, val: u8, high: bool)
+	{
+		match high
+		{
+			false 	=> self.latch = (self.latch & 0xF0) | (val & 0x0F),
+			true 	=> self.latch = (self.latch & 0x0F) | (val << 4)
+		}
+	}
+
+	pub fn write_control(&mut self, val: u8)
+	{
+		self.enabled_after_ack = (val & 0x01) == 0x01;
+		self.enabled = (val & 0x02) == 0x02;
+		self.cycle_mode = (val & 0x04) == 0x04;
+		self.pending = false;
+
+		if self.enabled
+		{
+			self.counter = self.latch;
+			self.prescaler = 341;
+		}
+	}
+
+	pub fn acknowledge(&mut self)
+	{
+		self.pending = false;
+		self.enabled = self.enabled_after_ack;
+	}
+
+	pub fn pending(&self) -> bool
+	{
+		self.pending
+	}
+
+	pub fn clock(&mut self)
+	{
+		if !self.enabled
+		{
+			return;
+		}
+
+		if !self.cycle_mode
+		{
+			self.prescaler -= 3;
+			if self.prescaler > 0
+			{
+				return;
+			}
+
+			self.prescaler += 341;
+		}
+
+		match self.counter
+		{
+			0xFF => {
+				self.counter = self.latch;
+				self.pending = true;
+			},
+
+			_ => self.counter += 1
+		}
+	}
+}
+
+// VRC2 and VRC4, mappers 21, 22, 23 and 25
+pub struct VRC
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	vrc4: bool,
+	pins: Vec<(u8, u8)>,
+	chr_shift: u8,
+
+	prg_banks: [u8; 2],
+	chr_banks: [u16; 8],
+	prg_swap: bool,
+	prg_ram_enabled: bool,
+	mirroring: Mirroring,
+
+	// VRC2 boards without PRG-RAM still answer with a single latched bit at $6000-$6FFF
+	latch: u8,
+
+	irq: IrqCounter
+}
+
+impl VRC
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> VRC
+	{
+		// Submapper 0 leaves the wiring unknown, in that case both variants are decoded at once
+		let (vrc4, pins) = match (header.mapper, header.submapper)
+		{
+			(21, 1) => (true, vec![(1, 2)]),
+			(21, 2) => (true, vec![(6, 7)]),
+			(21, _) => (true, vec![(1, 2), (6, 7)]),
+			(22, _) => (false, vec![(1, 0)]),
+			(23, 1) => (true, vec![(0, 1)]),
+			(23, 2) => (true, vec![(2, 3)]),
+			(23, 3) => (false, vec![(0, 1)]),
+			(23, _) => (true, vec![(0, 1), (2, 3)]),
+			(25, 1) => (true, vec![(1, 0)]),
+			(25, 2) => (true, vec![(3, 2)]),
+			(25, 3) => (false, vec![(1, 0)]),
+			(_, _) 	=> (true, vec![(1, 0), (3, 2)])
+		};
+
+		VRC
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			vrc4,
+			pins,
+
+			// VRC2a ignores the lowest bit of the CHR bank numbers
+			chr_shift: (header.mapper == 22) as u8,
+
+			prg_banks: [0; 2],
+			chr_banks: [0; 8],
+			prg_swap: false,
+			prg_ram_enabled: !vrc4,
+			mirroring: header.mirroring,
+
+			latch: 0,
+
+			irq: IrqCounter::new()
+		}
+	}
+
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		let second_last = (self.prg.len() / 0x2000).saturating_sub(2);
+
+		let bank = match (addr, self.prg_swap)
+		{
+			(0x8000..=0x9FFF, false) 	=> self.prg_banks[0] as usize,
+			(0x8000..=0x9FFF, true) 	=> second_last,
+			(0xA000..=0xBFFF, _) 		=> self.prg_banks[1] as usize,
+			(0xC000..=0xDFFF, false) 	=> second_last,
+			(0xC000..=0xDFFF, true) 	=> self.prg_banks[0] as usize,
+			_ 							=> second_last + 1
+		};
+
+		bank_offset(self.prg.len(), 0x2000, bank, addr)
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let bank = self.chr_banks[((addr >> 10) & 0x07) as usize] >> self.chr_shift;
+		bank_offset(self.chr.len(), 0x0400, bank as usize, addr)
+	}
+}
+
+impl Mapper for VRC
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.prg_ram_enabled && !self.prg_ram.is_empty() => {
+				self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, 0, addr)]
+			},
+
+			0x6000..=0x6FFF if !self.vrc4 => self.latch,
+			0x8000..=0xFFFF => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		let register = register_select(addr, &self.pins);
+
+		match (addr & 0xF000, register)
+		{
+			(0x6000 | 0x7000, _) => {
+				if self.prg_ram_enabled && !self.prg_ram.is_empty()
+				{
+					let offset = bank_offset(self.prg_ram.len(), 0x2000, 0, addr);
+					self.prg_ram[offset] = val;
+				}
+				else if !self.vrc4 && addr < 0x7000
+				{
+					self.latch = val & 0x01;
+				}
+			},
+
+			(0x8000, _) => self.prg_banks[0] = val & 0x1F,
+
+			(0x9000, 0 | 1) => {
+				// The VRC2 only supports horizontal and vertical mirroring
+				let mask = match self.vrc4
+				{
+					true 	=> 0x03,
+					false 	=> 0x01
+				};
+
+				self.mirroring = match val & mask
+				{
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenLower,
+					_ => Mirroring::SingleScreenUpper
+				};
+			},
+
+			(0x9000, _) if self.vrc4 => {
+				self.prg_ram_enabled = (val & 0x01) == 0x01;
+				self.prg_swap = (val & 0x02) == 0x02;
+			},
+
+			(0xA000, _) => self.prg_banks[1] = val & 0x1F,
+
+			(0xB000..=0xE000, _) => {
+				let bank = (((addr & 0xF000) - 0xB000) >> 11) as usize | (register >> 1);
+				let current = self.chr_banks[bank];
+
+				self.chr_banks[bank] = match register & 0x01
+				{
+					0 => (current & 0x1F0) | (val & 0x0F) as u16,
+					_ => (current & 0x00F) | (((val & 0x1F) as u16) << 4)
+				};
+			},
+
+			(0xF000, 0) if self.vrc4 => self.irq.write_latch_nibble(val, false),
+			(0xF000, 1) if self.vrc4 => self.irq.write_latch_nibble(val, true),
+			(0xF000, 2) if self.vrc4 => self.irq.write_control(val),
+			(0xF000, _) if self.vrc4 => self.irq.acknowledge(),
+
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		self.mirroring
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq.pending()
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		self.irq.clock();
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM and 256K of CHR-ROM, every byte holds the number of its 8K or 1K bank
+	fn vrc(mapper: u8, submapper: u8) -> VRC
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x20\x50\x10\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.mapper = mapper as u16;
+		header.submapper = submapper;
+
+		let prg = (0..16).flat_map(|bank| [bank; 0x2000]).collect();
+		let chr = (0..=255).flat_map(|bank| [bank; 0x0400]).collect();
+
+		VRC::new(&header, prg, chr)
+	}
+
+	#[test]
+	fn decodes_registers_from_the_board_wiring()
+	{
+		// The address lines of the low and high nibble register of CHR bank 0
+		let boards = [(21, 1, 0x02), (21, 2, 0x40), (23, 1, 0x01), (23, 2, 0x04), (25, 1, 0x02), (25, 2, 0x08), (23, 0, 0x04)];
+
+		for (mapper, submapper, high) in boards
+		{
+			let mut vrc = vrc(mapper, submapper);
+			vrc.write_cpu(0xB000, 0x05);
+			vrc.write_cpu(0xB000 | high, 0x01);
+
+			assert_eq!(vrc.read_ppu(0x0000), 0x15);
+		}
+
+		// VRC2a drops the lowest bit of the bank
+		let mut vrc = vrc(22, 0);
+		vrc.write_cpu(0xB000, 0x05);
+		vrc.write_cpu(0xB002, 0x01);
+		assert_eq!(vrc.read_ppu(0x0000), 0x0A);
+	}
+
+	#[test]
+	fn swaps_the_prg_banks_on_vrc4()
+	{
+		let mut vrc = vrc(23, 1);
+
+		vrc.write_cpu(0x8000, 3);
+		vrc.write_cpu(0xA000, 5);
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| vrc.read_cpu(addr)), [3, 5, 14, 15]);
+
+		vrc.write_cpu(0x9002, 0x02);
+		assert_eq!([0x8000, 0xA000, 0xC000, 0xE000].map(|addr| vrc.read_cpu(addr)), [14, 5, 3, 15]);
+	}
+
+	#[test]
+	fn counts_cpu_cycles()
+	{
+		let mut irq = IrqCounter::new();
+		irq.write_latch(0xFD);
+		irq.write_control(0x06);
+
+		irq.clock();
+		irq.clock();
+		assert!(!irq.pending());
+
+		irq.clock();
+		assert!(irq.pending());
+
+		// Acknowledging disables the counter unless bit 0 of the control asked to keep it going
+		irq.acknowledge();
+		for _ in 0..0x100
+		{
+			irq.clock();
+		}
+
+		assert!(!irq.pending());
+	}
+
+	#[test]
+	fn counts_scanlines_with_the_prescaler()
+	{
+		let mut irq = IrqCounter::new();
+		irq.write_latch(0xFF);
+		irq.write_control(0x02);
+
+		// 341 PPU dots make a scanline, three of them per CPU cycle
+		for _ in 0..113
+		{
+			irq.clock();
+		}
+
+		assert!(!irq.pending());
+
+		irq.clock();
+		assert!(irq.pending());
+	}
+}
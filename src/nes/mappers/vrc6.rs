@@ -0,0 +1,394 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+use crate::nes::mappers::vrc::{IrqCounter, register_select};
+
+struct Pulse
+{
+	volume: u8,
+	duty: u8,
+	constant: bool,
+	period: u16,
+	enabled: bool,
+
+	timer: u16,
+	step: u8
+}
+
+impl Pulse
+{
+	fn new() -> Pulse
+	{
+		Pulse { volume: 0, duty: 0, constant: false, period: 0, enabled: false, timer: 0, step: 0 }
+	}
+
+	fn write(&mut self, register: usize, val: u8)
+	{
+		match register
+		{
+			0 => {
+				self.constant = (val & 0x80) == 0x80;
+				self.duty = (val >> 4) & 0x07;
+				self.volume = val & 0x0F;
+			},
+
+			1 => self.period = (self.period & 0x0F00) | val as u16,
+
+			_ => {
+				self.period = (self.period & 0x00FF) | (((val & 0x0F) as u16) << 8);
+				self.enabled = (val & 0x80) == 0x80;
+
+				if !self.enabled
+				{
+					self.step = 15;
+				}
+			}
+		}
+	}
+
+	fn clock(&mut self, shift: u8)
+	{
+		if !self.enabled
+		{
+			return;
+		}
+
+		match self.timer
+		{
+			0 => {
+				self.timer = self.period >> shift;
+				self.step = self.step.wrapping_sub(1) & 0x0F;
+			},
+
+			_ => self.timer -= 1
+		}
+	}
+
+	fn output(&self) -> u8
+	{
+		match self.enabled && (self.constant || self.step <= self.duty)
+		{
+			true 	=> self.volume,
+			false 	=> 0
+		}
+	}
+}
+
+struct Sawtooth
+{
+	rate: u8,
+	period: u16,
+	enabled: bool,
+
+	timer: u16,
+	step: u8,
+	accumulator: u8
+}
+
+impl Sawtooth
+{
+	fn new() -> Sawtooth
+	{
+		Sawtooth { rate: 0, period: 0, enabled: false, timer: 0, step: 0, accumulator: 0 }
+	}
+
+	fn write(&mut self, register: usize, val: u8)
+	{
+		match register
+		{
+			0 => self.rate = val & 0x3F,
+			1 => self.period = (self.period & 0x0F00) | val as u16,
+
+			_ => {
+				self.period = (self.period & 0x00FF) | (((val & 0x0F) as u16) << 8);
+				self.enabled = (val & 0x80) == 0x80;
+
+				if !self.enabled
+				{
+					self.step = 0;
+					self.accumulator = 0;
+				}
+			}
+		}
+	}
+
+	// The accumulator is increased on every second timer clock and reset after the seventh increase
+	fn clock(&mut self, shift: u8)
+	{
+		if !self.enabled
+		{
+			return;
+		}
+
+		if self.timer > 0
+		{
+			self.timer -= 1;
+			return;
+		}
+
+		self.timer = self.period >> shift;
+		self.step += 1;
+
+		match self.step
+		{
+			14 => {
+				self.step = 0;
+				self.accumulator = 0;
+			},
+
+			s if s % 2 == 0 => self.accumulator = self.accumulator.wrapping_add(self.rate),
+			_ => { }
+		}
+	}
+
+	fn output(&self) -> u8
+	{
+		self.accumulator >> 3
+	}
+}
+
+// VRC6, mappers 24 (VRC6a) and 26 (VRC6b, with swapped register lines)
+pub struct VRC6
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	pins: [(u8, u8); 1],
+
+	prg_16k: u8,
+	prg_8k: u8,
+	chr_banks: [u8; 8],
+	banking_mode: u8,
+
+	halt: bool,
+	frequency_shift: u8,
+	pulses: [Pulse; 2],
+	sawtooth: Sawtooth,
+
+	irq: IrqCounter
+}
+
+impl VRC6
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> VRC6
+	{
+		let pins = match header.mapper
+		{
+			26 => [(1, 0)],
+			_ => [(0, 1)]
+		};
+
+		VRC6
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			pins,
+
+			prg_16k: 0,
+			prg_8k: 0,
+			chr_banks: [0; 8],
+			banking_mode: 0,
+
+			halt: false,
+			frequency_shift: 0,
+			pulses: [Pulse::new(), Pulse::new()],
+			sawtooth: Sawtooth::new(),
+
+			irq: IrqCounter::new()
+		}
+	}
+
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		match addr
+		{
+			0x8000..=0xBFFF => bank_offset(self.prg.len(), 0x4000, self.prg_16k as usize, addr),
+			0xC000..=0xDFFF => bank_offset(self.prg.len(), 0x2000, self.prg_8k as usize, addr),
+			_ 				=> bank_offset(self.prg.len(), 0x2000, (self.prg.len() / 0x2000).saturating_sub(1), addr)
+		}
+	}
+
+	// Modes 1-3 combine pairs of registers into 2K banks, ignoring the lowest bit of the register
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let slot = ((addr >> 10) & 0x07) as usize;
+
+		let (bank, size) = match (self.banking_mode & 0x03, slot)
+		{
+			(0, _) 			=> (self.chr_banks[slot] as usize, 0x0400),
+			(1, _) 			=> ((self.chr_banks[slot / 2] >> 1) as usize, 0x0800),
+			(_, 0..=3) 		=> (self.chr_banks[slot] as usize, 0x0400),
+			(_, _) 			=> ((self.chr_banks[4 + (slot - 4) / 2] >> 1) as usize, 0x0800)
+		};
+
+		bank_offset(self.chr.len(), size, bank, addr)
+	}
+
+	fn prg_ram_enabled(&self) -> bool
+	{
+		(self.banking_mode & 0x80) == 0x80 && !self.prg_ram.is_empty()
+	}
+}
+
+impl Mapper for VRC6
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.prg_ram_enabled() => {
+				self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, 0, addr)]
+			},
+
+			0x8000..=0xFFFF => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		let register = register_select(addr, &self.pins);
+
+		match (addr & 0xF000, register)
+		{
+			(0x6000 | 0x7000, _) if self.prg_ram_enabled() => {
+				let offset = bank_offset(self.prg_ram.len(), 0x2000, 0, addr);
+				self.prg_ram[offset] = val;
+			},
+
+			(0x8000, _) => self.prg_16k = val & 0x0F,
+
+			(0x9000, 3) => {
+				self.halt = (val & 0x01) == 0x01;
+				self.frequency_shift = match val & 0x06
+				{
+					0 => 0,
+					2 => 4,
+					_ => 8
+				};
+			},
+
+			(0x9000, r) => self.pulses[0].write(r, val),
+			(0xA000, r) if r < 3 => self.pulses[1].write(r, val),
+			(0xB000, 3) => self.banking_mode = val,
+			(0xB000, r) => self.sawtooth.write(r, val),
+			(0xC000, _) => self.prg_8k = val & 0x1F,
+			(0xD000, r) => self.chr_banks[r] = val,
+			(0xE000, r) => self.chr_banks[4 + r] = val,
+			(0xF000, 0) => self.irq.write_latch(val),
+			(0xF000, 1) => self.irq.write_control(val),
+			(0xF000, 2) => self.irq.acknowledge(),
+
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		match (self.banking_mode >> 2) & 0x03
+		{
+			0 => Mirroring::Vertical,
+			1 => Mirroring::Horizontal,
+			2 => Mirroring::SingleScreenLower,
+			_ => Mirroring::SingleScreenUpper
+		}
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq.pending()
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		self.irq.clock();
+
+		if !self.halt
+		{
+			self.pulses[0].clock(self.frequency_shift);
+			self.pulses[1].clock(self.frequency_shift);
+			self.sawtooth.clock(self.frequency_shift);
+		}
+	}
+
+	fn audio_output(&self) -> f32
+	{
+		let sum = self.pulses[0].output() + self.pulses[1].output() + self.sawtooth.output();
+		sum as f32 / 61.0
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 256K of CHR-ROM, every byte holds the number of its 1K bank
+	fn vrc6(mapper: u16) -> VRC6
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x20\x80\x10\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.mapper = mapper;
+
+		let chr = (0..=255).flat_map(|bank| [bank; 0x0400]).collect();
+		VRC6::new(&header, vec![0u8; 0x20000], chr)
+	}
+
+	#[test]
+	fn swaps_the_register_lines_on_vrc6b()
+	{
+		let mut vrc6a = vrc6(24);
+		vrc6a.write_cpu(0xD001, 1);
+		vrc6a.write_cpu(0xD002, 2);
+		assert_eq!((vrc6a.read_ppu(0x0400), vrc6a.read_ppu(0x0800)), (1, 2));
+
+		let mut vrc6b = vrc6(26);
+		vrc6b.write_cpu(0xD001, 1);
+		vrc6b.write_cpu(0xD002, 2);
+		assert_eq!((vrc6b.read_ppu(0x0400), vrc6b.read_ppu(0x0800)), (2, 1));
+	}
+
+	#[test]
+	fn plays_the_pulse_volume()
+	{
+		let mut vrc6 = vrc6(24);
+		assert_eq!(vrc6.audio_output(), 0.0);
+
+		// Constant volume 15
+		vrc6.write_cpu(0x9000, 0x8F);
+		vrc6.write_cpu(0x9002, 0x80);
+		vrc6.cpu_clock();
+
+		assert_eq!(vrc6.audio_output(), 15.0 / 61.0);
+	}
+}
@@ -0,0 +1,223 @@
+use crate::nes::cartridge::{Header, Mirroring};
+use crate::nes::mappers::{Mapper, bank_offset, battery_ram, load_ram};
+use crate::nes::mappers::vrc::IrqCounter;
+
+// VRC7, mapper 85. Submapper 1 (VRC7b) selects registers with A3, submapper 2 (VRC7a) with A4
+pub struct VRC7
+{
+	prg: Vec<u8>,
+	chr: Vec<u8>,
+	chr_ram: bool,
+	prg_ram: Vec<u8>,
+	battery: bool,
+
+	select_mask: u16,
+
+	prg_banks: [u8; 3],
+	chr_banks: [u8; 8],
+	control: u8,
+
+	// The FM synthesizer's registers are latched here, the synthesis itself is not emulated
+	audio_addr: u8,
+	audio_registers: [u8; 0x40],
+
+	irq: IrqCounter
+}
+
+impl VRC7
+{
+	pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> VRC7
+	{
+		// Unlike the other VRCs only a single address line selects the register
+		let select_mask = match header.submapper
+		{
+			1 => 0x0008,
+			2 => 0x0010,
+			_ => 0x0018
+		};
+
+		VRC7
+		{
+			prg,
+			chr,
+			chr_ram: header.chr_rom_size == 0,
+			prg_ram: vec![0u8; header.prg_ram_size + header.prg_nvram_size],
+			battery: header.battery,
+
+			select_mask,
+
+			prg_banks: [0; 3],
+			chr_banks: [0; 8],
+			control: 0,
+
+			audio_addr: 0,
+			audio_registers: [0; 0x40],
+
+			irq: IrqCounter::new()
+		}
+	}
+
+	fn prg_offset(&self, addr: u16) -> usize
+	{
+		let bank = match addr
+		{
+			0x8000..=0x9FFF => self.prg_banks[0] as usize,
+			0xA000..=0xBFFF => self.prg_banks[1] as usize,
+			0xC000..=0xDFFF => self.prg_banks[2] as usize,
+			_ 				=> (self.prg.len() / 0x2000).saturating_sub(1)
+		};
+
+		bank_offset(self.prg.len(), 0x2000, bank, addr)
+	}
+
+	fn prg_ram_enabled(&self) -> bool
+	{
+		(self.control & 0x80) == 0x80 && !self.prg_ram.is_empty()
+	}
+
+	fn chr_offset(&self, addr: u16) -> usize
+	{
+		let bank = self.chr_banks[((addr >> 10) & 0x07) as usize];
+		bank_offset(self.chr.len(), 0x0400, bank as usize, addr)
+	}
+}
+
+impl Mapper for VRC7
+{
+	fn read_cpu(&mut self, addr: u16) -> u8
+	{
+		match addr
+		{
+			0x6000..=0x7FFF if self.prg_ram_enabled() => {
+				self.prg_ram[bank_offset(self.prg_ram.len(), 0x2000, 0, addr)]
+			},
+
+			0x8000..=0xFFFF => self.prg[self.prg_offset(addr)],
+			_ => 0
+		}
+	}
+
+	fn write_cpu(&mut self, addr: u16, val: u8)
+	{
+		let register = ((addr & self.select_mask) != 0) as usize;
+
+		match (addr & 0xF000, register)
+		{
+			(0x6000 | 0x7000, _) if self.prg_ram_enabled() => {
+				let offset = bank_offset(self.prg_ram.len(), 0x2000, 0, addr);
+				self.prg_ram[offset] = val;
+			},
+
+			(0x8000, r) => self.prg_banks[r] = val & 0x3F,
+
+			// The audio ports at $9010 and $9030 are decoded by A4 and A5 on every board, so they're
+			// checked before A3 is taken to select the register on VRC7b
+			(0x9000, _) if (addr & 0x0010) == 0x0010 => match addr & 0x0030
+			{
+				0x0030 	=> self.audio_registers[(self.audio_addr & 0x3F) as usize] = val,
+				_ 		=> self.audio_addr = val
+			},
+
+			(0x9000, 0) => self.prg_banks[2] = val & 0x3F,
+
+			(0xA000..=0xD000, r) => {
+				let bank = (((addr & 0xF000) - 0xA000) >> 11) as usize | r;
+				self.chr_banks[bank] = val;
+			},
+
+			(0xE000, 0) => self.control = val,
+			(0xE000, _) => self.irq.write_latch(val),
+			(0xF000, 0) => self.irq.write_control(val),
+			(0xF000, _) => self.irq.acknowledge(),
+
+			_ => { }
+		}
+	}
+
+	fn read_ppu(&mut self, addr: u16) -> u8
+	{
+		self.chr[self.chr_offset(addr)]
+	}
+
+	fn write_ppu(&mut self, addr: u16, val: u8)
+	{
+		// CHR-ROM ignores writes, only boards with CHR-RAM store them
+		if self.chr_ram
+		{
+			let offset = self.chr_offset(addr);
+			self.chr[offset] = val;
+		}
+	}
+
+	fn mirroring(&self) -> Mirroring
+	{
+		match self.control & 0x03
+		{
+			0 => Mirroring::Vertical,
+			1 => Mirroring::Horizontal,
+			2 => Mirroring::SingleScreenLower,
+			_ => Mirroring::SingleScreenUpper
+		}
+	}
+
+	fn irq(&self) -> bool
+	{
+		self.irq.pending()
+	}
+
+	fn cpu_clock(&mut self)
+	{
+		self.irq.clock();
+	}
+
+	fn battery_data(&self) -> Option<&[u8]>
+	{
+		battery_ram(self.battery, &self.prg_ram)
+	}
+
+	fn load_battery_data(&mut self, data: &[u8])
+	{
+		load_ram(&mut self.prg_ram, data);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// 128K of PRG-ROM, every byte holds the number of its 8K bank
+	fn vrc7(submapper: u8) -> VRC7
+	{
+		let mut header = Header::parse(b"NES\x1A\x08\x00\x50\x50\x00\x00\x00\x00\x00\x00\x00\x00");
+		header.submapper = submapper;
+
+		let prg = (0..16).flat_map(|bank| [bank; 0x2000]).collect();
+		VRC7::new(&header, prg, vec![0u8; 0x2000])
+	}
+
+	#[test]
+	fn selects_registers_by_submapper()
+	{
+		for (submapper, register) in [(1, 0x8008), (2, 0x8010)]
+		{
+			let mut vrc7 = vrc7(submapper);
+			vrc7.write_cpu(0x8000, 3);
+			vrc7.write_cpu(register, 5);
+
+			assert_eq!((vrc7.read_cpu(0x8000), vrc7.read_cpu(0xA000)), (3, 5));
+		}
+	}
+
+	#[test]
+	fn audio_ports_are_not_prg_registers()
+	{
+		// A3 is clear on both ports, VRC7b would take them for the $C000 bank otherwise
+		let mut vrc7 = vrc7(1);
+		vrc7.write_cpu(0x9010, 0x05);
+		vrc7.write_cpu(0x9030, 0x42);
+
+		assert_eq!(vrc7.read_cpu(0xC000), 0);
+		assert_eq!(vrc7.audio_registers[5], 0x42);
+	}
+}
@@ -2,8 +2,14 @@ pub mod nes;
 
 mod cpu;
 mod ppu;
+mod apu;
 mod bus;
 mod addressing;
 mod instructions;
 mod mnemonic;
-mod cartridge;
\ No newline at end of file
+pub mod cartridge;
+mod archive;
+mod patch;
+pub mod database;
+mod unif;
+mod mappers;
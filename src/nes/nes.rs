@@ -1,84 +1,309 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use crate::nes::bus::Bus;
 use crate::nes::cpu::CPU;
 use crate::nes::ppu::PPU;
+use crate::nes::apu::APU;
+use crate::nes::cartridge::{Cartridge, Timing};
+use crate::nes::mappers::nsf::{self, NsfInfo};
+
+pub use crate::nes::apu::SAMPLE_RATE;
 
 pub struct NES
 {
 	bus: Rc<RefCell<Bus>>,
 	cpu: Rc<RefCell<CPU>>,
-	ppu: Rc<RefCell<PPU>>
+	ppu: Rc<RefCell<PPU>>,
+	apu: Rc<RefCell<APU>>,
+	cartridge: Rc<RefCell<Cartridge>>,
+
+	frames: Cell<u32>,
+
+	// NSF player state, PLAY is called once the timer runs out and the CPU is back in the idle loop
+	nsf: Option<NsfInfo>,
+	track: Cell<u8>,
+	play_timer: Cell<u32>,
+	play_pending: Cell<bool>
+}
+
+// Whether the CPU is still running, or stuck on a JAM opcode until the console is reset
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum State
+{
+	Running,
+	Jammed { pc: u16, opcode: u8 }
 }
 
+// Battery-backed memory is flushed to disk every few seconds, so a crash doesn't lose much progress
+const SAVE_INTERVAL: u32 = 300;
+
+// The PPU is only borrowed after the CPU cycle, since the CPU reaches its registers through the bus
 macro_rules! clock 
 {
-	($cpu: ident, $ppu: ident) =>
-	{
+	($self: ident, $cpu: ident) =>
+	{{
+		$self.drive_player(&mut $cpu);
+
 		let res = $cpu.cycle();
+		$self.cartridge.borrow_mut().cpu_clock();
+		$self.apu.borrow_mut().clock();
 
-		$ppu.dot();
-		$ppu.dot();
-		$ppu.dot();
+		let mut ppu = $self.ppu.borrow_mut();
+		ppu.dot();
+		ppu.dot();
+		ppu.dot();
 
-		if res {
-			let (x, y) = $ppu.current_dot();
+		if res && $cpu.trace {
+			let (x, y) = ppu.current_dot();
 			println!("PPU:{: <3},{: <3}", y, x);
 		}
-	}
+	}}
 }
 
 impl NES
 {
-	pub fn new() -> NES 
+	pub fn new(cartridge: Cartridge) -> NES 
 	{
-		let bus: Rc<RefCell<Bus>> = Rc::new(RefCell::new(Bus::new()));
+		let bus: Rc<RefCell<Bus>> = Rc::new(RefCell::new(Bus::new(cartridge)));
 		let cpu: Rc<RefCell<CPU>> = Rc::new(RefCell::new(CPU::new(&bus)));
 		let ppu: Rc<RefCell<PPU>> = Rc::new(RefCell::new(PPU::new(&bus)));
+		let apu: Rc<RefCell<APU>> = Rc::new(RefCell::new(APU::new(&bus)));
+		let cartridge = bus.borrow().cartridge();
+		let nsf = cartridge.borrow().nsf().cloned();
 
 		bus.borrow_mut().attach_cpu(&cpu);
 		bus.borrow_mut().attach_ppu(&ppu);
+		bus.borrow_mut().attach_apu(&apu);
 
 		NES 
 		{
 			bus: bus,
 			cpu: cpu,
-			ppu: ppu
+			ppu,
+			apu,
+			cartridge,
+
+			frames: Cell::new(0),
+
+			track: Cell::new(nsf.as_ref().map_or(0, |nsf| nsf.start_song)),
+			nsf,
+			play_timer: Cell::new(0),
+			play_pending: Cell::new(false)
 		}
 	}
 
 	pub fn powerup(&self)
 	{
 		self.cpu.borrow_mut().powerup();
+
+		if self.nsf.is_some()
+		{
+			self.select_track(self.track.get());
+		}
+	}
+
+	// Starts somewhere other than the reset vector, like nestest's automation mode at $C000
+	pub fn powerup_at(&self, entry: u16)
+	{
+		self.powerup();
+		self.cpu.borrow_mut().set_entry(entry);
+	}
+
+	// Logs every instruction with the CPU registers and PPU position, like nestest.log
+	pub fn set_trace(&self, enabled: bool)
+	{
+		self.cpu.borrow_mut().trace = enabled;
+	}
+
+	// Picks the constant that XAA and LAX #imm OR into A, which differs between consoles
+	pub fn set_magic_constant(&self, magic: u8)
+	{
+		self.cpu.borrow_mut().magic = magic;
+	}
+
+	// The reset button keeps the contents of RAM, unlike turning the console off and on.
+	// NSF files restart the current song
+	pub fn reset(&self)
+	{
+		self.cpu.borrow_mut().reset();
+		self.ppu.borrow_mut().reset();
+		self.apu.borrow_mut().reset();
+
+		if self.nsf.is_some()
+		{
+			self.select_track(self.track.get());
+		}
+	}
+
+	pub fn state(&self) -> State
+	{
+		match self.cpu.borrow().jammed()
+		{
+			Some((pc, opcode)) 	=> State::Jammed { pc, opcode },
+			None 				=> State::Running
+		}
 	}
 
 	pub fn clock(&self)
 	{
 		let mut cpu = self.cpu.borrow_mut();
-		let mut ppu = self.ppu.borrow_mut();
 
-		clock!(cpu, ppu);
+		clock!(self, cpu);
 	}
 
+	// Runs the rest of the current instruction, or the next one if the CPU is between instructions
 	pub fn single_step(&self)
 	{
 		let mut cpu = self.cpu.borrow_mut();
-		let mut ppu = self.ppu.borrow_mut();
 
+		clock!(self, cpu);
 		while !cpu.sync() {
-			clock!(cpu, ppu);
+			clock!(self, cpu);
 		}
-
-		clock!(cpu, ppu);
 	}
 
 	pub fn single_frame(&self)
 	{
 		let mut cpu = self.cpu.borrow_mut();
-		let mut ppu = self.ppu.borrow_mut();
 
-		while !ppu.sync() {
-			clock!(cpu, ppu);
+		while !self.ppu.borrow_mut().sync() {
+			clock!(self, cpu);
 		}
+
+		self.frames.set(self.frames.get().wrapping_add(1));
+		if self.frames.get().is_multiple_of(SAVE_INTERVAL)
+		{
+			self.cartridge.borrow_mut().save();
+		}
+	}
+
+	// Ejects the disk and inserts the next side, going back to the first after the last
+	pub fn switch_disk_side(&self)
+	{
+		let mut cartridge = self.cartridge.borrow_mut();
+
+		let sides = cartridge.disk_sides();
+		if sides > 0
+		{
+			let next = cartridge.current_disk().map_or(0, |side| (side + 1) % sides);
+			cartridge.insert_disk(Some(next));
+		}
+	}
+
+	pub fn eject_disk(&self)
+	{
+		self.cartridge.borrow_mut().insert_disk(None);
 	}
-}
\ No newline at end of file
+
+	// Only available when playing an NSF file
+	pub fn nsf(&self) -> Option<&NsfInfo>
+	{
+		self.nsf.as_ref()
+	}
+
+	pub fn current_track(&self) -> u8
+	{
+		self.track.get()
+	}
+
+	// Restarts the player on the given song, the way NSF players prepare the console for INIT
+	pub fn select_track(&self, track: u8)
+	{
+		let nsf = match &self.nsf
+		{
+			Some(nsf) 	=> nsf,
+			None 		=> return
+		};
+
+		let track = track % nsf.songs;
+		self.track.set(track);
+
+		let mut cpu = self.cpu.borrow_mut();
+		{
+			let mut bus = self.bus.borrow_mut();
+
+			for addr in (0x0000..0x0800).chain(0x6000..0x8000)
+			{
+				bus.write_cpu(addr, 0);
+			}
+
+			for addr in 0x4000..=0x4013
+			{
+				bus.write_cpu(addr, 0);
+			}
+
+			bus.write_cpu(0x4015, 0x0F);
+			bus.write_cpu(0x4017, 0x40);
+
+			for (i, &bank) in nsf.banks.iter().enumerate()
+			{
+				bus.write_cpu(0x5FF8 + i as u16, bank);
+			}
+		}
+
+		// INIT gets the song in A and whether to play at PAL speed in X
+		cpu.acc = track;
+		cpu.x = (nsf.timing == Timing::Pal) as u8;
+		cpu.y = 0;
+		cpu.sp = 0xFD;
+		cpu.p = 0x24;
+		cpu.call(nsf.init, nsf::IDLE_LOOP);
+
+		self.play_timer.set(nsf.play_period());
+		self.play_pending.set(false);
+	}
+
+	pub fn next_track(&self)
+	{
+		if let Some(nsf) = &self.nsf
+		{
+			self.select_track((self.track.get() + 1) % nsf.songs);
+		}
+	}
+
+	pub fn previous_track(&self)
+	{
+		if let Some(nsf) = &self.nsf
+		{
+			let track = match self.track.get()
+			{
+				0 		=> nsf.songs - 1,
+				track 	=> track - 1
+			};
+
+			self.select_track(track);
+		}
+	}
+
+	// Samples produced since the last call, at SAMPLE_RATE
+	pub fn audio_samples(&self) -> Vec<f32>
+	{
+		self.apu.borrow_mut().take_samples()
+	}
+
+	// A PLAY call that's due waits until INIT or the previous PLAY have returned to the idle loop
+	fn drive_player(&self, cpu: &mut CPU)
+	{
+		let nsf = match &self.nsf
+		{
+			Some(nsf) 	=> nsf,
+			None 		=> return
+		};
+
+		match self.play_timer.get()
+		{
+			0 => {
+				self.play_timer.set(nsf.play_period());
+				self.play_pending.set(true);
+			},
+
+			timer => self.play_timer.set(timer - 1)
+		}
+
+		if self.play_pending.get() && cpu.sync() && cpu.pc == nsf::IDLE_LOOP
+		{
+			cpu.call(nsf.play, nsf::IDLE_LOOP);
+			self.play_pending.set(false);
+		}
+	}
+}
@@ -0,0 +1,523 @@
+use std::{error::Error, fmt};
+
+#[derive(Debug)]
+pub enum PatchError
+{
+	UnknownFormat,
+	Corrupt,
+	TooLarge,
+	SourceChecksum { expected: u32, actual: u32 },
+	TargetChecksum { expected: u32, actual: u32 },
+	PatchChecksum { expected: u32, actual: u32 }
+}
+
+impl fmt::Display for PatchError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		match self
+		{
+			PatchError::UnknownFormat 							=> write!(f, "Patch is not in the IPS, UPS or BPS format"),
+			PatchError::Corrupt 								=> write!(f, "Patch is corrupt"),
+			PatchError::TooLarge 								=> write!(f, "Data is too large for the 24-bit offsets of an IPS patch"),
+			PatchError::SourceChecksum { expected, actual } 	=> write!(f, "ROM checksum is {:08X}, the patch expects {:08X}", actual, expected),
+			PatchError::TargetChecksum { expected, actual } 	=> write!(f, "Patched ROM checksum is {:08X}, the patch expects {:08X}", actual, expected),
+			PatchError::PatchChecksum { expected, actual } 		=> write!(f, "Patch checksum is {:08X}, but should be {:08X}", actual, expected)
+		}
+	}
+}
+
+impl Error for PatchError
+{
+
+}
+
+struct PatchReader<'a>
+{
+	data: &'a [u8],
+	offset: usize
+}
+
+impl<'a> PatchReader<'a>
+{
+	fn new(data: &'a [u8], offset: usize) -> PatchReader<'a>
+	{
+		PatchReader
+		{
+			data,
+			offset
+		}
+	}
+
+	fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError>
+	{
+		let bytes = self.data.get(self.offset..self.offset + len).ok_or(PatchError::Corrupt)?;
+		self.offset += len;
+
+		Ok(bytes)
+	}
+
+	fn byte(&mut self) -> Result<u8, PatchError>
+	{
+		Ok(self.bytes(1)?[0])
+	}
+
+	fn big_endian(&mut self, len: usize) -> Result<usize, PatchError>
+	{
+		Ok(self.bytes(len)?.iter().fold(0, |val, &byte| (val << 8) | byte as usize))
+	}
+
+	// UPS and BPS store numbers as variable-length integers, 7 bits at a time with the last byte flagged by bit 7
+	fn number(&mut self) -> Result<usize, PatchError>
+	{
+		let mut val: usize = 0;
+		let mut shift: usize = 1;
+
+		loop
+		{
+			let byte = self.byte()?;
+			val = (byte as usize & 0x7F).checked_mul(shift).and_then(|n| n.checked_add(val)).ok_or(PatchError::Corrupt)?;
+
+			if (byte & 0x80) == 0x80
+			{
+				return Ok(val);
+			}
+
+			shift = shift.checked_shl(7).ok_or(PatchError::Corrupt)?;
+			val = val.checked_add(shift).ok_or(PatchError::Corrupt)?;
+		}
+	}
+}
+
+// Applies an IPS, UPS or BPS patch to a ROM image, the format is detected by its magic number
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError>
+{
+	if patch.starts_with(b"PATCH")
+	{
+		apply_ips(rom, patch)
+	}
+	else if patch.starts_with(b"UPS1")
+	{
+		apply_ups(rom, patch)
+	}
+	else if patch.starts_with(b"BPS1")
+	{
+		apply_bps(rom, patch)
+	}
+	else
+	{
+		Err(PatchError::UnknownFormat)
+	}
+}
+
+// IPS is a list of records that overwrite or fill (RLE) bytes at 24-bit offsets. It has no checksums,
+// but may be followed by a size the ROM gets truncated to
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError>
+{
+	let mut output = rom.to_vec();
+	let mut reader = PatchReader::new(patch, 5);
+
+	loop
+	{
+		if patch[reader.offset..].starts_with(b"EOF")
+		{
+			break;
+		}
+
+		let offset = reader.big_endian(3)?;
+		let len = reader.big_endian(2)?;
+
+		let (len, fill) = match len
+		{
+			0 => (reader.big_endian(2)?, Some(reader.byte()?)),
+			_ => (len, None)
+		};
+
+		if output.len() < offset + len
+		{
+			output.resize(offset + len, 0);
+		}
+
+		match fill
+		{
+			Some(val) 	=> output[offset..offset + len].fill(val),
+			None 		=> output[offset..offset + len].copy_from_slice(reader.bytes(len)?)
+		}
+	}
+
+	reader.offset += 3;
+	if let Ok(size) = reader.big_endian(3)
+	{
+		output.truncate(size);
+	}
+
+	Ok(output)
+}
+
+// Creates an IPS patch that turns the original into the modified data. Bytes past the end of the original
+// always get a record, a shorter result is truncated to its size after the EOF marker
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, PatchError>
+{
+	let mut patch = b"PATCH".to_vec();
+	let mut offset = 0;
+	let changed = |offset: usize| original.get(offset) != Some(&modified[offset]);
+
+	while offset < modified.len()
+	{
+		if !changed(offset)
+		{
+			offset += 1;
+			continue;
+		}
+
+		// A record at an offset that spells "EOF" would end the patch, so it starts a byte earlier
+		let start = match offset
+		{
+			0x454F46 => offset - 1,
+			_ => offset
+		};
+
+		if start > 0xFFFFFF
+		{
+			return Err(PatchError::TooLarge);
+		}
+
+		let mut end = offset;
+		while end < modified.len() && end - start < 0xFFFF && changed(end)
+		{
+			end += 1;
+		}
+
+		patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+		patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+		patch.extend_from_slice(&modified[start..end]);
+
+		offset = end;
+	}
+
+	patch.extend_from_slice(b"EOF");
+
+	if modified.len() < original.len()
+	{
+		if modified.len() > 0xFFFFFF
+		{
+			return Err(PatchError::TooLarge);
+		}
+
+		patch.extend_from_slice(&(modified.len() as u32).to_be_bytes()[1..]);
+	}
+
+	Ok(patch)
+}
+
+// UPS and BPS end with the CRC32 of the source, the target and the patch itself.
+// The patch and source are checked here, the expected target checksum is returned
+fn verify_checksums(rom: &[u8], patch: &[u8]) -> Result<u32, PatchError>
+{
+	if patch.len() < 16
+	{
+		return Err(PatchError::Corrupt);
+	}
+
+	let footer = &patch[patch.len() - 12..];
+	let checksum = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+
+	let expected = checksum(8);
+	let actual = crc32fast::hash(&patch[..patch.len() - 4]);
+	if expected != actual
+	{
+		return Err(PatchError::PatchChecksum { expected, actual });
+	}
+
+	let expected = checksum(0);
+	let actual = crc32fast::hash(rom);
+	if expected != actual
+	{
+		return Err(PatchError::SourceChecksum { expected, actual });
+	}
+
+	Ok(checksum(4))
+}
+
+fn verify_target(output: &[u8], expected: u32) -> Result<(), PatchError>
+{
+	let actual = crc32fast::hash(output);
+	match expected == actual
+	{
+		true 	=> Ok(()),
+		false 	=> Err(PatchError::TargetChecksum { expected, actual })
+	}
+}
+
+// UPS skips over unchanged bytes and XORs the source with the patch until it hits a zero
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError>
+{
+	let target_checksum = verify_checksums(rom, patch)?;
+	let end = patch.len() - 12;
+
+	let mut reader = PatchReader::new(patch, 4);
+	let _source_size = reader.number()?;
+	let target_size = reader.number()?;
+
+	let mut output = rom.to_vec();
+	output.resize(target_size, 0);
+
+	let mut offset: usize = 0;
+	while reader.offset < end
+	{
+		offset = offset.checked_add(reader.number()?).ok_or(PatchError::Corrupt)?;
+
+		loop
+		{
+			let val = reader.byte()?;
+			if offset < output.len()
+			{
+				output[offset] = rom.get(offset).copied().unwrap_or(0) ^ val;
+			}
+
+			offset += 1;
+			if val == 0
+			{
+				break;
+			}
+		}
+	}
+
+	verify_target(&output, target_checksum)?;
+	Ok(output)
+}
+
+// BPS builds the target from commands that copy from the source, the patch or the already written target
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError>
+{
+	let target_checksum = verify_checksums(rom, patch)?;
+	let end = patch.len() - 12;
+
+	let mut reader = PatchReader::new(patch, 4);
+	let _source_size = reader.number()?;
+	let target_size = reader.number()?;
+	let metadata_size = reader.number()?;
+	reader.bytes(metadata_size)?;
+
+	let mut output = Vec::with_capacity(target_size);
+	let mut source_offset: usize = 0;
+	let mut target_offset: usize = 0;
+
+	// Relative offsets store the sign in the lowest bit
+	let relative = |offset: usize, val: usize| -> Result<usize, PatchError>
+	{
+		match val & 0x01
+		{
+			0 => offset.checked_add(val >> 1),
+			_ => offset.checked_sub(val >> 1)
+		}.ok_or(PatchError::Corrupt)
+	};
+
+	while reader.offset < end
+	{
+		let command = reader.number()?;
+		let len = (command >> 2) + 1;
+
+		match command & 0x03
+		{
+			0 => {
+				let start = output.len();
+				output.extend_from_slice(rom.get(start..start + len).ok_or(PatchError::Corrupt)?);
+			},
+
+			1 => output.extend_from_slice(reader.bytes(len)?),
+
+			2 => {
+				source_offset = relative(source_offset, reader.number()?)?;
+				output.extend_from_slice(rom.get(source_offset..source_offset + len).ok_or(PatchError::Corrupt)?);
+				source_offset += len;
+			},
+
+			// The copy may overlap the bytes it is writing, so it has to go one byte at a time
+			_ => {
+				target_offset = relative(target_offset, reader.number()?)?;
+				for _ in 0..len
+				{
+					let val = *output.get(target_offset).ok_or(PatchError::Corrupt)?;
+					output.push(val);
+					target_offset += 1;
+				}
+			}
+		}
+	}
+
+	if output.len() != target_size
+	{
+		return Err(PatchError::Corrupt);
+	}
+
+	verify_target(&output, target_checksum)?;
+	Ok(output)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn number(mut val: usize, patch: &mut Vec<u8>)
+	{
+		loop
+		{
+			let bits = (val & 0x7F) as u8;
+			val >>= 7;
+
+			if val == 0
+			{
+				patch.push(bits | 0x80);
+				return;
+			}
+
+			patch.push(bits);
+			val -= 1;
+		}
+	}
+
+	fn footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8>
+	{
+		patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+		patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+		patch.extend_from_slice(&crc32fast::hash(&patch).to_le_bytes());
+		patch
+	}
+
+	#[test]
+	fn ips_writes_records_past_the_end()
+	{
+		let patch = b"PATCH\x00\x00\x02\x00\x03\x01\x02\x03\x00\x00\x09\x00\x01\xFFEOF";
+		assert_eq!(apply(&[0; 8], patch).unwrap(), [0, 0, 1, 2, 3, 0, 0, 0, 0, 0xFF]);
+	}
+
+	#[test]
+	fn ips_fills_rle_records()
+	{
+		let patch = b"PATCH\x00\x00\x01\x00\x00\x00\x04\xAAEOF";
+		assert_eq!(apply(&[0; 6], patch).unwrap(), [0, 0xAA, 0xAA, 0xAA, 0xAA, 0]);
+	}
+
+	#[test]
+	fn ips_truncates_to_the_size_after_eof()
+	{
+		let patch = b"PATCH\x00\x00\x00\x00\x01\x42EOF\x00\x00\x03";
+		assert_eq!(apply(&[0; 8], patch).unwrap(), [0x42, 0, 0]);
+	}
+
+	#[test]
+	fn ips_rejects_cut_off_records()
+	{
+		assert!(matches!(apply(&[0; 8], b"PATCH\x00\x00\x02\x00\x03\x01"), Err(PatchError::Corrupt)));
+		assert!(matches!(apply(&[0; 8], b"PATCH\x00\x00"), Err(PatchError::Corrupt)));
+	}
+
+	#[test]
+	fn created_ips_patches_apply()
+	{
+		let original: Vec<u8> = (0..=255).collect();
+		let mut modified = original.clone();
+		modified[0] = 0xFF;
+		modified[100..110].fill(0);
+		modified[255] = 0;
+
+		let patch = create_ips(&original, &modified).unwrap();
+		assert_eq!(apply(&original, &patch).unwrap(), modified);
+
+		for modified in [&original[..200], &[original.as_slice(), &[1, 2, 3]].concat()]
+		{
+			let patch = create_ips(&original, modified).unwrap();
+			assert_eq!(apply(&original, &patch).unwrap(), modified);
+		}
+	}
+
+	#[test]
+	fn created_ips_patches_stay_within_24_bits()
+	{
+		let original = vec![0u8; 0x1000001];
+		let mut modified = original.clone();
+		assert_eq!(create_ips(&original, &modified).unwrap(), b"PATCHEOF");
+
+		modified[0x1000000] = 1;
+		assert!(matches!(create_ips(&original, &modified), Err(PatchError::TooLarge)));
+		assert!(matches!(create_ips(&original, &original[..0x1000000]), Err(PatchError::TooLarge)));
+	}
+
+	fn ups_patch(source: &[u8], target: &[u8]) -> Vec<u8>
+	{
+		// Skip a byte, XOR the next, then skip one more and XOR in the appended byte
+		let mut patch = b"UPS1".to_vec();
+		number(source.len(), &mut patch);
+		number(target.len(), &mut patch);
+		number(1, &mut patch);
+		patch.extend_from_slice(&[source[1] ^ target[1], 0]);
+		number(1, &mut patch);
+		patch.extend_from_slice(&[target[4], 0]);
+
+		footer(patch, source, target)
+	}
+
+	#[test]
+	fn ups_xors_and_grows_the_rom()
+	{
+		let source = [1, 2, 3, 4];
+		let target = [1, 9, 3, 4, 5];
+		assert_eq!(apply(&source, &ups_patch(&source, &target)).unwrap(), target);
+	}
+
+	#[test]
+	fn ups_checks_the_source_and_patch_checksums()
+	{
+		let source = [1, 2, 3, 4];
+		let target = [1, 9, 3, 4, 5];
+		let mut patch = ups_patch(&source, &target);
+
+		assert!(matches!(apply(&[1, 2, 3, 5], &patch), Err(PatchError::SourceChecksum { .. })));
+
+		patch[6] ^= 0x01;
+		assert!(matches!(apply(&source, &patch), Err(PatchError::PatchChecksum { .. })));
+	}
+
+	fn bps_patch(source: &[u8], target: &[u8]) -> Vec<u8>
+	{
+		let mut patch = b"BPS1".to_vec();
+		number(source.len(), &mut patch);
+		number(target.len(), &mut patch);
+		number(0, &mut patch);
+
+		// Source read of 2, target read of 1, source copy of 2 from the start, then an overlapping target copy of 2
+		number(1 << 2, &mut patch);
+		number(1, &mut patch);
+		patch.push(0xAA);
+		number((1 << 2) | 2, &mut patch);
+		number(0, &mut patch);
+		number((1 << 2) | 3, &mut patch);
+		number(4 << 1, &mut patch);
+
+		footer(patch, source, target)
+	}
+
+	#[test]
+	fn bps_runs_all_commands()
+	{
+		let source = [1, 2, 3, 4];
+		let target = [1, 2, 0xAA, 1, 2, 2, 2];
+		assert_eq!(apply(&source, &bps_patch(&source, &target)).unwrap(), target);
+	}
+
+	#[test]
+	fn bps_checks_the_target_checksum()
+	{
+		let source = [1, 2, 3, 4];
+		let patch = bps_patch(&source, &[1, 2, 0xAA, 1, 2, 2, 3]);
+		assert!(matches!(apply(&source, &patch), Err(PatchError::TargetChecksum { .. })));
+	}
+
+	#[test]
+	fn unknown_patches_are_rejected()
+	{
+		assert!(matches!(apply(&[0; 8], b"NOTAPATCH"), Err(PatchError::UnknownFormat)));
+	}
+}
@@ -1,59 +1,226 @@
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use crate::nes::bus::Bus;
+use crate::nes::cartridge::Cartridge;
 
 pub struct PPU
 {
 	screen_x: u16,
 	screen_y: u16,
+	odd_frame: bool,
 	new_frame: bool,
 
+	ctrl: u8,
+	mask: u8,
+	status: u8,
+	oam_addr: u8,
+	data_buffer: u8,
+
+	// Internal scrolling registers, named after loopy's documentation
+	v: u16,
+	t: u16,
+	fine_x: u8,
+	w: bool,
+
+	vram: Vec<u8>,
+	palette: Vec<u8>,
+	oam: Vec<u8>,
+	secondary_oam: Vec<u8>,
+	sprite_count: usize,
+
+	nametable_latch: u8,
+	attribute_latch: u8,
+	pattern_lo_latch: u8,
+	pattern_hi_latch: u8,
+
+	cartridge: Rc<RefCell<Cartridge>>,
 	bus: Weak<RefCell<Bus>>
 }
 
-impl PPU 
+impl PPU
 {
-	pub fn new(bus: &Rc<RefCell<Bus>>) -> PPU 
+	pub fn new(bus: &Rc<RefCell<Bus>>) -> PPU
 	{
 		PPU {
 			screen_x: 0,
 			screen_y: 0,
+			odd_frame: false,
 			new_frame: false,
 
+			ctrl: 0,
+			mask: 0,
+			status: 0,
+			oam_addr: 0,
+			data_buffer: 0,
+
+			v: 0,
+			t: 0,
+			fine_x: 0,
+			w: false,
+
+			vram: vec![0; 0x1000],
+			palette: vec![0; 0x20],
+			oam: vec![0; 0x100],
+			secondary_oam: vec![0xFF; 0x20],
+			sprite_count: 0,
+
+			nametable_latch: 0,
+			attribute_latch: 0,
+			pattern_lo_latch: 0,
+			pattern_hi_latch: 0,
+
+			cartridge: bus.borrow().cartridge(),
 			bus: Rc::downgrade(bus)
 		}
 	}
 
+	// The reset line clears the write-only registers, but leaves the vblank flag and memory alone
+	pub fn reset(&mut self)
+	{
+		self.ctrl = 0;
+		self.mask = 0;
+		self.data_buffer = 0;
+		self.t = 0;
+		self.fine_x = 0;
+		self.w = false;
+		self.odd_frame = false;
+	}
+
 	pub fn set_regsiter(&mut self, addr: u16, val: u8)
 	{
-		match addr 
+		match addr
 		{
+			0 => {
+				self.ctrl = val;
+				self.t = (self.t & !0x0C00) | (((val & 0x03) as u16) << 10);
+			},
+
+			1 => self.mask = val,
+			2 => { },
+			3 => self.oam_addr = val,
+
+			4 => {
+				self.oam[self.oam_addr as usize] = val;
+				self.oam_addr = self.oam_addr.wrapping_add(1);
+			},
+
+			5 => {
+				match self.w
+				{
+					false => {
+						self.t = (self.t & !0x001F) | (val >> 3) as u16;
+						self.fine_x = val & 0x07;
+					},
+
+					true => {
+						self.t = (self.t & !0x73E0) | (((val & 0x07) as u16) << 12) | (((val & 0xF8) as u16) << 2);
+					}
+				}
+
+				self.w = !self.w;
+			},
+
+			6 => {
+				match self.w
+				{
+					false 	=> self.t = (self.t & 0x00FF) | (((val & 0x3F) as u16) << 8),
+					true 	=> {
+						self.t = (self.t & 0xFF00) | val as u16;
+						self.v = self.t;
+					}
+				}
+
+				self.w = !self.w;
+			},
+
+			7 => {
+				self.write_memory(self.v, val);
+				self.increment_vram_addr();
+			},
+
 			_ => panic!("Register not implemented")
 		}
 	}
 
 	pub fn get_regsiter(&mut self, addr: u16) -> u8
 	{
-		match addr 
+		match addr
 		{
-			_ => panic!("Register not implemented")
+			2 => {
+				let val = (self.status & 0xE0) | (self.data_buffer & 0x1F);
+
+				self.status &= !0x80;
+				self.w = false;
+
+				val
+			},
+
+			4 => self.oam[self.oam_addr as usize],
+
+			7 => {
+				let addr = self.v;
+				let mut val = self.data_buffer;
+				self.data_buffer = self.read_memory(addr);
+
+				// Palette reads are not buffered, but the buffer is filled with the nametable underneath
+				if addr >= 0x3F00
+				{
+					val = self.palette[palette_index(addr)];
+					self.data_buffer = self.read_memory(addr & 0x2FFF);
+				}
+
+				self.increment_vram_addr();
+				val
+			},
+
+			_ => 0
 		}
 	}
 
 	pub fn dot(&mut self)
 	{
+		if self.rendering_enabled() && (self.screen_y < 240 || self.screen_y == 261)
+		{
+			self.render_dot();
+		}
+
+		match (self.screen_y, self.screen_x)
+		{
+			(241, 1) => {
+				self.status |= 0x80;
+				self.new_frame = true;
+			},
+
+			(261, 1) => self.status &= !0xE0,
+
+			_ => { }
+		}
+
 		self.screen_x += 1;
 
+		// The pre-render line of odd frames is one dot shorter while rendering
+		if self.screen_y == 261 && self.screen_x == 340 && self.odd_frame && self.rendering_enabled()
+		{
+			self.screen_x += 1;
+		}
+
 		if self.screen_x > 340 {
 			self.screen_x = 0;
 			self.screen_y += 1;
 
 			if self.screen_y > 261 {
 				self.screen_y = 0;
+				self.odd_frame = !self.odd_frame;
 			}
 		}
 	}
 
+	// The NMI output is low while both the vblank flag and the NMI enable bit in PPUCTRL are set
+	pub fn nmi(&self) -> bool
+	{
+		(self.status & self.ctrl & 0x80) == 0x80
+	}
+
 	pub fn sync(&mut self) -> bool
 	{
 		if self.new_frame {
@@ -68,4 +235,228 @@ impl PPU
 	{
 		(self.screen_x, self.screen_y)
 	}
-}
\ No newline at end of file
+
+	fn rendering_enabled(&self) -> bool
+	{
+		(self.mask & 0x18) != 0
+	}
+
+	fn sprite_height(&self) -> u16
+	{
+		match self.ctrl & 0x20
+		{
+			0 => 8,
+			_ => 16
+		}
+	}
+
+	// Performs the memory fetches of a single dot on a visible or the pre-render scanline
+	fn render_dot(&mut self)
+	{
+		let x = self.screen_x;
+
+		match x
+		{
+			1..=256 | 321..=336 => {
+				self.background_fetch(x);
+
+				if x.is_multiple_of(8)
+				{
+					self.increment_coarse_x();
+				}
+
+				if x == 256
+				{
+					self.increment_y();
+				}
+			},
+
+			257..=320 => {
+				if x == 257
+				{
+					self.v = (self.v & !0x041F) | (self.t & 0x041F);
+					self.evaluate_sprites();
+				}
+
+				if self.screen_y == 261 && (280..=304).contains(&x)
+				{
+					self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+				}
+
+				self.sprite_fetch(x);
+			},
+
+			337 | 339 => {
+				self.nametable_latch = self.read_memory(0x2000 | (self.v & 0x0FFF));
+			},
+
+			_ => { }
+		}
+	}
+
+	fn background_fetch(&mut self, x: u16)
+	{
+		let pattern_addr = (((self.ctrl & 0x10) as u16) << 8) | ((self.nametable_latch as u16) << 4) | ((self.v >> 12) & 0x07);
+
+		match x % 8
+		{
+			1 => self.nametable_latch = self.read_memory(0x2000 | (self.v & 0x0FFF)),
+			3 => self.attribute_latch = self.read_memory(0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)),
+			5 => self.pattern_lo_latch = self.read_memory(pattern_addr),
+			7 => self.pattern_hi_latch = self.read_memory(pattern_addr + 8),
+			_ => { }
+		}
+	}
+
+	fn sprite_fetch(&mut self, x: u16)
+	{
+		let slot = ((x - 257) / 8) as usize;
+
+		// Sprite fetches still put garbage nametable addresses on the bus
+		match (x - 257) % 8
+		{
+			0 | 2 => { self.read_memory(0x2000 | (self.v & 0x0FFF)); },
+
+			4 | 6 => {
+				let addr = self.sprite_pattern_addr(slot) + ((x - 257) % 8 == 6) as u16 * 8;
+				self.read_memory(addr);
+			},
+
+			_ => { }
+		}
+	}
+
+	fn sprite_pattern_addr(&self, slot: usize) -> u16
+	{
+		let sprite = &self.secondary_oam[(slot * 4)..(slot * 4 + 4)];
+
+		// Unused slots fetch tile $FF
+		let (y, tile, attributes) = match slot < self.sprite_count
+		{
+			true 	=> (sprite[0], sprite[1], sprite[2]),
+			false 	=> (0xFF, 0xFF, 0x00)
+		};
+
+		let mut row = self.screen_y.wrapping_sub(y as u16) & (self.sprite_height() - 1);
+		if (attributes & 0x80) == 0x80
+		{
+			row = self.sprite_height() - 1 - row;
+		}
+
+		match self.sprite_height()
+		{
+			8 => (((self.ctrl & 0x08) as u16) << 9) | ((tile as u16) << 4) | row,
+			_ => (((tile & 0x01) as u16) << 12) | (((tile & 0xFE) as u16) << 4) | ((row & 0x08) << 1) | (row & 0x07)
+		}
+	}
+
+	fn evaluate_sprites(&mut self)
+	{
+		let height = self.sprite_height();
+
+		self.secondary_oam.fill(0xFF);
+		self.sprite_count = 0;
+
+		for sprite in 0..64
+		{
+			let y = self.oam[sprite * 4] as u16;
+			if self.screen_y < y || self.screen_y >= y + height
+			{
+				continue;
+			}
+
+			if self.sprite_count == 8
+			{
+				self.status |= 0x20;
+				break;
+			}
+
+			let slot = self.sprite_count * 4;
+			self.secondary_oam[slot..(slot + 4)].copy_from_slice(&self.oam[(sprite * 4)..(sprite * 4 + 4)]);
+			self.sprite_count += 1;
+		}
+	}
+
+	fn increment_vram_addr(&mut self)
+	{
+		let increment = match self.ctrl & 0x04
+		{
+			0 => 1,
+			_ => 32
+		};
+
+		self.v = self.v.wrapping_add(increment) & 0x7FFF;
+	}
+
+	fn increment_coarse_x(&mut self)
+	{
+		match self.v & 0x001F
+		{
+			31 => self.v = (self.v & !0x001F) ^ 0x0400,
+			_ => self.v += 1
+		}
+	}
+
+	fn increment_y(&mut self)
+	{
+		if (self.v & 0x7000) != 0x7000
+		{
+			self.v += 0x1000;
+			return;
+		}
+
+		self.v &= !0x7000;
+
+		let mut coarse_y = (self.v & 0x03E0) >> 5;
+		match coarse_y
+		{
+			29 => {
+				coarse_y = 0;
+				self.v ^= 0x0800;
+			},
+
+			31 => coarse_y = 0,
+			_ => coarse_y += 1
+		}
+
+		self.v = (self.v & !0x03E0) | (coarse_y << 5);
+	}
+
+	fn read_memory(&mut self, addr: u16) -> u8
+	{
+		let addr = addr & 0x3FFF;
+		self.cartridge.borrow_mut().ppu_address(addr);
+
+		match addr
+		{
+			0x0000..=0x1FFF => self.cartridge.borrow_mut().read_ppu(addr),
+			0x2000..=0x3EFF => self.cartridge.borrow_mut().read_nametable(addr, &self.vram),
+			_ 				=> self.palette[palette_index(addr)]
+		}
+	}
+
+	fn write_memory(&mut self, addr: u16, val: u8)
+	{
+		let addr = addr & 0x3FFF;
+		self.cartridge.borrow_mut().ppu_address(addr);
+
+		match addr
+		{
+			0x0000..=0x1FFF => self.cartridge.borrow_mut().write_ppu(addr, val),
+			0x2000..=0x3EFF => self.cartridge.borrow_mut().write_nametable(addr, val, &mut self.vram),
+			_ 				=> self.palette[palette_index(addr)] = val
+		}
+	}
+}
+
+// $3F10, $3F14, $3F18 and $3F1C mirror the background entries below them
+fn palette_index(addr: u16) -> usize
+{
+	let index = (addr & 0x1F) as usize;
+
+	match index
+	{
+		0x10 | 0x14 | 0x18 | 0x1C => index - 0x10,
+		_ => index
+	}
+}
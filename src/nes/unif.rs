@@ -0,0 +1,230 @@
+use crate::nes::cartridge::{Header, Mirroring, RomError, Timing};
+
+// UNIF identifies boards by name instead of number, these are the ones that map onto the
+// implemented mappers. Names are matched without their prefix (NES-, HVC-, UNL-, BMC-, MLT-, ...),
+// so pirate copies of the Nintendo boards are found under the original name
+static BOARDS: &[(&str, u16, u8)] = &[
+	("NROM", 0, 0), ("NROM-128", 0, 0), ("NROM-256", 0, 0), ("RROM", 0, 0),
+	("SAROM", 1, 0), ("SBROM", 1, 0), ("SCROM", 1, 0), ("SEROM", 1, 0), ("SFROM", 1, 0), ("SGROM", 1, 0),
+	("SHROM", 1, 0), ("SJROM", 1, 0), ("SKROM", 1, 0), ("SLROM", 1, 0), ("SL1ROM", 1, 0), ("SNROM", 1, 0),
+	("SOROM", 1, 0), ("SUROM", 1, 0), ("SXROM", 1, 0),
+	("UNROM", 2, 0), ("UOROM", 2, 0),
+	("CNROM", 3, 0),
+	("TBROM", 4, 0), ("TEROM", 4, 0), ("TFROM", 4, 0), ("TGROM", 4, 0), ("TKROM", 4, 0), ("TLROM", 4, 0),
+	("TL1ROM", 4, 0), ("TR1ROM", 4, 0), ("TSROM", 4, 0), ("TVROM", 4, 0), ("B4", 4, 0),
+	("EKROM", 5, 0), ("ELROM", 5, 0), ("ETROM", 5, 0), ("EWROM", 5, 0),
+	("AMROM", 7, 0), ("ANROM", 7, 0), ("AN1ROM", 7, 0), ("AOROM", 7, 0),
+	("PNROM", 9, 0), ("PEEOROM", 9, 0),
+	("FJROM", 10, 0), ("FKROM", 10, 0),
+	("GNROM", 66, 0), ("MHROM", 66, 0),
+	("ACTION52", 228, 0)
+];
+
+pub struct UnifImage
+{
+	pub header: Header,
+	pub prg: Vec<u8>,
+	pub chr: Vec<u8>,
+	pub name: Option<String>
+}
+
+fn board(name: &str) -> Option<(u16, u8)>
+{
+	let find = |name: &str| BOARDS.iter().find(|&&(board, _, _)| board == name).map(|&(_, mapper, submapper)| (mapper, submapper));
+
+	let name = name.to_ascii_uppercase();
+	find(&name).or_else(|| name.split_once('-').and_then(|(_, board)| find(board)))
+}
+
+// Strings in UNIF chunks are null-terminated
+fn string(data: &[u8]) -> String
+{
+	let end = data.iter().position(|&byte| byte == 0).unwrap_or(data.len());
+	String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+// The 32 byte header is followed by chunks of a 4 character ID, a 32-bit length and the data.
+// PRG and CHR are split across up to 16 numbered chunks each, which are concatenated in order
+pub fn parse(data: &[u8]) -> Result<UnifImage, RomError>
+{
+	let mut offset = 32;
+
+	let mut board_name = None;
+	let mut name = None;
+	let mut prg_chunks: [&[u8]; 16] = [&[]; 16];
+	let mut chr_chunks: [&[u8]; 16] = [&[]; 16];
+	let mut mirroring = Mirroring::Horizontal;
+	let mut battery = false;
+	let mut timing = Timing::Ntsc;
+
+	while offset < data.len()
+	{
+		let chunk_header = data.get(offset..offset + 8).ok_or(RomError::Truncated)?;
+		let len = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]) as usize;
+		let chunk = data.get(offset + 8..offset + 8 + len).ok_or(RomError::Truncated)?;
+		offset += 8 + len;
+
+		// Chunk numbers are a single hex digit, PRG and CHR chunks with anything else are skipped
+		let index = (chunk_header[3] as char).to_digit(16).map(|index| index as usize);
+
+		match (&chunk_header[..4], index)
+		{
+			(b"MAPR", _) => board_name = Some(string(chunk)),
+			(b"NAME", _) => name = Some(string(chunk)),
+			(b"BATR", _) => battery = true,
+
+			([b'P', b'R', b'G', _], Some(index)) => prg_chunks[index] = chunk,
+			([b'C', b'H', b'R', _], Some(index)) => chr_chunks[index] = chunk,
+
+			(b"MIRR", _) => mirroring = match chunk.first()
+			{
+				Some(1) => Mirroring::Vertical,
+				Some(2) => Mirroring::SingleScreenLower,
+				Some(3) => Mirroring::SingleScreenUpper,
+				Some(4) => Mirroring::FourScreen,
+				_ 		=> Mirroring::Horizontal
+			},
+
+			(b"TVCI", _) => timing = match chunk.first()
+			{
+				Some(1) => Timing::Pal,
+				Some(2) => Timing::MultiRegion,
+				_ 		=> Timing::Ntsc
+			},
+
+			_ => { }
+		}
+	}
+
+	let board_name = board_name.unwrap_or_default();
+	let (mapper, submapper) = board(&board_name).ok_or(RomError::UnsupportedBoard(board_name))?;
+
+	let prg = prg_chunks.concat();
+	let chr = chr_chunks.concat();
+
+	// UNIF has no notion of RAM sizes, assume 8K of PRG-RAM and CHR-RAM for boards without CHR-ROM
+	let mut header = Header::new(mapper, prg.len(), chr.len());
+	header.submapper = submapper;
+	header.mirroring = mirroring;
+	header.four_screen = mirroring == Mirroring::FourScreen;
+	header.battery = battery;
+	header.timing = timing;
+
+	match battery
+	{
+		true 	=> header.prg_nvram_size = 0x2000,
+		false 	=> header.prg_ram_size = 0x2000
+	};
+
+	if chr.is_empty()
+	{
+		header.chr_ram_size = 0x2000;
+	}
+
+	Ok(UnifImage
+	{
+		header,
+		prg,
+		chr,
+		name
+	})
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn unif(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8>
+	{
+		let mut data = b"UNIF".to_vec();
+		data.resize(32, 0);
+
+		for (id, chunk) in chunks
+		{
+			data.extend_from_slice(*id);
+			data.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+			data.extend_from_slice(chunk);
+		}
+
+		data
+	}
+
+	#[test]
+	fn parses_chunks()
+	{
+		let data = unif(&[
+			(b"MAPR", b"NES-SLROM\0"),
+			(b"NAME", b"Test Game\0"),
+			(b"PRG1", &[2; 0x4000]),
+			(b"PRG0", &[1; 0x4000]),
+			(b"CHR0", &[3; 0x2000]),
+			(b"MIRR", &[1]),
+			(b"TVCI", &[1]),
+			(b"BATR", &[0]),
+			(b"DINF", &[0; 204])
+		]);
+
+		let image = parse(&data).unwrap();
+		assert_eq!(image.name.as_deref(), Some("Test Game"));
+		assert_eq!(image.header.mapper, 1);
+		assert_eq!(image.header.mirroring, Mirroring::Vertical);
+		assert_eq!(image.header.timing, Timing::Pal);
+		assert!(image.header.battery);
+		assert_eq!((image.header.prg_ram_size, image.header.prg_nvram_size), (0, 0x2000));
+
+		// Numbered chunks are put together in order, whatever order they were stored in
+		assert_eq!(image.prg.len(), 0x8000);
+		assert_eq!((image.prg[0], image.prg[0x4000]), (1, 2));
+		assert_eq!(image.chr, [3; 0x2000]);
+		assert_eq!(image.header.chr_ram_size, 0);
+	}
+
+	#[test]
+	fn gives_chr_ram_to_boards_without_chr_rom()
+	{
+		let image = parse(&unif(&[(b"MAPR", b"unl-unrom\0"), (b"PRG0", &[0; 0x4000])])).unwrap();
+
+		assert_eq!(image.header.mapper, 2);
+		assert_eq!(image.header.mirroring, Mirroring::Horizontal);
+		assert_eq!(image.header.prg_ram_size, 0x2000);
+		assert!(image.chr.is_empty());
+		assert_eq!(image.header.chr_ram_size, 0x2000);
+		assert!(image.name.is_none());
+	}
+
+	#[test]
+	fn finds_multicart_boards()
+	{
+		let image = parse(&unif(&[(b"MAPR", b"MLT-ACTION52\0"), (b"PRG0", &[0; 0x4000])])).unwrap();
+		assert_eq!(image.header.mapper, 228);
+	}
+
+	#[test]
+	fn skips_chunks_without_a_hex_number()
+	{
+		let image = parse(&unif(&[(b"MAPR", b"NES-NROM-128\0"), (b"PRG0", &[1; 0x4000]), (b"PRGx", &[2; 0x4000])])).unwrap();
+		assert_eq!(image.prg, [1; 0x4000]);
+	}
+
+	#[test]
+	fn rejects_unknown_boards()
+	{
+		let data = unif(&[(b"MAPR", b"UNL-NOTABOARD\0"), (b"PRG0", &[0; 0x4000])]);
+		assert!(matches!(parse(&data), Err(RomError::UnsupportedBoard(board)) if board == "UNL-NOTABOARD"));
+
+		let data = unif(&[(b"PRG0", &[0; 0x4000])]);
+		assert!(matches!(parse(&data), Err(RomError::UnsupportedBoard(board)) if board.is_empty()));
+	}
+
+	#[test]
+	fn rejects_cut_off_chunks()
+	{
+		let mut data = unif(&[(b"MAPR", b"NES-NROM-256\0"), (b"PRG0", &[0; 0x8000])]);
+		data.truncate(data.len() - 1);
+		assert!(matches!(parse(&data), Err(RomError::Truncated)));
+
+		data.truncate(32 + 4);
+		assert!(matches!(parse(&data), Err(RomError::Truncated)));
+	}
+}